@@ -1,14 +1,15 @@
+mod completion;
 mod documentation;
+mod highlight;
+mod markers;
 mod render;
 mod text;
+mod tooltips;
 
-use crate::project;
 use eframe::egui;
 use egui::menu;
+use laser_studio::project;
 use rfd::FileDialog;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
@@ -39,6 +40,9 @@ pub struct LaserStudioApp {
     current_path: Option<PathBuf>,
 }
 
+/// The key the highlight theme is saved under in the app's storage.
+const HIGHLIGHT_THEME_KEY: &str = "highlight_theme";
+
 impl Default for LaserStudioApp {
     fn default() -> Self {
         let (tx, rx) = mpsc::channel();
@@ -76,6 +80,16 @@ impl eframe::App for LaserStudioApp {
                 ui.label("© 2020-2022 William Whittaker");
             });
 
+        if let Some(code) = self.render.requested_documentation.take() {
+            self.documentation.show_error(code);
+            self.show_documentation_window = true;
+        }
+
+        if let Some(span) = self.render.requested_location.take() {
+            self.text.select(span);
+            self.tab = Workspace::Text;
+        }
+
         self.documentation
             .update(ctx, &mut self.show_documentation_window);
 
@@ -118,6 +132,7 @@ impl eframe::App for LaserStudioApp {
                                 ui.close_menu();
                             }
                             if ui.button("Save As").clicked() {
+                                self.format_before_save();
                                 self.save_dialog();
                                 ui.close_menu();
                             }
@@ -217,7 +232,11 @@ impl eframe::App for LaserStudioApp {
             });
 
         match self.tab {
-            Workspace::Text => self.text.update_text_workspace(ctx, &mut self.project),
+            Workspace::Text => {
+                let values = self.render.variable_values();
+                self.text
+                    .update_text_workspace(ctx, &mut self.project, values)
+            }
             Workspace::Render => self.render.update_render_workspace(ctx, &mut self.project),
             _ => {
                 egui::SidePanel::left("about")
@@ -245,16 +264,34 @@ impl eframe::App for LaserStudioApp {
             }
         }
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, HIGHLIGHT_THEME_KEY, &self.text.highlight_theme);
+    }
 }
 
 impl LaserStudioApp {
+    /// Creates the app, with the settings saved by the last run.
+    pub fn new(cc: &eframe::CreationContext) -> Self {
+        let mut app = LaserStudioApp::default();
+
+        if let Some(theme) = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, HIGHLIGHT_THEME_KEY))
+        {
+            app.text.highlight_theme = theme;
+        }
+
+        app
+    }
+
     // a bunch of stuff to handle opening & saving Projects
     fn check_for_selection(&mut self) {
         match self.project_rx.try_recv() {
             Ok(value) => {
                 match value {
                     FileDialogSelection::Open(value) => {
-                        match LaserStudioApp::open_project(value) {
+                        match project::Project::open(&value) {
                             Ok(value) => {
                                 self.project = value;
                                 self.tab = Workspace::Text;
@@ -263,7 +300,7 @@ impl LaserStudioApp {
                         };
                     }
                     FileDialogSelection::Save(value) => {
-                        match LaserStudioApp::save_project(value, self.project.clone()) {
+                        match self.project.save(&value) {
                             Ok(_) => (),
                             Err(_err) => {} // TODO: show error dialog
                         };
@@ -315,37 +352,6 @@ impl LaserStudioApp {
         });
     }
 
-    fn open_project(path: PathBuf) -> std::result::Result<project::Project, String> {
-        let file = match File::open(path) {
-            Ok(value) => value,
-            Err(_err) => return Err("An error occured while opening the file for reading.".into()),
-        };
-
-        let reader = BufReader::new(file);
-
-        match serde_json::from_reader(reader) {
-            Ok(value) => Ok(value),
-            Err(_err) => return Err("An deserialization error occured.".into()),
-        }
-    }
-
-    fn save_project(path: PathBuf, project: project::Project) -> std::result::Result<(), String> {
-        let serialized = match serde_json::to_string(&project) {
-            Ok(value) => value,
-            Err(_error) => return Err("A serialization error occured.".into()),
-        };
-
-        let mut file = match File::create(path) {
-            Ok(value) => value,
-            Err(_error) => return Err("Failed to create a new file.".into()),
-        };
-
-        match file.write_all(serialized.as_bytes()) {
-            Ok(_value) => Ok(()),
-            Err(_error) => return Err("Failed to write to the file.".into()),
-        }
-    }
-
     fn menu_button_styling(ui: &mut egui::Ui) {
         ui.spacing_mut().button_padding = egui::vec2(8.0, 4.0);
         ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
@@ -354,10 +360,18 @@ impl LaserStudioApp {
         ui.visuals_mut().widgets.inactive.rounding = egui::Rounding::none();
     }
 
+    fn format_before_save(&mut self) {
+        if self.project.format_on_save {
+            self.text.format(&mut self.project);
+        }
+    }
+
     fn save_current_project(&mut self) {
+        self.format_before_save();
+
         match self.current_path.clone() {
             Some(value) => {
-                match LaserStudioApp::save_project(value, self.project.clone()) {
+                match self.project.save(&value) {
                     Ok(_) => (),
                     Err(_err) => (), // TODO: show error
                 }
@@ -373,6 +387,14 @@ impl LaserStudioApp {
             self.save_current_project();
         }
 
+        if self.tab == Workspace::Text
+            && input.modifiers.ctrl
+            && input.modifiers.shift
+            && input.key_pressed(egui::Key::F)
+        {
+            self.text.format(&mut self.project);
+        }
+
         if input.key_down(egui::Key::F5) {
             self.tab = Workspace::Render;
             self.render.eval_frozen = false;
@@ -0,0 +1,221 @@
+//! The completion popup of the text editor, which suggests names as they're typed (or when
+//! Ctrl+Space is pressed).
+
+use eframe::egui;
+use eframe::egui::text::{CCursor, CCursorRange};
+use laser_studio::expressions::complete::{self, CompletionKind, Completions};
+use laser_studio::expressions::edit::{apply_edits, TextEdit};
+
+// the most completions shown at once; the rest are reached with the arrow keys
+const MAX_SHOWN: usize = 8;
+
+#[derive(Default)]
+pub struct CompletionPopup {
+    /// The completions for the name at the cursor, while the popup is open.
+    completions: Option<Completions>,
+    selected: usize,
+    /// Where the cursor was when the completions were found.
+    offset: usize,
+    /// Ctrl+Space was pressed, so the popup should open even if nothing's been typed.
+    requested: bool,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '\''
+}
+
+impl CompletionPopup {
+    /// Handles the keys that control the popup, before the editor gets to see them (and insert a
+    /// new line instead of the selected completion, for example).
+    pub fn handle_keys(&mut self, ctx: &egui::Context, id: egui::Id, text: &mut String) {
+        if !ctx.memory().has_focus(id) {
+            return;
+        }
+
+        let none = egui::Modifiers::NONE;
+        let (requested, escape, down, up, accept) = {
+            let mut input = ctx.input_mut();
+
+            (
+                input.consume_key(egui::Modifiers::CTRL, egui::Key::Space),
+                self.completions.is_some() && input.consume_key(none, egui::Key::Escape),
+                self.completions.is_some() && input.consume_key(none, egui::Key::ArrowDown),
+                self.completions.is_some() && input.consume_key(none, egui::Key::ArrowUp),
+                self.completions.is_some()
+                    && (input.consume_key(none, egui::Key::Enter)
+                        || input.consume_key(none, egui::Key::Tab)),
+            )
+        };
+
+        self.requested |= requested;
+
+        let Some(completions) = &self.completions else {
+            return;
+        };
+
+        let count = completions.items.len();
+
+        if escape {
+            self.completions = None;
+        } else if down {
+            self.selected = (self.selected + 1) % count;
+        } else if up {
+            self.selected = (self.selected + count - 1) % count;
+        } else if accept {
+            self.accept(ctx, id, text, self.selected);
+        }
+    }
+
+    /// Replaces the name being typed with a completion, selecting its first argument if it's a
+    /// call.
+    fn accept(&mut self, ctx: &egui::Context, id: egui::Id, text: &mut String, index: usize) {
+        let Some(completions) = self.completions.take() else {
+            return;
+        };
+
+        let completion = &completions.items[index];
+        let start = completions.span.start;
+
+        *text = apply_edits(
+            text,
+            &[TextEdit {
+                span: completions.span.clone(),
+                replacement: completion.insert.clone(),
+            }],
+        );
+
+        let mut state = egui::TextEdit::load_state(ctx, id).unwrap_or_default();
+        state.set_ccursor_range(Some(CCursorRange::two(
+            CCursor::new(start + completion.selection.start),
+            CCursor::new(start + completion.selection.end),
+        )));
+        egui::TextEdit::store_state(ctx, id, state);
+        ctx.memory().request_focus(id);
+    }
+
+    /// Opens, updates or closes the popup after the editor has been shown, and shows it below the
+    /// name being typed.
+    pub fn show(
+        &mut self,
+        ui: &egui::Ui,
+        output: &egui::text_edit::TextEditOutput,
+        text: &mut String,
+    ) {
+        let id = output.response.id;
+        let requested = std::mem::take(&mut self.requested);
+
+        // clicking a completion takes the focus away from the editor, but the popup has to stay
+        // open for the click to be seen
+        let focused = output.response.has_focus() || output.response.lost_focus();
+
+        let cursor = match output.state.ccursor_range() {
+            Some(range) if range.primary == range.secondary && focused => range.primary.index,
+            _ => {
+                self.completions = None;
+                return;
+            }
+        };
+
+        let after_name = cursor > 0 && text.chars().nth(cursor - 1).is_some_and(is_name_char);
+
+        let typed = output.response.changed() && after_name;
+        let moved = output.response.changed() || cursor != self.offset;
+
+        if requested || typed || (self.completions.is_some() && moved) {
+            if requested || after_name {
+                let selected = self
+                    .completions
+                    .as_ref()
+                    .map(|completions| completions.items[self.selected].name.clone());
+
+                self.completions = complete::complete(text, cursor)
+                    .filter(|completions| !completions.items.is_empty());
+
+                // keep the same completion selected while it's still there
+                self.selected = self
+                    .completions
+                    .as_ref()
+                    .zip(selected)
+                    .and_then(|(completions, selected)| {
+                        completions
+                            .items
+                            .iter()
+                            .position(|item| item.name == selected)
+                    })
+                    .filter(|_| !typed)
+                    .unwrap_or(0);
+            } else {
+                self.completions = None;
+            }
+
+            self.offset = cursor;
+        }
+
+        let Some(completions) = &self.completions else {
+            return;
+        };
+
+        let galley = &output.galley;
+        let start = galley
+            .pos_from_cursor(&galley.from_ccursor(CCursor::new(completions.span.start)))
+            .translate(output.text_draw_pos.to_vec2());
+
+        let first = self.selected.saturating_sub(MAX_SHOWN - 1);
+        let mut clicked = None;
+
+        egui::Area::new(id.with("completions"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(start.left_bottom())
+            .show(ui.ctx(), |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.set_min_width(200.0);
+
+                    for (index, item) in completions
+                        .items
+                        .iter()
+                        .enumerate()
+                        .skip(first)
+                        .take(MAX_SHOWN)
+                    {
+                        let label = match item.kind {
+                            CompletionKind::Function => item.detail.clone(),
+                            _ => item.name.clone(),
+                        };
+
+                        ui.horizontal(|ui| {
+                            let response = ui.selectable_label(
+                                index == self.selected,
+                                egui::RichText::new(label).monospace(),
+                            );
+
+                            if response.clicked() {
+                                clicked = Some(index);
+                            }
+
+                            ui.with_layout(
+                                egui::Layout::right_to_left(egui::Align::Center),
+                                |ui| {
+                                    ui.label(egui::RichText::new(item.kind.name()).weak());
+                                },
+                            );
+                        });
+                    }
+
+                    if completions.items.len() > MAX_SHOWN {
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{} of {}",
+                                self.selected + 1,
+                                completions.items.len()
+                            ))
+                            .weak(),
+                        );
+                    }
+                });
+            });
+
+        if let Some(index) = clicked {
+            self.accept(ui.ctx(), id, text, index);
+        }
+    }
+}
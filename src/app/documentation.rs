@@ -1,5 +1,7 @@
 use eframe::egui;
 use egui_commonmark::*;
+use laser_studio::expressions::catalog::{self, CATALOG};
+use laser_studio::expressions::errors::ErrorCode;
 
 #[derive(PartialEq, Clone)]
 enum DocumentationEntry {
@@ -46,9 +48,12 @@ impl Default for DocumentationWindow {
                 vec![
                     DocumentationEntry::Page("Editor".into(), "# Unfinished".into()),
                     DocumentationEntry::Page("Render".into(), "# Unfinished".into()),
-                    DocumentationEntry::Page("Errors".into(), "# Unfinished".into()),
                 ],
             ),
+            DocumentationEntry::Group(
+                "Errors".into(),
+                CATALOG.iter().map(|entry| error_page(entry.code)).collect(),
+            ),
         ];
 
         Self {
@@ -58,7 +63,20 @@ impl Default for DocumentationWindow {
     }
 }
 
+/// The page for an error code, generated from the error catalog.
+fn error_page(code: ErrorCode) -> DocumentationEntry {
+    DocumentationEntry::Page(
+        format!("{code}: {}", catalog::entry(code).title),
+        catalog::page(code),
+    )
+}
+
 impl DocumentationWindow {
+    /// Selects the page for an error code.
+    pub fn show_error(&mut self, code: ErrorCode) {
+        self.page = error_page(code);
+    }
+
     pub fn update(&mut self, ctx: &egui::Context, open: &mut bool) {
         egui::Window::new("Documentation")
             .open(open)
@@ -0,0 +1,192 @@
+//! Syntax highlighting for the text editor, driven by the expression lexer.
+
+use eframe::egui;
+use eframe::egui::text::{LayoutJob, LayoutSection, TextFormat};
+use eframe::egui::util::cache::{ComputerMut, FrameCache};
+use eframe::egui::Color32;
+use laser_studio::expressions::eval::check;
+use laser_studio::expressions::eval::resolve::{INPUT_NAMES, OUTPUT_NAMES};
+use laser_studio::expressions::lexer::{lex, TokenKind};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The colours the editor shows each kind of token in. It's saved along with the rest of the
+/// app's settings, so any colour changed from a preset is kept between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HighlightTheme {
+    pub background: Color32,
+    /// Whitespace, and anything without a colour of its own.
+    pub text: Color32,
+    pub number: Color32,
+    /// Variables the expression assigns to itself.
+    pub variable: Color32,
+    pub input: Color32,
+    pub output: Color32,
+    pub function: Color32,
+    pub operator: Color32,
+    /// Parentheses, commas and semi-colons.
+    pub punctuation: Color32,
+    pub comment: Color32,
+    /// Characters that aren't part of the expression language.
+    pub invalid: Color32,
+}
+
+impl Default for HighlightTheme {
+    fn default() -> Self {
+        HighlightTheme::dark()
+    }
+}
+
+impl HighlightTheme {
+    pub fn dark() -> HighlightTheme {
+        HighlightTheme {
+            background: Color32::from_gray(10),
+            text: Color32::from_gray(200),
+            number: Color32::from_rgb(181, 206, 168),
+            variable: Color32::from_rgb(156, 220, 254),
+            input: Color32::from_rgb(78, 201, 176),
+            output: Color32::from_rgb(255, 198, 109),
+            function: Color32::from_rgb(220, 220, 170),
+            operator: Color32::from_rgb(212, 212, 212),
+            punctuation: Color32::from_gray(150),
+            comment: Color32::from_rgb(106, 153, 85),
+            invalid: Color32::from_rgb(244, 71, 71),
+        }
+    }
+
+    pub fn light() -> HighlightTheme {
+        HighlightTheme {
+            background: Color32::from_gray(250),
+            text: Color32::from_gray(30),
+            number: Color32::from_rgb(9, 134, 88),
+            variable: Color32::from_rgb(0, 16, 128),
+            input: Color32::from_rgb(38, 127, 153),
+            output: Color32::from_rgb(175, 0, 219),
+            function: Color32::from_rgb(121, 94, 38),
+            operator: Color32::from_gray(50),
+            punctuation: Color32::from_gray(110),
+            comment: Color32::from_rgb(0, 128, 0),
+            invalid: Color32::from_rgb(205, 49, 49),
+        }
+    }
+
+    /// The themes that can be picked in the editor, with their names.
+    pub fn presets() -> [(&'static str, HighlightTheme); 2] {
+        [
+            ("Dark", HighlightTheme::dark()),
+            ("Light", HighlightTheme::light()),
+        ]
+    }
+
+    /// Every colour of the theme, with the name it's shown under in the editor.
+    pub fn colors_mut(&mut self) -> [(&'static str, &mut Color32); 11] {
+        [
+            ("Background", &mut self.background),
+            ("Text", &mut self.text),
+            ("Numbers", &mut self.number),
+            ("Variables", &mut self.variable),
+            ("Inputs", &mut self.input),
+            ("Outputs", &mut self.output),
+            ("Functions", &mut self.function),
+            ("Operators", &mut self.operator),
+            ("Punctuation", &mut self.punctuation),
+            ("Comments", &mut self.comment),
+            ("Invalid characters", &mut self.invalid),
+        ]
+    }
+
+    fn color(&self, kind: TokenKind, text: &str) -> Color32 {
+        match kind {
+            TokenKind::Number => self.number,
+            TokenKind::Identifier if check::arity(text).is_some() => self.function,
+            TokenKind::Identifier if INPUT_NAMES.contains(&text) => self.input,
+            TokenKind::Identifier if OUTPUT_NAMES.contains(&text) => self.output,
+            TokenKind::Identifier => self.variable,
+            TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Star
+            | TokenKind::Slash
+            | TokenKind::Percent
+            | TokenKind::Caret
+            | TokenKind::Less
+            | TokenKind::Greater
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual
+            | TokenKind::EqualEqual
+            | TokenKind::Ampersand
+            | TokenKind::Pipe
+            | TokenKind::Bang
+            | TokenKind::Equals => self.operator,
+            TokenKind::Comma
+            | TokenKind::Semicolon
+            | TokenKind::LeftParen
+            | TokenKind::RightParen => self.punctuation,
+            TokenKind::Comment => self.comment,
+            TokenKind::Whitespace => self.text,
+            TokenKind::Unknown => self.invalid,
+        }
+    }
+}
+
+/// Lays out highlighted text. Neighbouring tokens of the same colour share a section, so that
+/// whitespace and long runs of operators don't each get their own.
+fn highlight(theme: &HighlightTheme, font_id: egui::FontId, text: &str) -> LayoutJob {
+    let mut job = LayoutJob {
+        text: String::with_capacity(text.len()),
+        ..Default::default()
+    };
+
+    for token in lex(text) {
+        let format = TextFormat::simple(font_id.clone(), theme.color(token.kind, &token.text));
+        let start = job.text.len();
+
+        job.text.push_str(&token.text);
+
+        match job.sections.last_mut() {
+            Some(last) if last.format == format => last.byte_range.end = job.text.len(),
+            _ => job.sections.push(LayoutSection {
+                leading_space: 0.0,
+                byte_range: start..job.text.len(),
+                format,
+            }),
+        }
+    }
+
+    job
+}
+
+#[derive(Default)]
+struct Highlighter;
+
+impl ComputerMut<(&HighlightTheme, &egui::FontId, &str), LayoutJob> for Highlighter {
+    fn compute(
+        &mut self,
+        (theme, font_id, text): (&HighlightTheme, &egui::FontId, &str),
+    ) -> LayoutJob {
+        highlight(theme, font_id.clone(), text)
+    }
+}
+
+type HighlightCache = FrameCache<LayoutJob, Highlighter>;
+
+/// Lays out the text of the editor, highlighted with `theme`. The text is only lexed again when
+/// it (or the theme) changes; otherwise the layout from the last frame is reused.
+pub fn layout(
+    ui: &egui::Ui,
+    theme: &HighlightTheme,
+    text: &str,
+    wrap_width: f32,
+) -> Arc<egui::Galley> {
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+
+    let mut job = ui
+        .ctx()
+        .memory()
+        .caches
+        .cache::<HighlightCache>()
+        .get((theme, &font_id, text));
+
+    job.wrap.max_width = wrap_width;
+    ui.fonts().layout_job(job)
+}
@@ -0,0 +1,216 @@
+//! Shows the problems with the expression in the text editor: squiggles under the text they're
+//! about, icons in the line-number gutter and their messages on hover.
+
+use ahash::AHashMap;
+use eframe::egui;
+use eframe::egui::text::CCursor;
+use laser_studio::expressions::errors::{Diagnostic, ErrorCode, Severity};
+use laser_studio::expressions::eval::check;
+use laser_studio::expressions::parser::Span;
+use laser_studio::expressions::partial;
+use laser_studio::expressions::source_map::SourceMap;
+use std::sync::Arc;
+
+// how far the squiggles go up and down, and how long each stroke of them is
+const SQUIGGLE_HEIGHT: f32 = 1.5;
+const SQUIGGLE_STEP: f32 = 3.0;
+
+/// The problems that can be found without running the expression, kept up to date with its
+/// text.
+#[derive(Default)]
+pub struct EditorDiagnostics {
+    /// The text and Tower compatibility setting the diagnostics were found with.
+    text: String,
+    tower: bool,
+    source_map: SourceMap,
+    /// The diagnostics shown in the editor, with their spans trimmed of whitespace. Skipped
+    /// assignments (I1) are left out, since the parser errors that caused them are shown.
+    diagnostics: Vec<(Span, Diagnostic)>,
+    /// The most serious problem that starts on each line, and the messages of every problem
+    /// that does.
+    line_markers: AHashMap<usize, (Severity, String)>,
+}
+
+pub fn severity_color(visuals: &egui::Visuals, severity: Severity) -> egui::Color32 {
+    match severity {
+        Severity::Error => visuals.error_fg_color,
+        Severity::Warning => visuals.warn_fg_color,
+        Severity::Info | Severity::Hint => visuals.hyperlink_color,
+    }
+}
+
+fn severity_icon(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "！",
+        Severity::Warning => "⚠",
+        Severity::Info | Severity::Hint => "ℹ",
+    }
+}
+
+impl EditorDiagnostics {
+    /// Finds the problems with the text again, if it (or the Tower compatibility setting) has
+    /// changed since the last time.
+    pub fn update(&mut self, text: &str, tower: bool) {
+        if self.text == text && self.tower == tower {
+            return;
+        }
+
+        self.text = text.to_string();
+        self.tower = tower;
+        self.source_map = SourceMap::new(text);
+
+        // the parts of the text that do parse are still checked, like the render tab runs them
+        let partial = partial::parse(text, tower);
+
+        let mut diagnostics = partial.errors;
+        diagnostics.extend(partial.warnings);
+        diagnostics.extend(check::check(&partial.assignments));
+
+        self.diagnostics = diagnostics
+            .into_iter()
+            .filter(|diagnostic| diagnostic.code != ErrorCode::I1)
+            .map(|diagnostic| (self.source_map.trim(&diagnostic.span), diagnostic))
+            .collect();
+        self.diagnostics.sort_by_key(|(span, _)| span.start);
+
+        self.line_markers.clear();
+
+        for (span, diagnostic) in self.diagnostics.iter() {
+            let line = self.source_map.line_of_char(span.start);
+
+            self.line_markers
+                .entry(line)
+                .and_modify(|(severity, messages)| {
+                    *severity = (*severity).min(diagnostic.severity);
+                    *messages += &format!("\n{}", diagnostic.reason());
+                })
+                .or_insert_with(|| (diagnostic.severity, diagnostic.reason()));
+        }
+    }
+
+    /// The line starts of the text the diagnostics were last found in.
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// Paints the gutter icon for a line, at the right of the row the line number is in.
+    pub fn paint_line_marker(&self, ui: &mut egui::Ui, line: usize, row: egui::Rect) {
+        let Some((severity, messages)) = self.line_markers.get(&line) else {
+            return;
+        };
+
+        let color = severity_color(ui.visuals(), *severity);
+
+        ui.painter().text(
+            egui::pos2(row.right() - 2.0, row.center().y),
+            egui::Align2::RIGHT_CENTER,
+            severity_icon(*severity),
+            egui::FontId::proportional(12.0),
+            color,
+        );
+
+        ui.interact(
+            row,
+            ui.id().with(("line_marker", line)),
+            egui::Sense::hover(),
+        )
+        .on_hover_text(messages.as_str());
+    }
+
+    /// Underlines every problem in the laid out text, and shows the messages of the ones under
+    /// the pointer. Returns whether any messages were shown.
+    pub fn paint_squiggles(
+        &self,
+        ui: &egui::Ui,
+        response: &egui::Response,
+        galley: &Arc<egui::Galley>,
+        text_draw_pos: egui::Pos2,
+    ) -> bool {
+        // the galley is from this frame, but the diagnostics might not be if the text was
+        // edited after they were found
+        if galley.text() != self.text {
+            return false;
+        }
+
+        let painter = ui.painter();
+        let char_rect = |offset: usize| {
+            galley
+                .pos_from_cursor(&galley.from_ccursor(CCursor::new(offset)))
+                .translate(text_draw_pos.to_vec2())
+        };
+
+        for (span, diagnostic) in self.diagnostics.iter() {
+            let stroke = egui::Stroke::new(1.0, severity_color(ui.visuals(), diagnostic.severity));
+            let first = self.source_map.line_of_char(span.start);
+            let last = self.source_map.line_of_char(span.end);
+
+            for line in first..=last {
+                let bounds = self.source_map.line_span(line);
+                let start = char_rect(span.start.max(bounds.start));
+                let end = char_rect(span.end.min(bounds.end));
+
+                // spans without any text, like a missing semi-colon, still get a short squiggle
+                let right = end.left().max(start.left() + SQUIGGLE_STEP * 2.0);
+
+                painter.add(squiggle(start.left(), right, start.bottom(), stroke));
+            }
+        }
+
+        let Some(pointer) = response.hover_pos() else {
+            return false;
+        };
+
+        let offset = galley
+            .cursor_from_pos(pointer - text_draw_pos)
+            .ccursor
+            .index;
+
+        let hovered: Vec<&Diagnostic> = self
+            .diagnostics
+            .iter()
+            .filter(|(span, _)| span.start <= offset && offset <= span.end.max(span.start + 1))
+            .map(|(_, diagnostic)| diagnostic)
+            .collect();
+
+        if hovered.is_empty() {
+            return false;
+        }
+
+        egui::show_tooltip_at_pointer(ui.ctx(), ui.id().with("squiggle_tooltip"), |ui| {
+            for diagnostic in hovered {
+                ui.label(
+                    egui::RichText::new(diagnostic.reason())
+                        .color(severity_color(ui.visuals(), diagnostic.severity)),
+                );
+
+                if let Some(help) = &diagnostic.help {
+                    ui.label(egui::RichText::new(format!("help: {help}")).weak());
+                }
+            }
+        });
+
+        true
+    }
+}
+
+/// A wavy line from `left` to `right`, just above `bottom`.
+fn squiggle(left: f32, right: f32, bottom: f32, stroke: egui::Stroke) -> egui::Shape {
+    let middle = bottom - SQUIGGLE_HEIGHT;
+    let mut points = vec![];
+    let mut x = left;
+    let mut up = true;
+
+    while x < right {
+        let y = if up {
+            middle - SQUIGGLE_HEIGHT
+        } else {
+            middle + SQUIGGLE_HEIGHT
+        };
+        points.push(egui::pos2(x, y));
+        x += SQUIGGLE_STEP;
+        up = !up;
+    }
+
+    points.push(egui::pos2(right, middle));
+    egui::Shape::line(points, stroke)
+}
@@ -1,11 +1,27 @@
-use crate::expressions::*;
+use super::markers::severity_color;
 use ahash::AHashMap;
 use chrono::{DateTime, Local};
-use chumsky::Parser;
 use eframe::egui;
 use eframe::egui::plot;
 use egui_extras::{Size, TableBuilder};
-use rayon::prelude::*;
+use laser_studio::expressions::eval::check;
+use laser_studio::expressions::eval::context::{Backend, ExecutionContext};
+use laser_studio::expressions::eval::data::CalcuatedPoint;
+use laser_studio::expressions::eval::grid::Grid;
+use laser_studio::expressions::source_map::SourceMap;
+use laser_studio::expressions::*;
+
+/// The values of the variables from the last render, for the editor's tooltips.
+#[derive(Default)]
+pub struct VariableValues {
+    /// The index the inspector is showing.
+    pub index: u16,
+    pub at_index: AHashMap<String, f64>,
+    /// The smallest and largest value of each variable over every point.
+    pub ranges: AHashMap<String, (f64, f64)>,
+    /// The number of points that were rendered.
+    pub count: usize,
+}
 
 #[derive(PartialEq)]
 pub enum ToolsTab {
@@ -15,11 +31,18 @@ pub enum ToolsTab {
 }
 
 pub struct RenderWorkspace {
-    parser_result: Vec<parser::Assignment>,
-    parser_errors: Vec<errors::Error>,
-    eval_errors: Vec<Vec<errors::Error>>,
-    eval_variables: Vec<AHashMap<String, f64>>,
-    eval_result: Vec<RenderedPoint>,
+    backend: Backend,
+    context: Box<dyn ExecutionContext>,
+    parser_errors: Vec<errors::Diagnostic>,
+    compile_errors: Vec<errors::Diagnostic>,
+    /// The Tower Unite quirks the expression relies on, when it's parsed in Tower compatibility
+    /// mode.
+    warnings: Vec<errors::Diagnostic>,
+    /// The assignments left out because the expression only partly parsed.
+    skipped: Vec<errors::Diagnostic>,
+    /// The text that was last loaded, which the spans of all the errors point into.
+    source_map: SourceMap,
+    eval_result: Vec<CalcuatedPoint>,
     projection_start_time: DateTime<Local>,
     tools_tab: ToolsTab,
     tools_index_tb: u16,
@@ -27,15 +50,27 @@ pub struct RenderWorkspace {
     encountered_eval_error: bool,
     encountered_parser_error: bool,
     eval_error_indexes: Vec<u16>,
+    /// The code whose documentation page was asked for from the errors table, until the app
+    /// opens it.
+    pub requested_documentation: Option<errors::ErrorCode>,
+    /// The span of the error that was clicked in the errors table, until the app shows it in the
+    /// text editor.
+    pub requested_location: Option<parser::Span>,
+    /// The values of the variables, worked out the first time they're asked for after each
+    /// render.
+    variable_values: Option<VariableValues>,
 }
 
 impl Default for RenderWorkspace {
     fn default() -> Self {
         Self {
-            parser_result: vec![],
+            backend: Backend::Interpreter,
+            context: Backend::Interpreter.create(),
             parser_errors: vec![],
-            eval_errors: vec![],
-            eval_variables: vec![],
+            compile_errors: vec![],
+            warnings: vec![],
+            skipped: vec![],
+            source_map: SourceMap::default(),
             eval_result: vec![],
             projection_start_time: Local::now(),
             tools_tab: ToolsTab::Hidden,
@@ -44,137 +79,182 @@ impl Default for RenderWorkspace {
             encountered_eval_error: false,
             encountered_parser_error: false,
             eval_error_indexes: vec![],
+            requested_documentation: None,
+            requested_location: None,
+            variable_values: None,
         }
     }
 }
 
-#[derive(Debug)]
-struct RenderedPoint {
-    x: f64,
-    y: f64,
-    h: f64,
-    s: f64,
-    v: f64,
-    index: u16,
-}
-
 impl RenderWorkspace {
-    pub fn on_switch_render(&mut self, project: &crate::project::Project) {
+    pub fn on_switch_render(&mut self, project: &laser_studio::project::Project) {
+        if project.backend() != self.backend {
+            self.backend = project.backend();
+            self.context = self.backend.create();
+        }
+
         self.eval_frozen = false;
         self.encountered_eval_error = false;
         self.encountered_parser_error = false;
         self.eval_error_indexes = vec![];
-        self.eval_errors = vec![];
         self.parser_errors = vec![];
+        self.compile_errors = vec![];
+        self.warnings = vec![];
+        self.skipped = vec![];
+        self.variable_values = None;
         self.projection_start_time = Local::now();
 
-        match parser::parser().parse(project.text_data.content.clone()) {
-            Ok(value) => {
-                self.parser_errors = vec![];
-                self.parser_result = value;
-                self.calculate_points(project.text_data.content.clone(), 20, 20);
+        let text = &project.text_data.content;
+        self.source_map = SourceMap::new(text);
+
+        let parsed = if project.tower_compatibility {
+            compat::parse(text).map(|parsed| {
+                self.warnings = parsed.warnings;
+                parsed.assignments
+            })
+        } else {
+            parser::parse(text)
+        };
+
+        match parsed {
+            Ok(assignments) => {
+                self.compile_errors = check::check(&assignments);
+                self.context.load_parsed(assignments);
+
+                if !self.compile_errors.is_empty() {
+                    self.tools_tab = ToolsTab::Errors;
+                }
+
+                self.eval_result = self.calculate_points(20, 20);
             }
             Err(error) => {
-                self.parser_errors = error
-                    .iter()
-                    .map(|err| {
-                        parser::process_parser_error(err.clone(), project.text_data.content.clone())
-                    })
-                    .collect();
+                // the assignments that did parse are still run, so that a typo in one of them
+                // doesn't blank the whole preview
+                let partial = partial::parse(text, project.tower_compatibility);
 
-                // calculate a set of points so that we don't panic
-                self.calculate_points("".to_string(), 20, 20);
-                self.encountered_parser_error = true;
+                self.parser_errors = partial.errors;
+                self.skipped = partial.skipped;
                 self.tools_tab = ToolsTab::Errors;
+
+                if partial.assignments.is_empty() {
+                    // clears out anything left over from the last expression
+                    self.context.load_parsed(vec![]);
+
+                    self.parser_errors = error;
+                    self.skipped = vec![];
+                    self.eval_result = vec![];
+                    self.encountered_parser_error = true;
+                } else {
+                    self.warnings = partial.warnings;
+                    self.compile_errors = check::check(&partial.assignments);
+                    self.context.load_parsed(partial.assignments);
+                    self.eval_result = self.calculate_points(20, 20);
+                }
             }
         };
     }
 
-    fn calculate_points(&mut self, text: String, x_size: u16, y_size: u16) -> Vec<RenderedPoint> {
+    /// Replaces the current ExecutionContext with a new one from the given backend, and reloads
+    /// the project with it. The choice is stored in the project, so it's kept when it's saved.
+    fn set_backend(&mut self, backend: Backend, project: &mut laser_studio::project::Project) {
+        project.backend = Some(backend.id().to_string());
+        self.on_switch_render(project);
+    }
+
+    fn calculate_points(&mut self, x_size: u16, y_size: u16) -> Vec<CalcuatedPoint> {
         self.encountered_eval_error = false;
         self.eval_error_indexes = vec![];
+        self.variable_values = None;
 
         let time = Local::now().naive_local().timestamp_millis() as f64 / 1000.0;
 
         let projection_start_time =
             self.projection_start_time.naive_local().timestamp_millis() as f64 / 1000.0;
 
-        let base_ctx = eval::EvalContext {
-            x: 0.0,
-            y: 0.0,
-            index: 0.0,
-            count: (x_size * y_size) as f64,
-            fraction: 0.0,
-            pi: std::f64::consts::PI,
-            tau: std::f64::consts::TAU,
-            time,
-            projection_time: time - projection_start_time,
-            projection_start_time,
-        };
+        let grid = Grid { x_size, y_size };
 
-        self.eval_errors = vec![];
-        self.eval_variables = vec![];
-
-        let points: Vec<(
-            AHashMap<String, f64>,
-            Vec<errors::Error>,
-            eval::EvalContext,
-            u16,
-        )> = (0..(x_size * y_size))
-            .into_par_iter()
-            .map(|index| {
-                let mut ctx = base_ctx.clone();
-
-                let f_index = index as f64;
-                let x_size = x_size as f64;
-                let y_size = y_size as f64;
-
-                ctx.index = f_index;
-                ctx.x = -100.0 + (f_index % x_size) * (200.0 / (x_size - 1.0));
-                ctx.y = 100.0 - (200.0 / (y_size - 1.0)) * f64::floor(f_index / x_size);
-                ctx.fraction = f_index / (ctx.count - 1.0);
-
-                let mut hash_map = AHashMap::new();
-
-                let result =
-                    eval::run(self.parser_result.clone(), text.clone(), &mut hash_map, ctx);
-                let error = result.1.clone();
-                (hash_map, error, ctx, index)
-            })
-            .collect();
-
-        for tuple in points.clone() {
-            if tuple.1.len() > 0 {
-                self.encountered_eval_error = true;
-                self.eval_error_indexes.push(tuple.3);
-                self.eval_frozen = true;
-                self.tools_index_tb = tuple.3;
-                self.tools_tab = ToolsTab::Errors;
+        let (points, encountered_error) =
+            match self.context.execute(grid, time, projection_start_time) {
+                Ok(value) => value,
+                Err(error) => {
+                    self.parser_errors = vec![*error];
+                    self.encountered_parser_error = true;
+                    self.tools_tab = ToolsTab::Errors;
+                    return vec![];
+                }
+            };
+
+        if encountered_error {
+            for index in 0..grid.count() {
+                // errors that the compile-time check already found are only shown once, so
+                // they don't stop execution
+                let has_errors = match self.context.retrieve_errors(index) {
+                    Ok(errors) => errors.iter().any(|error| {
+                        !self
+                            .compile_errors
+                            .iter()
+                            .any(|compile_error| compile_error.is_same_as(error))
+                    }),
+                    Err(_) => false,
+                };
+
+                if has_errors {
+                    self.encountered_eval_error = true;
+                    self.eval_error_indexes.push(index);
+                    self.eval_frozen = true;
+                    self.tools_index_tb = index;
+                    self.tools_tab = ToolsTab::Errors;
+                }
             }
+        }
+
+        points
+    }
 
-            self.eval_errors.push(tuple.1.clone());
-            self.eval_variables.push(tuple.0.clone());
+    /// The values of the variables at the index the inspector is showing, and their ranges over
+    /// every point, from the last render.
+    pub fn variable_values(&mut self) -> &VariableValues {
+        let index = self.tools_index_tb;
+
+        if self
+            .variable_values
+            .as_ref()
+            .is_some_and(|values| values.index != index)
+        {
+            self.variable_values = None;
         }
 
-        let calculated_points = points
-            .par_iter()
-            .map(|(variables, _errors, _ctx, index)| RenderedPoint {
-                x: *variables.get("x'").unwrap_or(&0.0),
-                y: *variables.get("y'").unwrap_or(&0.0),
-                h: *variables.get("h").unwrap_or(&0.0),
-                s: *variables.get("s").unwrap_or(&0.0),
-                v: *variables.get("v").unwrap_or(&1.0),
-                index: *index,
-            })
-            .collect();
+        let count = self.eval_result.len();
+        let context = &mut self.context;
+
+        self.variable_values.get_or_insert_with(|| {
+            let mut ranges: AHashMap<String, (f64, f64)> = AHashMap::new();
+
+            for i in 0..count {
+                for (name, value) in context.retrieve_variables(i as u16).unwrap_or_default() {
+                    ranges
+                        .entry(name)
+                        .and_modify(|(min, max)| {
+                            *min = min.min(value);
+                            *max = max.max(value);
+                        })
+                        .or_insert((value, value));
+                }
+            }
 
-        calculated_points
+            VariableValues {
+                index,
+                at_index: context.retrieve_variables(index).unwrap_or_default(),
+                ranges,
+                count,
+            }
+        })
     }
 
     pub fn update_render_workspace(
         &mut self,
         ctx: &egui::Context,
-        project: &mut crate::project::Project,
+        project: &mut laser_studio::project::Project,
     ) {
         let mut tools_frame = egui::Frame::default();
 
@@ -207,6 +287,32 @@ impl RenderWorkspace {
                         )
                     })
                 });
+        } else if !self.parser_errors.is_empty() {
+            egui::containers::Area::new("Partial Parser Error")
+                .fixed_pos(egui::pos2(0.0, 30.0))
+                .show(ctx, |ui| {
+                    frame.show(ui, |ui| {
+                        ui.label(
+                            egui::RichText::new(
+                                "Some assignments couldn't be parsed and were skipped; see the errors displayed below.",
+                            )
+                            .color(egui::Color32::WHITE),
+                        )
+                    })
+                });
+        } else if !self.compile_errors.is_empty() {
+            egui::containers::Area::new("Compile Error")
+                .fixed_pos(egui::pos2(0.0, 30.0))
+                .show(ctx, |ui| {
+                    frame.show(ui, |ui| {
+                        ui.label(
+                            egui::RichText::new(
+                                "The expression contains errors; see the errors displayed below.",
+                            )
+                            .color(egui::Color32::WHITE),
+                        )
+                    })
+                });
         } else if self.encountered_eval_error {
             egui::containers::Area::new("Eval Error")
                 .fixed_pos(egui::pos2(0.0, 30.0))
@@ -275,13 +381,14 @@ impl RenderWorkspace {
                             self.tools_tab = ToolsTab::Hidden;
                         };
 
-                        let text = if self.encountered_parser_error {
-                            egui::RichText::new("！ Errors").color(egui::Color32::RED)
-                        } else if self.encountered_eval_error {
-                            egui::RichText::new("⚠ Errors").color(egui::Color32::YELLOW)
-                        } else {
-                            egui::RichText::new("Errors")
-                        };
+                        let text =
+                            if !self.parser_errors.is_empty() || !self.compile_errors.is_empty() {
+                                egui::RichText::new("！ Errors").color(egui::Color32::RED)
+                            } else if self.encountered_eval_error {
+                                egui::RichText::new("⚠ Errors").color(egui::Color32::YELLOW)
+                            } else {
+                                egui::RichText::new("Errors")
+                            };
 
                         if ui
                             .selectable_label(self.tools_tab == ToolsTab::Errors, text)
@@ -297,6 +404,46 @@ impl RenderWorkspace {
                             self.tools_tab = ToolsTab::Inspector;
                         };
 
+                        if !self.warnings.is_empty() {
+                            ui.separator();
+
+                            let fix = ui.button("Fix Tower quirks").on_hover_text(
+                                "Rewrite the expression so that it no longer relies on the mistakes Tower Unite accepts.",
+                            );
+
+                            if fix.clicked() {
+                                if let Ok(text) = compat::fix(&project.text_data.content) {
+                                    project.text_data.content = text;
+                                    self.on_switch_render(project);
+                                }
+                            }
+                        }
+
+                        let fixable = self
+                            .parser_errors
+                            .iter()
+                            .chain(self.compile_errors.iter())
+                            .chain(self.warnings.iter())
+                            .any(|error| error.fix.as_ref().is_some_and(|fix| fix.safe));
+
+                        if fixable {
+                            ui.separator();
+
+                            let fix_all = ui.button("Fix all").on_hover_text(
+                                "Apply the fix for every problem that has an obvious one, such as a missing semi-colon.",
+                            );
+
+                            if fix_all.clicked() {
+                                let (text, _) = quickfix::fix_all(
+                                    &project.text_data.content,
+                                    project.tower_compatibility,
+                                );
+
+                                project.text_data.content = text;
+                                self.on_switch_render(project);
+                            }
+                        }
+
                         ui.with_layout(egui::Layout::right_to_left(egui::Align::BOTTOM), |ui| {
                             if self.tools_tab != ToolsTab::Hidden {
                                 let index_value = egui::DragValue::new(&mut self.tools_index_tb)
@@ -331,6 +478,37 @@ impl RenderWorkspace {
                             ui.add(x_value);
 
                             frame.show(ui, |ui| ui.monospace("rectangular grid, "));
+
+                            ui.separator();
+
+                            let mut backend = self.backend;
+                            let mut changed = false;
+
+                            egui::ComboBox::from_id_source("render_backend")
+                                .selected_text(backend.name())
+                                .show_ui(ui, |ui| {
+                                    for &option in Backend::ALL {
+                                        changed |= ui
+                                            .selectable_value(&mut backend, option, option.name())
+                                            .changed();
+                                    }
+                                });
+
+                            if changed {
+                                self.set_backend(backend, project);
+                            }
+
+                            ui.separator();
+
+                            let tower = ui
+                                .checkbox(&mut project.tower_compatibility, "Tower compatibility")
+                                .on_hover_text(
+                                    "Accept missing semi-colons, arguments and parentheses the way Tower Unite does, with a warning for each.",
+                                );
+
+                            if tower.changed() {
+                                self.on_switch_render(project);
+                            }
                         });
                     });
                 });
@@ -340,30 +518,83 @@ impl RenderWorkspace {
 
                     let index = self.tools_index_tb as usize;
 
-                    let eval_errors = self.eval_errors[index].clone();
-                    let parser_errors = self.parser_errors.clone();
+                    let mut errors: Vec<(&str, errors::Diagnostic)> = vec![];
+
+                    for error in self
+                        .context
+                        .retrieve_errors(index as u16)
+                        .unwrap_or_default()
+                    {
+                        let reported = self
+                            .compile_errors
+                            .iter()
+                            .any(|compile_error| compile_error.is_same_as(&error));
+
+                        if !reported {
+                            errors.push(("Runtime", error));
+                        }
+                    }
+
+                    for error in self.compile_errors.iter() {
+                        errors.push(("Compile", error.clone()));
+                    }
+
+                    for error in self.parser_errors.iter() {
+                        errors.push(("Parse", error.clone()));
+                    }
+
+                    for error in self.warnings.iter() {
+                        errors.push(("Tower", error.clone()));
+                    }
+
+                    for error in self.skipped.iter() {
+                        errors.push(("Skipped", error.clone()));
+                    }
 
                     ui.visuals_mut().widgets.active.rounding = egui::Rounding::none();
                     ui.visuals_mut().widgets.hovered.rounding = egui::Rounding::none();
                     ui.visuals_mut().widgets.inactive.rounding = egui::Rounding::none();
 
+                    let visuals = ui.visuals().clone();
+                    let mut requested = None;
+                    let mut applied = None;
+                    let mut located = None;
+
                     TableBuilder::new(ui)
-                        .column(Size::exact(110.0))
-                        .column(Size::exact(100.0))
+                        .column(Size::exact(80.0))
+                        .column(Size::exact(70.0))
+                        .column(Size::exact(70.0))
+                        .column(Size::exact(70.0))
+                        .column(Size::exact(80.0))
                         .column(Size::remainder())
                         .striped(true)
                         .header(22.0, |mut header| {
                             header.col(|ui| {
                                 ui.horizontal(|ui| {
                                     ui.add_space(10.0);
+                                    ui.label(egui::RichText::new("Severity").strong());
+                                });
+                            });
+                            header.col(|ui| {
+                                ui.horizontal(|ui| {
                                     ui.label(egui::RichText::new("Type").strong());
                                 });
                             });
+                            header.col(|ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("Code").strong());
+                                });
+                            });
                             header.col(|ui| {
                                 ui.horizontal(|ui| {
                                     ui.label(egui::RichText::new("Location").strong());
                                 });
                             });
+                            header.col(|ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("Fix").strong());
+                                });
+                            });
                             header.col(|ui| {
                                 ui.horizontal(|ui| {
                                     ui.label(egui::RichText::new("Error").strong());
@@ -371,55 +602,124 @@ impl RenderWorkspace {
                             });
                         })
                         .body(|mut body| {
-                            for error in eval_errors {
-                                body.row(18.0, |mut row| {
+                            for (error_type, error) in errors {
+                                let location = |span: &parser::Span| {
+                                    let span = self.source_map.trim(span);
+                                    self.source_map.position(span.start).to_string()
+                                };
+
+                                // the message, then one line for each label, note and help
+                                let mut details = vec![];
+
+                                if let Some(label) = &error.label {
+                                    details.push(format!("{}: {label}", location(&error.span)));
+                                }
+
+                                for secondary in error.secondary.iter() {
+                                    details.push(format!(
+                                        "{}: {}",
+                                        location(&secondary.span),
+                                        secondary.message
+                                    ));
+                                }
+
+                                for note in error.notes.iter() {
+                                    details.push(format!("note: {note}"));
+                                }
+
+                                if let Some(help) = &error.help {
+                                    details.push(format!("help: {help}"));
+                                }
+
+                                let height = 18.0 * (details.len() + 1) as f32;
+
+                                body.row(height, |mut row| {
                                     row.col(|ui| {
                                         ui.horizontal(|ui| {
                                             ui.add_space(10.0);
-                                            ui.label("Runtime");
+                                            ui.label(
+                                                egui::RichText::new(error.severity.name())
+                                                    .color(severity_color(&visuals, error.severity)),
+                                            );
                                         });
                                     });
                                     row.col(|ui| {
                                         ui.horizontal(|ui| {
-                                            ui.label(
-                                                error.line_number.to_string()
-                                                    + &String::from(":")
-                                                    + &error.col_number.to_string(),
-                                            );
+                                            ui.label(error_type);
                                         });
                                     });
                                     row.col(|ui| {
                                         ui.horizontal(|ui| {
-                                            ui.label(error.reason.to_string());
+                                            ui.label(error.code.as_str());
+
+                                            let help = ui
+                                                .small_button("?")
+                                                .on_hover_text(catalog::entry(error.code).title);
+
+                                            if help.clicked() {
+                                                requested = Some(error.code);
+                                            }
                                         });
                                     });
-                                });
-                            }
-                            for error in parser_errors {
-                                body.row(18.0, |mut row| {
                                     row.col(|ui| {
                                         ui.horizontal(|ui| {
-                                            ui.add_space(10.0);
-                                            ui.label("Parse");
+                                            let link = ui
+                                                .link(location(&error.span))
+                                                .on_hover_text("Show in the editor");
+
+                                            if link.clicked() {
+                                                located = Some(error.span.clone());
+                                            }
                                         });
                                     });
                                     row.col(|ui| {
                                         ui.horizontal(|ui| {
-                                            ui.label(
-                                                error.line_number.to_string()
-                                                    + &String::from(":")
-                                                    + &error.col_number.to_string(),
-                                            );
+                                            if let Some(fix) = &error.fix {
+                                                let apply = ui
+                                                    .small_button("Apply fix")
+                                                    .on_hover_text(&fix.message);
+
+                                                if apply.clicked() {
+                                                    applied = Some(fix.clone());
+                                                }
+                                            }
                                         });
                                     });
                                     row.col(|ui| {
-                                        ui.horizontal(|ui| {
-                                            ui.label(error.reason.to_string());
+                                        ui.vertical(|ui| {
+                                            let message = ui
+                                                .add(
+                                                    egui::Label::new(&error.message)
+                                                        .sense(egui::Sense::click()),
+                                                )
+                                                .on_hover_text("Show in the editor");
+
+                                            if message.clicked() {
+                                                located = Some(error.span.clone());
+                                            }
+
+                                            for detail in details.iter() {
+                                                ui.label(egui::RichText::new(detail).weak());
+                                            }
                                         });
                                     });
                                 });
                             }
                         });
+
+                    if requested.is_some() {
+                        self.requested_documentation = requested;
+                    }
+
+                    if let Some(span) = located {
+                        self.requested_location = Some(self.source_map.trim(&span));
+                    }
+
+                    if let Some(fix) = applied {
+                        project.text_data.content =
+                            quickfix::apply(&project.text_data.content, &fix);
+                        self.on_switch_render(project);
+                    }
                     //ui.allocate_space(ui.available_size());
                 }
 
@@ -428,7 +728,10 @@ impl RenderWorkspace {
 
                     let index = self.tools_index_tb as usize;
 
-                    let eval_variables: AHashMap<String, f64> = self.eval_variables[index].clone();
+                    let eval_variables: AHashMap<String, f64> = self
+                        .context
+                        .retrieve_variables(index as u16)
+                        .unwrap_or_default();
 
                     ui.visuals_mut().widgets.active.rounding = egui::Rounding::none();
                     ui.visuals_mut().widgets.hovered.rounding = egui::Rounding::none();
@@ -477,7 +780,6 @@ impl RenderWorkspace {
 
         if !self.eval_frozen && !self.encountered_parser_error {
             self.eval_result = self.calculate_points(
-                project.text_data.content.clone(),
                 project.text_data.size_x as u16,
                 project.text_data.size_y as u16,
             );
@@ -1,9 +1,31 @@
+use super::completion::CompletionPopup;
+use super::highlight::{self, HighlightTheme};
+use super::markers::EditorDiagnostics;
+use super::render::VariableValues;
+use super::tooltips;
 use eframe::egui;
 use eframe::egui::text::{CCursor, CCursorRange};
+use laser_studio::expressions::format::FormatStyle;
+use laser_studio::expressions::parser::Span;
+use laser_studio::expressions::{compat, format};
+
+// the width of the line numbers, with room for the icons of the problems on each line
+const GUTTER_WIDTH: f32 = 40.0;
+
+const EDITOR_ID: &str = "text_editor";
 
 pub struct TextWorkspace {
     cursor: egui::widgets::text_edit::CCursorRange,
     rows: Option<Vec<eframe::epaint::text::Row>>,
+    /// Why the last attempt at formatting failed, until the next one succeeds.
+    format_error: Option<String>,
+    /// The colours the editor highlights the expression with.
+    pub highlight_theme: HighlightTheme,
+    /// The problems with the expression, shown in the editor and its gutter.
+    diagnostics: EditorDiagnostics,
+    /// Text to select and scroll to the next time the editor is shown.
+    requested_selection: Option<Span>,
+    completion: CompletionPopup,
 }
 
 impl Default for TextWorkspace {
@@ -20,46 +42,71 @@ impl Default for TextWorkspace {
                 },
             },
             rows: None,
+            format_error: None,
+            highlight_theme: HighlightTheme::default(),
+            diagnostics: EditorDiagnostics::default(),
+            requested_selection: None,
+            completion: CompletionPopup::default(),
         }
     }
 }
 
+/// The options of the formatter, which are saved with the project.
+fn show_format_style(ui: &mut egui::Ui, style: &mut FormatStyle) {
+    ui.checkbox(
+        &mut style.spaces_around_operators,
+        "Spaces around operators",
+    );
+    ui.checkbox(&mut style.space_after_comma, "Space after commas");
+    ui.checkbox(&mut style.keep_parentheses, "Keep parentheses")
+        .on_hover_text("Keep parentheses that operator precedence doesn't need.");
+
+    ui.add(
+        egui::DragValue::new(&mut style.max_blank_lines)
+            .clamp_range(0..=10)
+            .prefix("Blank lines kept in a row: "),
+    );
+
+    ui.separator();
+
+    if ui.button("Reset to defaults").clicked() {
+        *style = FormatStyle::default();
+        ui.close_menu();
+    }
+}
+
 impl TextWorkspace {
-    pub fn get_position_from_range(range: CCursorRange, string: String) -> (u64, u64) {
-        // we don't need the whole span info for the error message,
-        // but we keep it the whole time for things like error highlighting
-        let mut remaining_chars: u64 = range
-            .primary
-            .index
-            .try_into()
-            .expect("text file is longer than 18446744073709551615 characters");
-        let mut line = 0;
-
-        for cur_line in string.lines() {
-            line += 1;
-
-            let len: u64 = cur_line.len().try_into().expect(
-                    "if you encounter this error then something is *extremely* wrong with your computer",
-            );
-
-            if remaining_chars > len {
-                remaining_chars -= len + 1;
-            } else {
-                break;
-            }
-        }
+    /// Formats the project's expression in its own style. Text with parser errors is left alone.
+    /// In Tower compatibility mode, the Tower Unite quirks it relies on are fixed first, since the
+    /// formatter only accepts what the strict parser does.
+    pub fn format(&mut self, project: &mut laser_studio::project::Project) {
+        let fixed = match project.tower_compatibility {
+            true => compat::fix(&project.text_data.content),
+            false => Ok(project.text_data.content.clone()),
+        };
 
-        if remaining_chars == 0 {
-            line += 1;
+        match fixed.and_then(|text| format::format_source(&text, &project.format_style)) {
+            Ok(text) => {
+                project.text_data.content = text;
+                self.format_error = None;
+            }
+            Err(errors) => {
+                self.format_error = Some(format!("Can't format: {} parser error(s)", errors.len()));
+            }
         }
+    }
 
-        return (line, remaining_chars);
+    /// Selects a span of the expression (in characters) and scrolls the editor to it, the next
+    /// time it's shown.
+    pub fn select(&mut self, span: Span) {
+        self.requested_selection = Some(span);
     }
 
     pub fn update_text_workspace(
         &mut self,
         ctx: &egui::Context,
-        project: &mut crate::project::Project,
+        project: &mut laser_studio::project::Project,
+        values: &VariableValues,
     ) {
         let mut frame = egui::Frame::default();
 
@@ -69,7 +116,7 @@ impl TextWorkspace {
             top: 0.0,
             bottom: 0.0,
         };
-        frame.fill = egui::Color32::from_gray(10);
+        frame.fill = self.highlight_theme.background;
 
         let mut status_frame = egui::Frame::default();
 
@@ -85,13 +132,59 @@ impl TextWorkspace {
         egui::TopBottomPanel::bottom("text_status")
             .frame(status_frame)
             .show(ctx, |ui| {
-                let pos = TextWorkspace::get_position_from_range(
-                    self.cursor,
-                    project.text_data.content.clone(),
-                );
+                // the text may have been changed since the editor was last shown, by opening a
+                // project for example
+                self.diagnostics
+                    .update(&project.text_data.content, project.tower_compatibility);
+
+                let pos = self
+                    .diagnostics
+                    .source_map()
+                    .position(self.cursor.primary.index);
+
+                ui.horizontal(|ui| {
+                    ui.label(format!("Line {}, col {}", pos.line + 1, pos.column + 1));
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.button("Format").clicked() {
+                            self.format(project);
+                        }
+
+                        ui.menu_button("Style", |ui| {
+                            show_format_style(ui, &mut project.format_style)
+                        });
+
+                        ui.checkbox(&mut project.format_on_save, "Format on save");
+
+                        ui.separator();
+
+                        let presets = HighlightTheme::presets();
+                        let selected = presets
+                            .iter()
+                            .find(|(_, theme)| *theme == self.highlight_theme)
+                            .map_or("Custom", |(name, _)| name);
 
-                ui.vertical(|ui| {
-                    ui.label(format!("Line {}, col {}", pos.0, pos.1));
+                        egui::ComboBox::from_id_source("highlight_theme")
+                            .selected_text(format!("{selected} theme"))
+                            .show_ui(ui, |ui| {
+                                for (name, theme) in presets {
+                                    ui.selectable_value(&mut self.highlight_theme, theme, name);
+                                }
+                            });
+
+                        ui.menu_button("Colours", |ui| {
+                            for (name, color) in self.highlight_theme.colors_mut() {
+                                ui.horizontal(|ui| {
+                                    ui.color_edit_button_srgba(color);
+                                    ui.label(name);
+                                });
+                            }
+                        });
+
+                        if let Some(error) = &self.format_error {
+                            ui.label(egui::RichText::new(error).color(egui::Color32::RED));
+                        }
+                    });
                 })
             });
 
@@ -115,7 +208,7 @@ impl TextWorkspace {
                         num_frame.show(ui, |ui| {
                             ui.vertical(|ui| {
                                 ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
-                                ui.set_min_width(40.0);
+                                ui.set_min_width(GUTTER_WIDTH);
                                 ui.set_min_height(size_y);
 
                                 let rows = self.rows.as_ref();
@@ -124,14 +217,26 @@ impl TextWorkspace {
                                     Some(rows) => {
                                         let mut cur_num = 1;
                                         let mut last_had_new_line = true;
+                                        let gutter_left = ui.max_rect().left();
 
                                         for row in rows {
                                             if last_had_new_line {
-                                                ui.add(egui::Label::new(
+                                                let number = ui.add(egui::Label::new(
                                                     egui::RichText::new(cur_num.to_string())
                                                         .size(14.0)
                                                         .text_style(egui::TextStyle::Monospace),
                                                 ));
+
+                                                let row = egui::Rect::from_min_size(
+                                                    egui::pos2(gutter_left, number.rect.top()),
+                                                    egui::vec2(GUTTER_WIDTH, number.rect.height()),
+                                                );
+
+                                                self.diagnostics.paint_line_marker(
+                                                    ui,
+                                                    cur_num - 1,
+                                                    row,
+                                                );
                                             } else {
                                                 ui.add(egui::Label::new(
                                                     egui::RichText::new(" ")
@@ -156,12 +261,77 @@ impl TextWorkspace {
                         ui.vertical(|ui| {
                             ui.style_mut().wrap = Some(false);
 
+                            let theme = self.highlight_theme;
+                            let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                                highlight::layout(ui, &theme, text, wrap_width)
+                            };
+
+                            let id = egui::Id::new(EDITOR_ID);
+
+                            // the text might have changed since the span was found
+                            let selection = self.requested_selection.take().map(|span| {
+                                let len = project.text_data.content.chars().count();
+                                CCursorRange::two(
+                                    CCursor::new(span.start.min(len)),
+                                    CCursor::new(span.end.min(len)),
+                                )
+                            });
+
+                            if let Some(selection) = selection {
+                                let mut state =
+                                    egui::TextEdit::load_state(ctx, id).unwrap_or_default();
+                                state.set_ccursor_range(Some(selection));
+                                egui::TextEdit::store_state(ctx, id, state);
+                                ui.memory().request_focus(id);
+                            }
+
+                            self.completion
+                                .handle_keys(ctx, id, &mut project.text_data.content);
+
                             let response =
                                 egui::TextEdit::multiline(&mut project.text_data.content)
+                                    .id(id)
                                     .code_editor()
                                     .frame(false)
                                     .desired_width(f32::INFINITY)
+                                    .layouter(&mut layouter)
                                     .show(ui);
+
+                            if let Some(selection) = selection {
+                                let galley = &response.galley;
+                                let start = galley
+                                    .pos_from_cursor(&galley.from_ccursor(selection.secondary));
+                                let end =
+                                    galley.pos_from_cursor(&galley.from_ccursor(selection.primary));
+
+                                ui.scroll_to_rect(
+                                    start.union(end).translate(response.text_draw_pos.to_vec2()),
+                                    Some(egui::Align::Center),
+                                );
+                            }
+
+                            self.completion
+                                .show(ui, &response, &mut project.text_data.content);
+
+                            self.diagnostics
+                                .update(&project.text_data.content, project.tower_compatibility);
+                            let showed_problems = self.diagnostics.paint_squiggles(
+                                ui,
+                                &response.response,
+                                &response.galley,
+                                response.text_draw_pos,
+                            );
+
+                            // the problems with a name are more important than what it is
+                            if !showed_problems {
+                                tooltips::show(
+                                    ui,
+                                    &response,
+                                    self.diagnostics.source_map(),
+                                    values,
+                                );
+                            }
+
                             match response.state.ccursor_range() {
                                 Some(value) => {
                                     self.cursor = value;
@@ -0,0 +1,167 @@
+//! Tooltips for the names in the text editor: the reference for inputs, outputs and functions,
+//! and the values variables had in the last render.
+
+use super::render::VariableValues;
+use eframe::egui;
+use eframe::egui::text::CCursor;
+use egui_commonmark::*;
+use laser_studio::expressions::lexer::{lex, Token, TokenKind};
+use laser_studio::expressions::reference;
+use laser_studio::expressions::source_map::SourceMap;
+
+/// The `#` comments on the lines right above a line, without their `#`s.
+fn comment_above(source_map: &SourceMap, line: usize) -> Option<String> {
+    let mut lines = vec![];
+
+    for above in (0..line).rev() {
+        match source_map.line_text(above).trim().strip_prefix('#') {
+            Some(comment) => lines.push(comment.trim()),
+            None => break,
+        }
+    }
+
+    lines.reverse();
+    let comment = lines.join("\n");
+
+    (!comment.is_empty()).then_some(comment)
+}
+
+/// The token of the assignment a hovered variable's description comes from: the one being
+/// hovered if it's assigned to there, otherwise the last assignment to it before the pointer, or
+/// the first one after it.
+fn assignment_of<'a>(tokens: &'a [Token], hovered: &Token) -> Option<&'a Token> {
+    let meaningful: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| !token.kind.is_trivia())
+        .collect();
+
+    let assignments: Vec<&Token> = meaningful
+        .windows(2)
+        .filter(|pair| pair[0].text == hovered.text && pair[1].kind == TokenKind::Equals)
+        .map(|pair| pair[0])
+        .collect();
+
+    assignments
+        .iter()
+        .rev()
+        .find(|token| token.span.start <= hovered.span.start)
+        .or_else(|| assignments.first())
+        .copied()
+}
+
+fn show_values(ui: &mut egui::Ui, name: &str, values: &VariableValues) {
+    let Some((min, max)) = values.ranges.get(name) else {
+        ui.label(egui::RichText::new("Run the expression to see its values.").weak());
+        return;
+    };
+
+    match values.at_index.get(name) {
+        Some(value) => ui.label(format!("{value} at index {}", values.index)),
+        None => ui.label(format!("Not assigned to at index {}", values.index)),
+    };
+
+    ui.label(
+        egui::RichText::new(format!(
+            "{min} to {max} over {} points in the last render",
+            values.count
+        ))
+        .weak(),
+    );
+}
+
+/// Shows a tooltip for the name under the pointer, if there is one. `source_map` is of the text
+/// in the editor.
+pub fn show(
+    ui: &egui::Ui,
+    output: &egui::text_edit::TextEditOutput,
+    source_map: &SourceMap,
+    values: &VariableValues,
+) {
+    let text = source_map.text();
+
+    let Some(pointer) = output.response.hover_pos() else {
+        return;
+    };
+
+    let galley = &output.galley;
+
+    // the galley is from before any edit made this frame
+    if galley.text() != text {
+        return;
+    }
+
+    let offset = galley
+        .cursor_from_pos(pointer - output.text_draw_pos)
+        .ccursor
+        .index;
+
+    let tokens = lex(text);
+    let Some(hovered) = tokens.iter().find(|token| {
+        token.kind == TokenKind::Identifier
+            && token.span.start <= offset
+            && offset <= token.span.end
+    }) else {
+        return;
+    };
+
+    // the cursor nearest the pointer is at the end of the line when it's past the end of it, so
+    // the pointer has to actually be over the name
+    let char_rect = |offset: usize| {
+        galley
+            .pos_from_cursor(&galley.from_ccursor(CCursor::new(offset)))
+            .translate(output.text_draw_pos.to_vec2())
+    };
+
+    if !char_rect(hovered.span.start)
+        .union(char_rect(hovered.span.end))
+        .contains(pointer)
+    {
+        return;
+    }
+
+    let name = hovered.text.as_str();
+    let section = reference::section(name);
+    let assignment = assignment_of(&tokens, hovered);
+
+    // names that aren't builtins and are never assigned to already have an error of their own
+    if section.is_none() && assignment.is_none() {
+        return;
+    }
+
+    egui::show_tooltip_at_pointer(ui.ctx(), ui.id().with("name_tooltip"), |ui| {
+        ui.set_max_width(400.0);
+
+        match (section, assignment) {
+            (Some(section), _) => {
+                let title = section.signature.unwrap_or(section.name);
+                ui.label(egui::RichText::new(title).monospace().strong());
+
+                let mut cache = CommonMarkCache::default();
+                CommonMarkViewer::new("name_tooltip_viewer").show(
+                    ui,
+                    &mut cache,
+                    section.description,
+                );
+
+                // outputs can be read back like variables
+                if values.ranges.contains_key(name) {
+                    ui.separator();
+                    show_values(ui, name, values);
+                }
+            }
+            (None, Some(assignment)) => {
+                ui.label(egui::RichText::new(name).monospace().strong());
+
+                let line = source_map.line_of_char(assignment.span.start);
+
+                if let Some(comment) = comment_above(source_map, line) {
+                    ui.label(comment);
+                }
+
+                ui.separator();
+                show_values(ui, name, values);
+            }
+            (None, None) => {}
+        }
+    });
+}
@@ -0,0 +1,810 @@
+use chrono::Local;
+use laser_studio::expressions::eval::check;
+use laser_studio::expressions::eval::context::{Backend, ExecutionContext};
+use laser_studio::expressions::eval::data::CalcuatedPoint;
+use laser_studio::expressions::eval::grid::Grid;
+use laser_studio::expressions::format::FormatStyle;
+use laser_studio::expressions::source_map::SourceMap;
+use laser_studio::expressions::{
+    catalog, compat, edit, errors, format, minify, optimize, parser, quickfix, report,
+};
+use laser_studio::project::Project;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: laser-studio <command> [options]
+
+Commands:
+  check <file>...            Report parser, compile and evaluation errors, fixing the
+                             ones with an obvious fix when given --fix
+  fix <file>                 Print the expression with the mistakes Tower Unite accepts
+                             (missing semi-colons, arguments and parentheses) fixed
+  run <file>                 Print the calculated points for a single frame
+  format <file>              Print the expression with consistent formatting, keeping
+                             its comments
+  minify <file>              Print the shortest text that evaluates the same way,
+                             for pasting into Tower Unite
+  optimize <file>            Print the expression after constant folding, removing
+                             unused assignments and sharing repeated calculations
+  export <file> <output>     Write the calculated points to a file
+  explain <code>             Explain an error or warning code, such as E2
+
+Options:
+  --size <x>x<y>             Grid size (default: the project's size, or 20x20)
+  --time <seconds>           Value of the time input (default: now)
+  --start-time <seconds>     Value of the projectionStartTime input (default: --time)
+  --format <ndjson|csv>      Output format for run and export (default: ndjson, or
+                             csv when exporting to a .csv file)
+  --frames <count>           Number of frames to export, one file per frame (default: 1)
+  --fps <rate>               Frames per second between exported frames (default: 60)
+  --backend <name>           How expressions are executed: interpreter, bytecode,
+                             vectorized or jit (default: interpreter)
+  --style <file>             JSON file with the formatting style to use (default: the
+                             project's style, or the default style)
+  --optimize                 Optimize the expression before running, exporting or
+                             minifying it
+  --tower                    Parse the expression the way Tower Unite does, warning
+                             about each mistake it accepts (default: the project's
+                             setting, or off)
+  --fix                      Apply the fixes for every problem check finds that has an
+                             obvious fix, such as a missing semi-colon, and save the
+                             files before checking them
+
+Files ending in .lsp are read as projects; anything else is read as plain expression text.";
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Ndjson,
+    Csv,
+}
+
+struct Options {
+    files: Vec<PathBuf>,
+    size: Option<Grid>,
+    time: Option<f64>,
+    projection_start_time: Option<f64>,
+    format: Option<OutputFormat>,
+    frames: u32,
+    fps: f64,
+    backend: Backend,
+    optimize: bool,
+    tower: bool,
+    fix: bool,
+    style: Option<FormatStyle>,
+}
+
+struct Source {
+    path: PathBuf,
+    /// The expression, and where its lines start for pointing at its problems.
+    map: SourceMap,
+    grid: Grid,
+    style: FormatStyle,
+    /// Whether the expression is parsed in Tower compatibility mode.
+    tower: bool,
+}
+
+impl Source {
+    fn text(&self) -> &str {
+        self.map.text()
+    }
+}
+
+/// Runs a command line invocation, returning the process' exit code.
+///
+/// 0 means the command succeeded, 1 means the expression had errors, and 2 means the command
+/// itself couldn't be carried out.
+pub fn run(args: Vec<String>) -> i32 {
+    let mut args = args.into_iter();
+    let command = args.next().unwrap_or_default();
+
+    let options = match parse_options(args.collect()) {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return 2;
+        }
+    };
+
+    let result = match command.as_str() {
+        "check" => check(&options),
+        "fix" => fix_command(&options),
+        "run" => run_command(&options),
+        "format" => format_command(&options),
+        "optimize" => optimize_command(&options),
+        "minify" => minify_command(&options),
+        "export" => export(&options),
+        "explain" => explain(&options),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(0)
+        }
+        _ => Err(format!("unknown command '{command}'.\n\n{USAGE}")),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err}");
+            2
+        }
+    }
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        files: vec![],
+        size: None,
+        time: None,
+        projection_start_time: None,
+        format: None,
+        frames: 1,
+        fps: 60.0,
+        backend: Backend::Interpreter,
+        optimize: false,
+        tower: false,
+        fix: false,
+        style: None,
+    };
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.files.push(PathBuf::from(arg));
+            continue;
+        }
+
+        if arg == "--optimize" {
+            options.optimize = true;
+            continue;
+        }
+
+        if arg == "--tower" {
+            options.tower = true;
+            continue;
+        }
+
+        if arg == "--fix" {
+            options.fix = true;
+            continue;
+        }
+
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(format!("missing value for '{arg}'.")),
+        };
+
+        match arg.as_str() {
+            "--size" => options.size = Some(parse_size(&value)?),
+            "--time" => options.time = Some(parse_number(&arg, &value)?),
+            "--start-time" => options.projection_start_time = Some(parse_number(&arg, &value)?),
+            "--format" => {
+                options.format = Some(match value.as_str() {
+                    "ndjson" => OutputFormat::Ndjson,
+                    "csv" => OutputFormat::Csv,
+                    _ => return Err(format!("unknown output format '{value}'.")),
+                })
+            }
+            "--frames" => match value.parse() {
+                Ok(frames) if frames > 0 => options.frames = frames,
+                _ => return Err(format!("invalid frame count '{value}'.")),
+            },
+            "--fps" => match parse_number(&arg, &value)? {
+                fps if fps > 0.0 => options.fps = fps,
+                _ => return Err(format!("invalid frame rate '{value}'.")),
+            },
+            "--backend" => match Backend::from_id(&value) {
+                Some(backend) => options.backend = backend,
+                None => {
+                    let names = Backend::ALL
+                        .iter()
+                        .map(|backend| backend.id())
+                        .collect::<Vec<&str>>()
+                        .join(", ");
+
+                    return Err(format!(
+                        "unknown backend '{value}', expected one of: {names}."
+                    ));
+                }
+            },
+            "--style" => options.style = Some(read_style(&value)?),
+            _ => return Err(format!("unknown option '{arg}'.")),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_number(arg: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("invalid number '{value}' for '{arg}'.")),
+    }
+}
+
+fn parse_size(value: &str) -> Result<Grid, String> {
+    let invalid = || format!("invalid grid size '{value}', expected something like 20x20.");
+
+    let (x, y) = value.split_once('x').ok_or_else(invalid)?;
+
+    // sizes are limited the same way projects are, so that the index count fits in a u16
+    let x_size: u8 = x.parse().map_err(|_| invalid())?;
+    let y_size: u8 = y.parse().map_err(|_| invalid())?;
+
+    if x_size < 2 || y_size < 2 {
+        return Err(invalid());
+    }
+
+    Ok(Grid {
+        x_size: x_size as u16,
+        y_size: y_size as u16,
+    })
+}
+
+fn read_style(path: &str) -> Result<FormatStyle, String> {
+    let text =
+        fs::read_to_string(path).map_err(|err| format!("{path}: failed to read file: {err}"))?;
+
+    serde_json::from_str(&text).map_err(|err| format!("{path}: invalid formatting style: {err}"))
+}
+
+fn load_source(path: &Path, options: &Options) -> Result<Source, String> {
+    let is_project = path.extension().is_some_and(|ext| ext == "lsp");
+
+    let (text, project_grid, project_style, project_tower) = if is_project {
+        let project = Project::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+
+        let grid = Grid {
+            x_size: project.text_data.size_x as u16,
+            y_size: project.text_data.size_y as u16,
+        };
+
+        (
+            project.text_data.content,
+            Some(grid),
+            Some(project.format_style),
+            project.tower_compatibility,
+        )
+    } else {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("{}: failed to read file: {err}", path.display()))?;
+
+        (text, None, None, false)
+    };
+
+    Ok(Source {
+        path: path.to_path_buf(),
+        map: SourceMap::new(&text),
+        grid: options.size.or(project_grid).unwrap_or(Grid {
+            x_size: 20,
+            y_size: 20,
+        }),
+        style: options.style.clone().or(project_style).unwrap_or_default(),
+        tower: options.tower || project_tower,
+    })
+}
+
+/// Replaces the expression of a source file, keeping the rest of a project as it is.
+fn save_source(source: &Source, text: &str) -> Result<(), String> {
+    let path = &source.path;
+
+    if path.extension().is_some_and(|ext| ext == "lsp") {
+        let mut project =
+            Project::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        project.text_data.content = text.to_string();
+        project
+            .save(path)
+            .map_err(|err| format!("{}: {err}", path.display()))
+    } else {
+        fs::write(path, text)
+            .map_err(|err| format!("{}: failed to write file: {err}", path.display()))
+    }
+}
+
+fn single_source(options: &Options) -> Result<Source, String> {
+    match options.files.as_slice() {
+        [path] => load_source(path, options),
+        [] => Err("no input file given.".into()),
+        _ => Err("this command only accepts one input file.".into()),
+    }
+}
+
+/// Prints a diagnostic along with the lines of the source file it points at, in colour when
+/// stderr is a terminal. Expressions are only ever optimized when they have no errors, so every
+/// diagnostic points into the source file's own text.
+fn print_error(source: &Source, error: &errors::Diagnostic) {
+    eprintln!(
+        "{}",
+        report::render(
+            error,
+            &source.map,
+            &source.path.display().to_string(),
+            std::io::stderr().is_terminal()
+        )
+    );
+}
+
+/// Parses an expression, in Tower compatibility mode if the source file asks for it. Returns the
+/// assignments, along with a warning for every Tower Unite quirk they rely on.
+fn parse_text(
+    text: &str,
+    source: &Source,
+) -> Result<(Vec<parser::Assignment>, Vec<errors::Diagnostic>), Vec<errors::Diagnostic>> {
+    if source.tower {
+        compat::parse(text).map(|parsed| (parsed.assignments, parsed.warnings))
+    } else {
+        parser::parse(text).map(|assignments| (assignments, vec![]))
+    }
+}
+
+/// Parses a source file, printing any warnings and parser errors.
+fn parse_source(source: &Source) -> Option<Vec<parser::Assignment>> {
+    match parse_text(source.text(), source) {
+        Ok((value, warnings)) => {
+            for warning in warnings.iter() {
+                print_error(source, warning);
+            }
+
+            Some(value)
+        }
+        Err(parse_errors) => {
+            for err in parse_errors.iter() {
+                print_error(source, err);
+            }
+
+            None
+        }
+    }
+}
+
+/// Optimizes a source file's expression. Expressions with errors are returned unchanged, so that
+/// the errors point at the right place.
+fn optimized_text(source: &Source) -> String {
+    let (assignments, warnings) = match parse_text(source.text(), source) {
+        Ok(value) => value,
+        Err(_) => return source.text().to_string(),
+    };
+
+    if !check::check(&assignments).is_empty() {
+        eprintln!(
+            "warning: {}: not optimizing an expression with errors.",
+            source.path.display()
+        );
+
+        return source.text().to_string();
+    }
+
+    // the optimized text doesn't rely on any quirks, so this is the only time they're mentioned
+    for warning in warnings.iter() {
+        print_error(source, warning);
+    }
+
+    format::format(&optimize::optimize(&assignments))
+}
+
+/// Loads a source file into a new ExecutionContext from the selected backend, printing any
+/// warnings and parser errors.
+fn load_context(source: &Source, options: &Options) -> Option<Box<dyn ExecutionContext>> {
+    let mut context = options.backend.create();
+
+    let text = if options.optimize {
+        optimized_text(source)
+    } else {
+        source.text().to_string()
+    };
+
+    match parse_text(&text, source) {
+        Ok((assignments, warnings)) => {
+            for warning in warnings.iter() {
+                print_error(source, warning);
+            }
+
+            context.load_parsed(assignments);
+            Some(context)
+        }
+        Err(parse_errors) => {
+            for err in parse_errors.iter() {
+                print_error(source, err);
+            }
+
+            None
+        }
+    }
+}
+
+/// Parses a source file and checks it for errors ahead of evaluation, printing any it finds.
+fn check_source(source: &Source) -> Vec<errors::Diagnostic> {
+    let compile_errors = match parse_text(source.text(), source) {
+        Ok((assignments, _)) => check::check(&assignments),
+        Err(_) => vec![],
+    };
+
+    for err in compile_errors.iter() {
+        print_error(source, err);
+    }
+
+    compile_errors
+}
+
+/// Executes a loaded expression for a single frame, printing any evaluation errors that aren't
+/// already in `compile_errors`. Returns the calculated points, and whether there were any errors
+/// that were printed.
+fn execute(
+    context: &mut dyn ExecutionContext,
+    source: &Source,
+    time: f64,
+    projection_start_time: f64,
+    compile_errors: &[errors::Diagnostic],
+) -> Result<(Vec<CalcuatedPoint>, bool), String> {
+    let (points, encountered_error) = context
+        .execute(source.grid, time, projection_start_time)
+        .map_err(|err| {
+            let map = &source.map;
+            let position = map.position(map.trim(&err.span).start);
+            format!("{}:{position}: {}", source.path.display(), err.reason())
+        })?;
+
+    if !encountered_error {
+        return Ok((points, false));
+    }
+
+    let eval_errors: Vec<Vec<errors::Diagnostic>> = (0..source.grid.count())
+        .map(|index| context.retrieve_errors(index).unwrap_or_default())
+        .collect();
+
+    Ok((
+        points,
+        report_eval_errors(source, &eval_errors, compile_errors),
+    ))
+}
+
+fn times(options: &Options) -> (f64, f64) {
+    let time = options
+        .time
+        .unwrap_or_else(|| Local::now().naive_local().timestamp_millis() as f64 / 1000.0);
+
+    (time, options.projection_start_time.unwrap_or(time))
+}
+
+/// Prints every distinct evaluation error once, along with how many indexes it occured in.
+/// Errors that were already reported by the compile-time check are skipped. Returns true if there
+/// were any other errors.
+fn report_eval_errors(
+    source: &Source,
+    eval_errors: &[Vec<errors::Diagnostic>],
+    compile_errors: &[errors::Diagnostic],
+) -> bool {
+    let mut distinct: Vec<(&errors::Diagnostic, usize)> = vec![];
+
+    for error in eval_errors.iter().flatten() {
+        if compile_errors.iter().any(|seen| seen.is_same_as(error)) {
+            continue;
+        }
+
+        match distinct.iter_mut().find(|(seen, _)| seen.is_same_as(error)) {
+            Some((_, count)) => *count += 1,
+            None => distinct.push((error, 1)),
+        }
+    }
+
+    for (error, count) in distinct.iter() {
+        let index_text = if *count == 1 { "index" } else { "indexes" };
+        let error = (*error)
+            .clone()
+            .with_note(format!("This happened in {count} {index_text}."));
+
+        print_error(source, &error);
+    }
+
+    !distinct.is_empty()
+}
+
+fn check(options: &Options) -> Result<i32, String> {
+    if options.files.is_empty() {
+        return Err("no input file given.".into());
+    }
+
+    let (time, projection_start_time) = times(options);
+    let mut found_errors = false;
+
+    for path in options.files.iter() {
+        let mut source = load_source(path, options)?;
+
+        if options.fix {
+            let (text, applied) = quickfix::fix_all(source.text(), source.tower);
+
+            if applied > 0 {
+                save_source(&source, &text)?;
+                eprintln!(
+                    "{}: applied {applied} {}",
+                    path.display(),
+                    if applied == 1 { "fix" } else { "fixes" }
+                );
+                source.map = SourceMap::new(&text);
+            }
+        }
+
+        match load_context(&source, options) {
+            Some(mut context) => {
+                let compile_errors = check_source(&source);
+
+                let (_points, had_errors) = execute(
+                    context.as_mut(),
+                    &source,
+                    time,
+                    projection_start_time,
+                    &compile_errors,
+                )?;
+
+                found_errors |= had_errors || !compile_errors.is_empty();
+            }
+            None => found_errors = true,
+        }
+
+        let fixable = quickfix::diagnose(source.text(), source.tower)
+            .iter()
+            .filter(|diagnostic| diagnostic.fix.as_ref().is_some_and(|fix| fix.safe))
+            .count();
+
+        if fixable > 0 {
+            eprintln!(
+                "{}: {fixable} {} can be fixed with 'laser-studio check --fix'",
+                path.display(),
+                if fixable == 1 { "problem" } else { "problems" }
+            );
+        }
+    }
+
+    Ok(if found_errors { 1 } else { 0 })
+}
+
+fn write_points(
+    writer: &mut impl Write,
+    points: &[CalcuatedPoint],
+    format: OutputFormat,
+) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Ndjson => {
+            for point in points {
+                let line = serde_json::to_string(point)?;
+                writeln!(writer, "{line}")?;
+            }
+        }
+        OutputFormat::Csv => {
+            writeln!(writer, "index,x,y,h,s,v")?;
+
+            for point in points {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{}",
+                    point.index, point.x, point.y, point.h, point.s, point.v
+                )?;
+            }
+        }
+    }
+
+    writer.flush()
+}
+
+fn run_command(options: &Options) -> Result<i32, String> {
+    let source = single_source(options)?;
+
+    let mut context = match load_context(&source, options) {
+        Some(value) => value,
+        None => return Ok(1),
+    };
+
+    let compile_errors = check_source(&source);
+
+    let (time, projection_start_time) = times(options);
+    let (points, had_errors) = execute(
+        context.as_mut(),
+        &source,
+        time,
+        projection_start_time,
+        &compile_errors,
+    )?;
+    let found_errors = had_errors || !compile_errors.is_empty();
+
+    let stdout = std::io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+
+    write_points(
+        &mut writer,
+        &points,
+        options.format.unwrap_or(OutputFormat::Ndjson),
+    )
+    .map_err(|err| format!("failed to write output: {err}"))?;
+
+    Ok(if found_errors { 1 } else { 0 })
+}
+
+fn fix_command(options: &Options) -> Result<i32, String> {
+    let source = single_source(options)?;
+
+    match compat::parse(source.text()) {
+        Ok(parsed) => {
+            for warning in parsed.warnings.iter() {
+                print_error(&source, warning);
+            }
+
+            print!("{}", edit::apply_edits(source.text(), &parsed.edits));
+            Ok(0)
+        }
+        Err(parse_errors) => {
+            for err in parse_errors.iter() {
+                print_error(&source, err);
+            }
+
+            Ok(1)
+        }
+    }
+}
+
+fn format_command(options: &Options) -> Result<i32, String> {
+    let source = single_source(options)?;
+
+    let text = if source.tower {
+        match compat::fix(source.text()) {
+            Ok(text) => text,
+            Err(parse_errors) => {
+                for err in parse_errors.iter() {
+                    print_error(&source, err);
+                }
+
+                return Ok(1);
+            }
+        }
+    } else {
+        source.text().to_string()
+    };
+
+    match format::format_source(&text, &source.style) {
+        Ok(text) => {
+            print!("{text}");
+            Ok(0)
+        }
+        Err(parse_errors) => {
+            for err in parse_errors.iter() {
+                print_error(&source, err);
+            }
+
+            Ok(1)
+        }
+    }
+}
+
+fn optimize_command(options: &Options) -> Result<i32, String> {
+    let source = single_source(options)?;
+
+    let assignments = match parse_source(&source) {
+        Some(value) => value,
+        None => return Ok(1),
+    };
+
+    let compile_errors = check::check(&assignments);
+
+    for err in compile_errors.iter() {
+        print_error(&source, err);
+    }
+
+    if !compile_errors.is_empty() {
+        return Ok(1);
+    }
+
+    print!("{}", format::format(&optimize::optimize(&assignments)));
+
+    Ok(0)
+}
+
+fn minify_command(options: &Options) -> Result<i32, String> {
+    let source = single_source(options)?;
+
+    let source = if options.optimize {
+        Source {
+            map: SourceMap::new(&optimized_text(&source)),
+            ..source
+        }
+    } else {
+        source
+    };
+
+    let assignments = match parse_source(&source) {
+        Some(value) => value,
+        None => return Ok(1),
+    };
+
+    println!("{}", minify::minify(&assignments));
+
+    Ok(0)
+}
+
+fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let file_name = match output.extension() {
+        Some(ext) => format!("{stem}-{frame:04}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{frame:04}"),
+    };
+
+    output.with_file_name(file_name)
+}
+
+fn export(options: &Options) -> Result<i32, String> {
+    let (input, output) = match options.files.as_slice() {
+        [input, output] => (input, output),
+        _ => return Err("export expects an input file and an output file.".into()),
+    };
+
+    let source = load_source(input, options)?;
+
+    let mut context = match load_context(&source, options) {
+        Some(value) => value,
+        None => return Ok(1),
+    };
+
+    let format =
+        options
+            .format
+            .unwrap_or(match output.extension().is_some_and(|ext| ext == "csv") {
+                true => OutputFormat::Csv,
+                false => OutputFormat::Ndjson,
+            });
+
+    let compile_errors = check_source(&source);
+
+    let (time, projection_start_time) = times(options);
+    let mut found_errors = !compile_errors.is_empty();
+
+    for frame in 0..options.frames {
+        let frame_time = time + frame as f64 / options.fps;
+        let (points, had_errors) = execute(
+            context.as_mut(),
+            &source,
+            frame_time,
+            projection_start_time,
+            &compile_errors,
+        )?;
+
+        found_errors |= had_errors;
+
+        let path = match options.frames {
+            1 => output.clone(),
+            _ => frame_path(output, frame),
+        };
+
+        let file = fs::File::create(&path)
+            .map_err(|err| format!("{}: failed to create file: {err}", path.display()))?;
+
+        write_points(&mut BufWriter::new(file), &points, format)
+            .map_err(|err| format!("{}: failed to write file: {err}", path.display()))?;
+    }
+
+    Ok(if found_errors { 1 } else { 0 })
+}
+
+fn explain(options: &Options) -> Result<i32, String> {
+    let code = match options.files.as_slice() {
+        [code] => code.to_string_lossy().to_string(),
+        [] => return Err("no error code given.".into()),
+        _ => return Err("explain only accepts one error code.".into()),
+    };
+
+    match code.parse::<errors::ErrorCode>() {
+        Ok(code) => {
+            print!("{}", catalog::page(code));
+            Ok(0)
+        }
+        Err(()) => {
+            let codes = errors::ErrorCode::ALL.map(|code| code.as_str()).join(", ");
+            Err(format!(
+                "unknown error code '{code}'. The codes are {codes}."
+            ))
+        }
+    }
+}
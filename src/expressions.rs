@@ -1,3 +1,20 @@
+//! Parsing, evaluating and printing expressions.
+
+pub mod catalog;
+pub mod compat;
+pub mod complete;
+pub mod cst;
+pub mod edit;
 pub mod errors;
 pub mod eval;
+pub mod format;
+pub mod lexer;
+pub mod minify;
+pub mod optimize;
 pub mod parser;
+pub mod partial;
+pub mod quickfix;
+pub mod reference;
+pub mod report;
+pub mod source_map;
+pub mod suggest;
@@ -0,0 +1,157 @@
+//! An explanation of every error and warning code, with an example that causes it and the same
+//! example fixed.
+
+use super::errors::ErrorCode;
+
+pub struct CatalogEntry {
+    pub code: ErrorCode,
+    /// A few words describing the problem, for lists of codes.
+    pub title: &'static str,
+    /// What the problem is and how to fix it, in Markdown.
+    pub explanation: &'static str,
+    /// An expression with the problem, and the same expression without it. Codes that are only
+    /// ever caused by a bug in Laser Studio don't have any.
+    pub example: Option<(&'static str, &'static str)>,
+}
+
+pub static CATALOG: [CatalogEntry; 15] = [
+    CatalogEntry {
+        code: ErrorCode::P1,
+        title: "Unexpected character",
+        explanation: "The parser found a character that can't appear where it is, such as an \
+operator with nothing after it, a character that isn't part of the expression language at all, or \
+a `#` comment inside an assignment. Comments are only allowed between assignments.",
+        example: Some(("a = 1 +;", "a = 1 + 2;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::P2,
+        title: "Unexpected end of file",
+        explanation: "The expression ends in the middle of an assignment. This is usually a \
+missing semi-colon after the last assignment, or a parenthesis that's never closed.",
+        example: Some(("x' = x * 2", "x' = x * 2;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::P3,
+        title: "Expected a semi-colon",
+        explanation: "An assignment has ended, but the next character isn't the semi-colon \
+that has to follow it. Every assignment, including the last one, ends with a `;`.",
+        example: Some(("a = 2 3;", "a = 2 * 3;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::P4,
+        title: "Expected a closing parenthesis",
+        explanation: "A parenthesis was opened, but the expression inside it ended without a \
+`)` to close it. The error points at where the `)` was expected, and also shows the parenthesis \
+that's still open.",
+        example: Some(("a = (1 + 2;", "a = (1 + 2);")),
+    },
+    CatalogEntry {
+        code: ErrorCode::P255,
+        title: "Unexpected parser error",
+        explanation: "The parser ran into a problem it doesn't have a better description for. \
+This is a bug in Laser Studio; please report it, along with the expression that caused it.",
+        example: None,
+    },
+    CatalogEntry {
+        code: ErrorCode::E1,
+        title: "Wrong number of arguments",
+        explanation: "A builtin function was called with more or fewer arguments than it takes. \
+The Functions page lists how many arguments each function expects.\n\nTower Unite quietly uses \
+`0` for missing arguments and ignores extra ones. If an expression relies on that, Tower \
+compatibility mode runs it the same way, with a warning (W2) instead of this error.",
+        example: Some(("a = min(1);", "a = min(1, 2);")),
+    },
+    CatalogEntry {
+        code: ErrorCode::E2,
+        title: "Variable used before it's assigned",
+        explanation: "A variable was used, but nothing has been assigned to it yet. Assignments \
+run from top to bottom, so a variable has to be assigned to above the first place it's used. \
+Check the spelling too: variable names are case sensitive, so `projectiontime` isn't the input \
+`projectionTime`.",
+        example: Some(("x' = a;\na = 5;", "a = 5;\nx' = a;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::E3,
+        title: "No such function",
+        explanation: "A function was called that isn't one of the builtin functions. \
+Expressions can't define their own functions; the Functions page lists the ones that exist.",
+        example: Some(("a = sine(x);", "a = sin(x);")),
+    },
+    CatalogEntry {
+        code: ErrorCode::E4,
+        title: "Assignment to a reserved name",
+        explanation: "A variable was given the name of an input (such as `x` or `time`) or a \
+function. Inputs can't be changed, so the assignment is refused. Outputs like `x'` can be \
+assigned to, so if the goal was to move the point, assign to the output instead.",
+        example: Some(("x = 5;", "offset = 5;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::E255,
+        title: "Invalid parser output",
+        explanation: "The evaluator was given a part of an expression that the parser couldn't \
+make sense of. This is a bug in Laser Studio; please report it, along with the expression that \
+caused it.",
+        example: None,
+    },
+    CatalogEntry {
+        code: ErrorCode::W1,
+        title: "Missing semi-colon (Tower compatibility)",
+        explanation: "An assignment doesn't end with a semi-colon. Tower Unite ends the \
+assignment where the next one starts, and so does Tower compatibility mode. The Fix Tower quirks \
+button (or `laser-studio fix`) adds the missing semi-colons.",
+        example: Some(("a = 1\nb = 2;", "a = 1;\nb = 2;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::W2,
+        title: "Missing or extra arguments (Tower compatibility)",
+        explanation: "A builtin function was called with the wrong number of arguments. Tower \
+Unite uses `0` for each missing argument and ignores any extra ones, and so does Tower \
+compatibility mode. Without it, this is error E1.",
+        example: Some(("a = min(x);", "a = min(x, 0);")),
+    },
+    CatalogEntry {
+        code: ErrorCode::W3,
+        title: "Unclosed parenthesis (Tower compatibility)",
+        explanation: "A parenthesis is never closed. Tower Unite closes it at the end of the \
+expression it's in, and so does Tower compatibility mode. Without it, this is error P2 or P4.",
+        example: Some(("a = sin(time;", "a = sin(time);")),
+    },
+    CatalogEntry {
+        code: ErrorCode::W4,
+        title: "Unmatched parenthesis (Tower compatibility)",
+        explanation: "A closing parenthesis doesn't have an opening one to match. Tower Unite \
+ignores it, and so does Tower compatibility mode.",
+        example: Some(("a = 1);", "a = 1;")),
+    },
+    CatalogEntry {
+        code: ErrorCode::I1,
+        title: "Assignment skipped",
+        explanation: "The expression has parser errors, so the render tab runs the rest of it \
+without the assignments that couldn't be parsed. Assignments that use a variable one of them \
+assigns to are skipped too, since they'd be using a value that doesn't exist. Fix the parser \
+errors and every assignment runs again.",
+        example: Some(("a = 1 +;\nx' = a;", "a = 1 + 2;\nx' = a;")),
+    },
+];
+
+/// The catalog entry for a code.
+pub fn entry(code: ErrorCode) -> &'static CatalogEntry {
+    CATALOG
+        .iter()
+        .find(|entry| entry.code == code)
+        .expect("every error code has a catalog entry")
+}
+
+/// The documentation page for a code, in Markdown.
+pub fn page(code: ErrorCode) -> String {
+    let entry = entry(code);
+    let mut page = format!("# {}: {}\n\n{}\n", code, entry.title, entry.explanation);
+
+    if let Some((bad, fixed)) = entry.example {
+        page += &format!(
+            "\n## Example\n\nThis causes {code}:\n\n```\n{bad}\n```\n\nFixed:\n\n```\n{fixed}\n```\n"
+        );
+    }
+
+    page
+}
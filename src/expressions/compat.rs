@@ -0,0 +1,452 @@
+//! Tower compatibility mode, for expressions written against Tower Unite's more forgiving parser.
+
+use super::cst::{self, CstAssignment, CstExpr, CstItem};
+use super::edit::{apply_edits, TextEdit};
+use super::errors::{Diagnostic, ErrorCode};
+use super::eval::check::arity;
+use super::lexer::{lex, TokenKind};
+use super::parser::{Assignment, Expr, Span, Spanned};
+use std::sync::Arc;
+
+/// An expression parsed in Tower compatibility mode.
+#[derive(Debug, Clone)]
+pub struct CompatParse {
+    /// The assignments, as Tower Unite runs them: calls with missing arguments are given `0`
+    /// for each of them, and extra arguments are left out.
+    pub assignments: Vec<Assignment>,
+    /// One warning for every quirk the expression relies on.
+    pub warnings: Vec<Diagnostic>,
+    /// The edits that rewrite the expression into text the regular parser accepts, and that
+    /// evaluates the same way.
+    pub edits: Vec<TextEdit>,
+}
+
+fn edit(span: Span, replacement: &str) -> TextEdit {
+    TextEdit {
+        span,
+        replacement: replacement.to_string(),
+    }
+}
+
+struct Collector {
+    warnings: Vec<Diagnostic>,
+    edits: Vec<TextEdit>,
+}
+
+impl Collector {
+    /// Records a warning, along with the edits of its fix.
+    fn warn(&mut self, warning: Diagnostic) {
+        if let Some(fix) = &warning.fix {
+            self.edits.extend(fix.edits.iter().cloned());
+        }
+
+        self.warnings.push(warning);
+    }
+
+    fn edit(&mut self, span: Span, replacement: &str) {
+        self.edits.push(edit(span, replacement));
+    }
+
+    fn unclosed(&mut self, open: &Span, end: usize) {
+        self.warn(
+            Diagnostic::new(
+                ErrorCode::W3,
+                "This parenthesis is never closed. Tower Unite closes it at the end of the expression.",
+                open.clone(),
+            )
+            .with_label("never closed")
+            .with_secondary(end..end, "Tower Unite closes it here")
+            .with_help("Add a ')' at the end of the expression.")
+            .with_fix("Add a ')'", vec![edit(end..end, ")")]),
+        );
+    }
+
+    fn visit_expr(&mut self, expr: &CstExpr) {
+        match expr {
+            CstExpr::Number(_) | CstExpr::Variable(_) => {}
+            CstExpr::Group { open, inner, close } => {
+                self.visit_expr(inner);
+
+                if close.is_none() {
+                    self.unclosed(&open.token.span, inner.span().end);
+                }
+            }
+            CstExpr::Binary { left, right, .. }
+            | CstExpr::ShortMultiply {
+                number: left,
+                right,
+            } => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            }
+            CstExpr::Unary { operand, .. } => self.visit_expr(operand),
+            CstExpr::Call {
+                name,
+                open,
+                args,
+                close,
+            } => {
+                let expected = arity(&name.token.text).unwrap_or(args.len());
+                let span = expr.span();
+
+                // arguments that are left out aren't looked at any further
+                for (arg, _) in args.iter().take(expected) {
+                    self.visit_expr(arg);
+                }
+
+                // the regular parser doesn't allow anything between the parentheses of a call
+                // without arguments
+                if let (true, Some(close)) = (args.is_empty(), close) {
+                    if !close.trivia.is_empty() {
+                        self.edit(open.token.span.end..close.token.span.start, "");
+                    }
+                }
+
+                let name = &name.token.text;
+                let actual = args.len();
+                let argument_text = |count: usize| match count {
+                    1 => "argument",
+                    _ => "arguments",
+                };
+
+                if actual < expected {
+                    let missing = vec!["0"; expected - actual].join(", ");
+
+                    let (at, inserted) = match (args.last(), close) {
+                        (Some((arg, _)), _) => (arg.span().end, format!(", {missing}")),
+                        (None, Some(close)) => (close.token.span.start, missing),
+                        (None, None) => (open.token.span.end, missing),
+                    };
+
+                    self.warn(
+                        Diagnostic::new(
+                            ErrorCode::W2,
+                            format!(
+                                "Function '{name}' expected {expected} {}, but only got {actual}. Tower Unite uses 0 for the missing {}.",
+                                argument_text(expected),
+                                argument_text(expected - actual),
+                            ),
+                            span.clone(),
+                        )
+                        .with_label(format!("called with {actual} {}", argument_text(actual)))
+                        .with_help(format!(
+                            "Pass the missing {} explicitly.",
+                            argument_text(expected - actual)
+                        ))
+                        .with_fix(
+                            format!("Pass 0 for the missing {}", argument_text(expected - actual)),
+                            vec![edit(at..at, &inserted)],
+                        ),
+                    );
+                } else if actual > expected {
+                    let start = match expected {
+                        0 => args[0].0.span().start,
+                        _ => args[expected - 1].0.span().end,
+                    };
+                    let end = args.last().unwrap().0.span().end;
+
+                    self.warn(
+                        Diagnostic::new(
+                            ErrorCode::W2,
+                            format!(
+                                "Function '{name}' expected {expected} {}, but got {actual}. Tower Unite ignores the extra {}.",
+                                argument_text(expected),
+                                argument_text(actual - expected),
+                            ),
+                            span.clone(),
+                        )
+                        .with_label(format!("called with {actual} {}", argument_text(actual)))
+                        .with_secondary(
+                            args[expected].0.span().start..end,
+                            "ignored by Tower Unite",
+                        )
+                        .with_help(format!(
+                            "Remove the extra {}.",
+                            argument_text(actual - expected)
+                        ))
+                        .with_fix(
+                            format!("Remove the extra {}", argument_text(actual - expected)),
+                            vec![edit(start..end, "")],
+                        ),
+                    );
+                }
+
+                // after any extra arguments, so that it isn't removed along with them
+                if close.is_none() {
+                    self.unclosed(&open.token.span, span.end);
+                }
+            }
+        }
+    }
+
+    fn visit_assignment(&mut self, assignment: &CstAssignment) {
+        self.visit_expr(&assignment.expression);
+
+        // the `;` goes straight after the expression, before any stray `)` that are removed
+        if assignment.semicolon.is_none() {
+            let end = assignment.expression.span().end;
+
+            self.warn(
+                Diagnostic::new(
+                    ErrorCode::W1,
+                    format!(
+                        "The assignment to '{}' is missing a semi-colon. Tower Unite ends it here.",
+                        assignment.name.token.text
+                    ),
+                    end..end,
+                )
+                .with_label("expected ';'")
+                .with_help("Add a ';' at the end of the assignment.")
+                .with_fix("Add a ';'", vec![edit(end..end, ";")]),
+            );
+        }
+
+        for stray in assignment.stray.iter() {
+            self.warn(
+                Diagnostic::new(
+                    ErrorCode::W4,
+                    "This parenthesis doesn't close anything. Tower Unite ignores it.",
+                    stray.token.span.clone(),
+                )
+                .with_label("unmatched parenthesis")
+                .with_help("Remove the parenthesis.")
+                .with_fix(
+                    "Remove the parenthesis",
+                    vec![edit(stray.token.span.clone(), "")],
+                ),
+            );
+        }
+    }
+}
+
+/// Gives every call to a builtin function the number of arguments it expects, filling in `0` for
+/// missing ones and leaving out extra ones.
+fn fill_arguments(spanned_expr: &Spanned<Arc<Expr>>) -> Spanned<Arc<Expr>> {
+    let (expr, span) = spanned_expr;
+
+    let filled = match &**expr {
+        Expr::Number(_) | Expr::Variable(_) | Expr::Error => return spanned_expr.clone(),
+        Expr::Group(inner) => Expr::Group(fill_arguments(inner)),
+        Expr::BinaryExpression(left, op, right) => {
+            Expr::BinaryExpression(fill_arguments(left), op.clone(), fill_arguments(right))
+        }
+        Expr::UnaryExpression(op, operand) => {
+            Expr::UnaryExpression(op.clone(), fill_arguments(operand))
+        }
+        Expr::Call(name, args) => {
+            let expected = arity(name).unwrap_or(args.len());
+            let mut filled: Vec<Spanned<Arc<Expr>>> =
+                args.iter().take(expected).map(fill_arguments).collect();
+
+            while filled.len() < expected {
+                filled.push((Arc::new(Expr::Number(0.0)), span.end..span.end));
+            }
+
+            Expr::Call(name.clone(), filled)
+        }
+    };
+
+    (Arc::new(filled), span.clone())
+}
+
+/// Parses an expression in Tower compatibility mode, accepting the mistakes Tower Unite lets
+/// through (see [`cst::parse_lenient`]) along with calls to builtin functions with too many or
+/// too few arguments. Each mistake gets a warning (W1 to W4) and an edit that fixes it.
+///
+/// Anything that still doesn't parse is returned as parser errors.
+pub fn parse(text: &str) -> Result<CompatParse, Vec<Diagnostic>> {
+    let tree = cst::parse_lenient(text);
+    let mut parse_errors: Vec<Diagnostic> = tree
+        .items
+        .iter()
+        .filter_map(|item| item.error(text))
+        .collect();
+
+    // comments are only allowed between assignments, in either mode
+    let spans: Vec<Span> = tree
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            CstItem::Assignment(assignment) => Some(assignment.span()),
+            CstItem::Error { .. } => None,
+        })
+        .collect();
+
+    for token in lex(text) {
+        let inside = spans.iter().any(|span| span.contains(&token.span.start));
+
+        if token.kind == TokenKind::Comment && inside {
+            parse_errors.push(
+                Diagnostic::new(ErrorCode::P1, "Unexpected character '#'.", token.span)
+                    .with_label("comment inside an assignment")
+                    .with_note("Comments are only allowed between assignments."),
+            );
+        }
+    }
+
+    if !parse_errors.is_empty() {
+        parse_errors.sort_by_key(|error| error.span.start);
+        return Err(parse_errors);
+    }
+
+    let mut collector = Collector {
+        warnings: vec![],
+        edits: vec![],
+    };
+
+    for item in tree.items.iter() {
+        if let CstItem::Assignment(assignment) = item {
+            collector.visit_assignment(assignment);
+        }
+    }
+
+    // parentheses are only known to be unclosed once their contents have been looked at, so
+    // the warnings are put back in the order they appear in the text
+    collector.warnings.sort_by_key(|warning| warning.span.start);
+
+    let assignments = tree
+        .assignments()
+        .into_iter()
+        .map(|assignment| Assignment {
+            expression: fill_arguments(&assignment.expression),
+            ..assignment
+        })
+        .collect();
+
+    Ok(CompatParse {
+        assignments,
+        warnings: collector.warnings,
+        edits: collector.edits,
+    })
+}
+
+/// Rewrites an expression that relies on Tower Unite's quirks into the form the regular parser
+/// accepts, keeping everything else (comments and whitespace included) as it is.
+pub fn fix(text: &str) -> Result<String, Vec<Diagnostic>> {
+    parse(text).map(|parsed| apply_edits(text, &parsed.edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expressions::eval::grid::assert_same_points;
+    use crate::expressions::parser;
+
+    /// Fixes an expression, checking the result parses strictly and evaluates the same as the
+    /// lenient parse, and that there's nothing left to fix. Returns the fixed text and the codes
+    /// of the warnings.
+    fn assert_fixes(source: &str) -> (String, Vec<String>) {
+        let parsed = parse(source).unwrap();
+        let fixed = fix(source).unwrap();
+
+        let strict = match parser::parse(&fixed) {
+            Ok(value) => value,
+            Err(errors) => panic!("{fixed:?} (from {source:?}) doesn't parse: {errors:?}"),
+        };
+
+        assert_same_points(&parsed.assignments, &strict);
+        assert!(parse(&fixed).unwrap().warnings.is_empty(), "{fixed:?}");
+
+        let codes = parsed
+            .warnings
+            .iter()
+            .map(|warning| warning.code.as_str().to_string())
+            .collect();
+
+        (fixed, codes)
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        assert_eq!(
+            assert_fixes("a = x * 2\nb = a + 1;\nx' = b"),
+            (
+                "a = x * 2;\nb = a + 1;\nx' = b;".into(),
+                vec!["W1".into(), "W1".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn wrong_argument_counts() {
+        assert_eq!(
+            assert_fixes("x' = min(x);"),
+            ("x' = min(x, 0);".into(), vec!["W2".into()])
+        );
+        assert_eq!(
+            assert_fixes("x' = min(x,1,2);"),
+            ("x' = min(x,1);".into(), vec!["W2".into()])
+        );
+        assert_eq!(
+            assert_fixes("x' = atan2( ) + lerp(1);"),
+            (
+                "x' = atan2(0, 0) + lerp(1, 0, 0);".into(),
+                vec!["W2".into(), "W2".into()]
+            )
+        );
+        assert_eq!(
+            assert_fixes("x' = rand(x) * 0 + sin(x, y, index);"),
+            (
+                "x' = rand() * 0 + sin(x);".into(),
+                vec!["W2".into(), "W2".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn unclosed_parenthesis() {
+        assert_eq!(
+            assert_fixes("x' = sin(time;\ny' = (x + 1;"),
+            (
+                "x' = sin(time);\ny' = (x + 1);".into(),
+                vec!["W3".into(), "W3".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn stray_parenthesis() {
+        assert_eq!(
+            assert_fixes("x' = x + 1);"),
+            ("x' = x + 1;".into(), vec!["W4".into()])
+        );
+    }
+
+    #[test]
+    fn edits_at_the_same_place() {
+        // the `)` goes before the `;`
+        assert_eq!(
+            assert_fixes("x' = sin(time\ny' = 1;"),
+            (
+                "x' = sin(time);\ny' = 1;".into(),
+                vec!["W3".into(), "W1".into()]
+            )
+        );
+        assert_eq!(
+            assert_fixes("x' = min(x\ny' = 1;"),
+            (
+                "x' = min(x, 0);\ny' = 1;".into(),
+                vec!["W2".into(), "W3".into(), "W1".into()]
+            )
+        );
+
+        // the `;` goes where the stray `)` was removed
+        assert_eq!(
+            assert_fixes("x' = x)\ny' = 2;"),
+            ("x' = x;\ny' = 2;".into(), vec!["W1".into(), "W4".into()])
+        );
+    }
+
+    #[test]
+    fn valid_expressions_are_left_alone() {
+        let source = "# comment\na = min(x, 1);\nx' = (a + 1) * 2;\n";
+
+        assert_eq!(assert_fixes(source), (source.into(), vec![]));
+    }
+
+    #[test]
+    fn errors_are_still_errors() {
+        assert!(parse("x' = 1 +;").is_err());
+        assert!(parse("x' = # comment\n 1;").is_err());
+    }
+}
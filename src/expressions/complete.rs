@@ -0,0 +1,236 @@
+//! Suggests the names that could finish the one being typed: inputs, outputs, builtin functions
+//! and the variables assigned to before it.
+
+use super::eval::builtin::Builtin;
+use super::eval::resolve::{INPUT_NAMES, OUTPUT_NAMES};
+use super::lexer::{lex, Token, TokenKind};
+use super::parser::Span;
+use super::reference;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CompletionKind {
+    Variable,
+    Input,
+    Output,
+    Function,
+}
+
+impl CompletionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompletionKind::Variable => "variable",
+            CompletionKind::Input => "input",
+            CompletionKind::Output => "output",
+            CompletionKind::Function => "function",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub kind: CompletionKind,
+    pub name: String,
+    /// What's shown next to the name: the signature of a function, or the kind of anything else.
+    pub detail: String,
+    /// The text that replaces the name being typed.
+    pub insert: String,
+    /// The characters of `insert` to select after inserting it, such as the first argument of a
+    /// call. Empty if the cursor should just be placed there.
+    pub selection: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completions {
+    /// The characters the completions replace: the whole name the cursor is in, or an empty span
+    /// at the cursor if it isn't in one.
+    pub span: Span,
+    /// The best match first.
+    pub items: Vec<Completion>,
+}
+
+/// How well `name` matches what's been typed of it, lower being better: names starting with it
+/// come first, then names starting with it in a different case, then names containing its
+/// characters in order. Within each, the names with fewer characters left over (or between the
+/// ones that match) come first.
+fn rank(typed: &str, name: &str) -> Option<(u8, usize)> {
+    let left_over = name.chars().count() - typed.chars().count().min(name.chars().count());
+
+    if name.starts_with(typed) {
+        return Some((0, left_over));
+    }
+
+    let typed = typed.to_lowercase();
+    let name = name.to_lowercase();
+
+    if name.starts_with(&typed) {
+        return Some((1, left_over));
+    }
+
+    // the characters of `typed` have to appear in order, and the further apart they are the
+    // worse the match
+    let mut gaps = 0;
+    let mut chars = name.chars().enumerate();
+    let mut last = None;
+
+    for wanted in typed.chars() {
+        let (position, _) = chars.find(|(_, c)| *c == wanted)?;
+        gaps += last.map_or(position, |last| position - last - 1);
+        last = Some(position);
+    }
+
+    Some((2, gaps + left_over))
+}
+
+/// The variables assigned to by the statements that end before `offset`, in the order they're
+/// first assigned to. The one the offset is in doesn't count, since a variable can't be used in
+/// its own first assignment.
+fn assigned_before(tokens: &[Token], offset: usize) -> Vec<String> {
+    let end = tokens
+        .iter()
+        .rev()
+        .find(|token| token.kind == TokenKind::Semicolon && token.span.end <= offset)
+        .map_or(0, |token| token.span.end);
+
+    let meaningful: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| token.span.end <= end && !token.kind.is_trivia())
+        .collect();
+
+    let mut names: Vec<String> = vec![];
+
+    for pair in meaningful.windows(2) {
+        let name = &pair[0].text;
+        let builtin = INPUT_NAMES.contains(&name.as_str())
+            || OUTPUT_NAMES.contains(&name.as_str())
+            || Builtin::from_name(name).is_some();
+
+        if pair[0].kind == TokenKind::Identifier
+            && pair[1].kind == TokenKind::Equals
+            && !builtin
+            && !names.contains(name)
+        {
+            names.push(name.clone());
+        }
+    }
+
+    names
+}
+
+fn function_completion(name: &str, arity: usize, call: bool) -> Completion {
+    let mut parameters = reference::section(name)
+        .map(|section| section.parameters())
+        .unwrap_or_default();
+
+    // the reference should always match, but the call still needs the right number of arguments
+    if parameters.len() != arity {
+        parameters = ["a", "b", "c", "d"]
+            .into_iter()
+            .cycle()
+            .take(arity)
+            .collect();
+    }
+
+    let signature = format!("{name}({})", parameters.join(", "));
+    let name_len = name.chars().count();
+
+    let (insert, selection) = if !call {
+        (name.to_string(), name_len..name_len)
+    } else if let Some(first) = parameters.first() {
+        (
+            signature.clone(),
+            name_len + 1..name_len + 1 + first.chars().count(),
+        )
+    } else {
+        let len = signature.chars().count();
+        (signature.clone(), len..len)
+    };
+
+    Completion {
+        kind: CompletionKind::Function,
+        name: name.to_string(),
+        detail: signature,
+        insert,
+        selection,
+    }
+}
+
+/// The completions for the name at a character offset in the text, or `None` if nothing can be
+/// typed there (in a comment or a number, for example).
+pub fn complete(text: &str, offset: usize) -> Option<Completions> {
+    let tokens = lex(text);
+
+    // the token the cursor is at the end or in the middle of
+    let current = tokens
+        .iter()
+        .find(|token| token.span.start < offset && offset <= token.span.end);
+
+    let span = match current {
+        Some(token) if token.kind == TokenKind::Identifier => token.span.clone(),
+        Some(token) if token.kind == TokenKind::Comment || token.kind == TokenKind::Number => {
+            return None
+        }
+        _ => offset..offset,
+    };
+
+    let typed: String = text
+        .chars()
+        .skip(span.start)
+        .take(offset - span.start)
+        .collect();
+
+    // a call that already has its parentheses only needs the name
+    let call = text.chars().nth(span.end) != Some('(');
+
+    let mut candidates: Vec<Completion> = vec![];
+
+    for name in assigned_before(&tokens, span.start) {
+        candidates.push(Completion {
+            kind: CompletionKind::Variable,
+            detail: CompletionKind::Variable.name().to_string(),
+            insert: name.clone(),
+            selection: name.chars().count()..name.chars().count(),
+            name,
+        });
+    }
+
+    for (names, kind) in [
+        (&INPUT_NAMES[..], CompletionKind::Input),
+        (&OUTPUT_NAMES[..], CompletionKind::Output),
+    ] {
+        for name in names {
+            candidates.push(Completion {
+                kind,
+                name: name.to_string(),
+                detail: kind.name().to_string(),
+                insert: name.to_string(),
+                selection: name.chars().count()..name.chars().count(),
+            });
+        }
+    }
+
+    for builtin in Builtin::ALL {
+        candidates.push(function_completion(builtin.name(), builtin.arity(), call));
+    }
+
+    let mut ranked: Vec<((u8, usize), Completion)> = candidates
+        .into_iter()
+        // a name that's already been typed out has nothing left to complete, unless it's a call
+        // that still needs its parentheses
+        .filter(|candidate| {
+            candidate.name != typed || (candidate.kind == CompletionKind::Function && call)
+        })
+        .filter_map(|candidate| Some((rank(&typed, &candidate.name)?, candidate)))
+        .collect();
+
+    ranked.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank
+            .cmp(b_rank)
+            .then(a.kind.cmp(&b.kind))
+            .then(a.name.cmp(&b.name))
+    });
+
+    Some(Completions {
+        span,
+        items: ranked.into_iter().map(|(_, candidate)| candidate).collect(),
+    })
+}
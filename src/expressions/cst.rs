@@ -0,0 +1,848 @@
+use super::errors::{Diagnostic, ErrorCode};
+use super::lexer::{lex, Token, TokenKind};
+use super::parser::{Assignment, BinaryOperation, Expr, Span, Spanned, UnaryOperation};
+use std::fmt;
+use std::sync::Arc;
+
+/// A token, along with the whitespace and comments directly before it.
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    pub trivia: Vec<Token>,
+    pub token: Token,
+}
+
+/// An expression, with every token it was written with.
+#[derive(Debug, Clone)]
+pub enum CstExpr {
+    Number(SyntaxToken),
+    Variable(SyntaxToken),
+    Group {
+        open: SyntaxToken,
+        inner: Box<CstExpr>,
+        /// Only missing in text parsed with [`parse_lenient`].
+        close: Option<SyntaxToken>,
+    },
+    Binary {
+        left: Box<CstExpr>,
+        op: SyntaxToken,
+        right: Box<CstExpr>,
+    },
+    /// A number directly followed by a variable, call or group, like `2sin(x)`, which multiplies
+    /// them without an operator in between.
+    ShortMultiply {
+        number: Box<CstExpr>,
+        right: Box<CstExpr>,
+    },
+    Unary {
+        op: SyntaxToken,
+        operand: Box<CstExpr>,
+    },
+    Call {
+        name: SyntaxToken,
+        open: SyntaxToken,
+        /// Every argument, and the comma after it if there is one.
+        args: Vec<(CstExpr, Option<SyntaxToken>)>,
+        /// Only missing in text parsed with [`parse_lenient`].
+        close: Option<SyntaxToken>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CstAssignment {
+    pub name: SyntaxToken,
+    pub equals: SyntaxToken,
+    pub expression: CstExpr,
+    /// Unmatched `)` after the expression, which [`parse_lenient`] ignores. Always empty in text
+    /// parsed with [`parse`].
+    pub stray: Vec<SyntaxToken>,
+    /// Only missing in text parsed with [`parse_lenient`].
+    pub semicolon: Option<SyntaxToken>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CstItem {
+    Assignment(CstAssignment),
+    /// Tokens that don't make up an assignment, up to and including the next `;`.
+    Error {
+        tokens: Vec<SyntaxToken>,
+        /// The index of the first token the parser couldn't make sense of, or the number of
+        /// tokens if it ran out of text.
+        unexpected: usize,
+    },
+}
+
+/// A lossless syntax tree of an expression's source text: printing it gives back the exact text
+/// it was parsed from, comments, whitespace and mistakes included.
+#[derive(Debug, Clone)]
+pub struct Cst {
+    pub items: Vec<CstItem>,
+    /// The whitespace and comments after the last item.
+    pub trailing: Vec<Token>,
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in self.trivia.iter() {
+            f.write_str(&trivia.text)?;
+        }
+
+        f.write_str(&self.token.text)
+    }
+}
+
+impl fmt::Display for CstExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CstExpr::Number(token) | CstExpr::Variable(token) => write!(f, "{token}"),
+            CstExpr::Group { open, inner, close } => {
+                write!(f, "{open}{inner}")?;
+
+                if let Some(close) = close {
+                    write!(f, "{close}")?;
+                }
+
+                Ok(())
+            }
+            CstExpr::Binary { left, op, right } => write!(f, "{left}{op}{right}"),
+            CstExpr::ShortMultiply { number, right } => write!(f, "{number}{right}"),
+            CstExpr::Unary { op, operand } => write!(f, "{op}{operand}"),
+            CstExpr::Call {
+                name,
+                open,
+                args,
+                close,
+            } => {
+                write!(f, "{name}{open}")?;
+
+                for (arg, comma) in args.iter() {
+                    write!(f, "{arg}")?;
+
+                    if let Some(comma) = comma {
+                        write!(f, "{comma}")?;
+                    }
+                }
+
+                if let Some(close) = close {
+                    write!(f, "{close}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for CstAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.name, self.equals, self.expression)?;
+
+        for token in self.stray.iter() {
+            write!(f, "{token}")?;
+        }
+
+        if let Some(semicolon) = &self.semicolon {
+            write!(f, "{semicolon}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in self.items.iter() {
+            match item {
+                CstItem::Assignment(assignment) => write!(f, "{assignment}")?,
+                CstItem::Error { tokens, .. } => {
+                    for token in tokens.iter() {
+                        write!(f, "{token}")?;
+                    }
+                }
+            }
+        }
+
+        for trivia in self.trailing.iter() {
+            f.write_str(&trivia.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn binary_operation(kind: TokenKind) -> Option<BinaryOperation> {
+    let op = match kind {
+        TokenKind::Plus => BinaryOperation::Add,
+        TokenKind::Minus => BinaryOperation::Subtract,
+        TokenKind::Star => BinaryOperation::Multiply,
+        TokenKind::Slash => BinaryOperation::Divide,
+        TokenKind::Percent => BinaryOperation::Modulo,
+        TokenKind::Caret => BinaryOperation::Exponent,
+        TokenKind::Less => BinaryOperation::LessThan,
+        TokenKind::Greater => BinaryOperation::GreaterThan,
+        TokenKind::LessEqual => BinaryOperation::LessThanOrEqual,
+        TokenKind::GreaterEqual => BinaryOperation::GreaterThanOrEqual,
+        TokenKind::EqualEqual => BinaryOperation::Equal,
+        TokenKind::Ampersand => BinaryOperation::And,
+        TokenKind::Pipe => BinaryOperation::Or,
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+impl CstExpr {
+    fn first_token(&self) -> &Token {
+        match self {
+            CstExpr::Number(token) | CstExpr::Variable(token) => &token.token,
+            CstExpr::Group { open, .. } => &open.token,
+            CstExpr::Binary { left, .. } => left.first_token(),
+            CstExpr::ShortMultiply { number, .. } => number.first_token(),
+            CstExpr::Unary { op, .. } => &op.token,
+            CstExpr::Call { name, .. } => &name.token,
+        }
+    }
+
+    fn last_token(&self) -> &Token {
+        match self {
+            CstExpr::Number(token) | CstExpr::Variable(token) => &token.token,
+            CstExpr::Group {
+                close: Some(close), ..
+            }
+            | CstExpr::Call {
+                close: Some(close), ..
+            } => &close.token,
+            CstExpr::Group { inner, .. } => inner.last_token(),
+            // a call can only be left open after an argument
+            CstExpr::Call { args, open, .. } => match args.last() {
+                Some((arg, _)) => arg.last_token(),
+                None => &open.token,
+            },
+            CstExpr::Binary { right, .. } | CstExpr::ShortMultiply { right, .. } => {
+                right.last_token()
+            }
+            CstExpr::Unary { operand, .. } => operand.last_token(),
+        }
+    }
+
+    /// The characters the expression covers, without the trivia before it.
+    pub fn span(&self) -> Span {
+        self.first_token().span.start..self.last_token().span.end
+    }
+
+    /// Converts the expression into the parser's syntax tree.
+    pub fn to_expr(&self) -> Spanned<Arc<Expr>> {
+        let expr = match self {
+            CstExpr::Number(token) => Expr::Number(token.token.text.parse().unwrap()),
+            CstExpr::Variable(token) => Expr::Variable(token.token.text.clone()),
+            CstExpr::Group { inner, .. } => Expr::Group(inner.to_expr()),
+            CstExpr::Binary { left, op, right } => Expr::BinaryExpression(
+                left.to_expr(),
+                binary_operation(op.token.kind).expect("binary operators are checked when parsing"),
+                right.to_expr(),
+            ),
+            CstExpr::ShortMultiply { number, right } => {
+                Expr::BinaryExpression(number.to_expr(), BinaryOperation::Multiply, right.to_expr())
+            }
+            CstExpr::Unary { op, operand } => Expr::UnaryExpression(
+                match op.token.kind {
+                    TokenKind::Bang => UnaryOperation::Not,
+                    _ => UnaryOperation::Negate,
+                },
+                operand.to_expr(),
+            ),
+            CstExpr::Call { name, args, .. } => Expr::Call(
+                name.token.text.clone(),
+                args.iter().map(|(arg, _)| arg.to_expr()).collect(),
+            ),
+        };
+
+        (Arc::new(expr), self.span())
+    }
+}
+
+impl CstAssignment {
+    /// The characters the assignment covers, from its name to its `;` (or the end of its
+    /// expression, if the `;` is missing).
+    pub fn span(&self) -> Span {
+        let last = match (&self.semicolon, self.stray.last()) {
+            (Some(semicolon), _) => &semicolon.token,
+            (None, Some(stray)) => &stray.token,
+            (None, None) => self.expression.last_token(),
+        };
+
+        self.name.token.span.start..last.span.end
+    }
+
+    pub fn to_assignment(&self) -> Assignment {
+        Assignment {
+            name: self.name.token.text.clone(),
+            expression: self.expression.to_expr(),
+            span: self.span(),
+        }
+    }
+}
+
+impl CstItem {
+    /// The characters the item covers, without the trivia before it.
+    pub fn span(&self) -> Span {
+        match self {
+            CstItem::Assignment(assignment) => assignment.span(),
+            CstItem::Error { tokens, .. } => match (tokens.first(), tokens.last()) {
+                (Some(first), Some(last)) => first.token.span.start..last.token.span.end,
+                _ => 0..0,
+            },
+        }
+    }
+
+    /// The parser error for an item that didn't parse, pointing at the first token that
+    /// couldn't be used, or at the end of `text` if it ran out.
+    pub fn error(&self, text: &str) -> Option<Diagnostic> {
+        let CstItem::Error { tokens, unexpected } = self else {
+            return None;
+        };
+
+        let error = match tokens.get(*unexpected) {
+            Some(token) => Diagnostic::new(
+                ErrorCode::P1,
+                format!(
+                    "Unexpected character '{}'.",
+                    token.token.text.chars().next().unwrap_or('_')
+                ),
+                token.token.span.clone(),
+            )
+            .with_label("unexpected character"),
+            None => {
+                let end = text.chars().count();
+
+                Diagnostic::new(ErrorCode::P2, "Unexpected end of file.", end..end)
+                    .with_label("the expression ends here")
+            }
+        };
+
+        Some(error)
+    }
+}
+
+impl Cst {
+    /// Converts every assignment into the parser's syntax tree, leaving out the ones that didn't
+    /// parse.
+    pub fn assignments(&self) -> Vec<Assignment> {
+        self.items
+            .iter()
+            .filter_map(|item| match item {
+                CstItem::Assignment(assignment) => Some(assignment.to_assignment()),
+                CstItem::Error { .. } => None,
+            })
+            .collect()
+    }
+
+    /// The characters covered by each part of the text that didn't parse.
+    pub fn errors(&self) -> Vec<Span> {
+        self.items
+            .iter()
+            .filter(|item| matches!(item, CstItem::Error { .. }))
+            .map(CstItem::span)
+            .collect()
+    }
+}
+
+/// A recursive descent parser over the tokens that aren't trivia, following the same grammar as
+/// [`super::parser::parser`].
+struct CstParser {
+    tokens: Vec<SyntaxToken>,
+    position: usize,
+    /// Whether to accept the mistakes Tower Unite lets through, see [`parse_lenient`].
+    lenient: bool,
+}
+
+impl CstParser {
+    fn peek_nth(&self, n: usize) -> Option<TokenKind> {
+        self.tokens
+            .get(self.position + n)
+            .map(|token| token.token.kind)
+    }
+
+    fn peek(&self) -> Option<TokenKind> {
+        self.peek_nth(0)
+    }
+
+    fn bump(&mut self) -> SyntaxToken {
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Option<SyntaxToken> {
+        if self.peek() == Some(kind) {
+            Some(self.bump())
+        } else {
+            None
+        }
+    }
+
+    /// Whether the `n`th token from here starts another assignment, like `b =`.
+    fn starts_assignment(&self, n: usize) -> bool {
+        self.peek_nth(n) == Some(TokenKind::Identifier)
+            && self.peek_nth(n + 1) == Some(TokenKind::Equals)
+    }
+
+    fn assignment(&mut self) -> Option<CstAssignment> {
+        let name = self.expect(TokenKind::Identifier)?;
+        let equals = self.expect(TokenKind::Equals)?;
+        let expression = self.expression()?;
+        let mut stray = vec![];
+
+        if self.lenient {
+            while self.peek() == Some(TokenKind::RightParen) {
+                stray.push(self.bump());
+            }
+        }
+
+        let semicolon = match self.expect(TokenKind::Semicolon) {
+            Some(semicolon) => Some(semicolon),
+            // without a `;`, the assignment ends where the next one starts
+            None if self.lenient && (self.peek().is_none() || self.starts_assignment(0)) => None,
+            None => return None,
+        };
+
+        Some(CstAssignment {
+            name,
+            equals,
+            expression,
+            stray,
+            semicolon,
+        })
+    }
+
+    /// Reads the `)` closing a group or call, which [`parse_lenient`] allows to be missing.
+    fn close(&mut self) -> Option<Option<SyntaxToken>> {
+        match self.expect(TokenKind::RightParen) {
+            Some(close) => Some(Some(close)),
+            None if self.lenient => Some(None),
+            None => None,
+        }
+    }
+
+    /// Parses one level of left associative binary operators.
+    fn binary(
+        &mut self,
+        operators: &[TokenKind],
+        operand: fn(&mut CstParser) -> Option<CstExpr>,
+    ) -> Option<CstExpr> {
+        let mut left = operand(self)?;
+
+        while let Some(kind) = self.peek() {
+            if !operators.contains(&kind) {
+                break;
+            }
+
+            let op = self.bump();
+            let right = operand(self)?;
+
+            left = CstExpr::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            };
+        }
+
+        Some(left)
+    }
+
+    fn expression(&mut self) -> Option<CstExpr> {
+        self.binary(&[TokenKind::Ampersand, TokenKind::Pipe], |parser| {
+            parser.comparison()
+        })
+    }
+
+    fn comparison(&mut self) -> Option<CstExpr> {
+        self.binary(
+            &[
+                TokenKind::LessEqual,
+                TokenKind::GreaterEqual,
+                TokenKind::EqualEqual,
+                TokenKind::Less,
+                TokenKind::Greater,
+            ],
+            |parser| parser.additive(),
+        )
+    }
+
+    fn additive(&mut self) -> Option<CstExpr> {
+        self.binary(&[TokenKind::Plus, TokenKind::Minus], |parser| {
+            parser.multiplicative()
+        })
+    }
+
+    fn multiplicative(&mut self) -> Option<CstExpr> {
+        self.binary(
+            &[TokenKind::Star, TokenKind::Slash, TokenKind::Percent],
+            |parser| parser.exponent(),
+        )
+    }
+
+    fn exponent(&mut self) -> Option<CstExpr> {
+        self.binary(&[TokenKind::Caret], |parser| parser.unary())
+    }
+
+    fn unary(&mut self) -> Option<CstExpr> {
+        match self.peek()? {
+            TokenKind::Minus | TokenKind::Bang => {
+                let op = self.bump();
+                let operand = self.unary()?;
+
+                Some(CstExpr::Unary {
+                    op,
+                    operand: Box::new(operand),
+                })
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Option<CstExpr> {
+        match self.peek()? {
+            TokenKind::Number => {
+                let number = CstExpr::Number(self.bump());
+
+                match self.peek() {
+                    // in lenient mode, `a = 2 b = 3` is two assignments rather than `2b`
+                    Some(TokenKind::Identifier) if self.lenient && self.starts_assignment(0) => {
+                        Some(number)
+                    }
+                    Some(TokenKind::Identifier) | Some(TokenKind::LeftParen) => {
+                        Some(CstExpr::ShortMultiply {
+                            number: Box::new(number),
+                            right: Box::new(self.short_multiply_exponent()?),
+                        })
+                    }
+                    _ => Some(number),
+                }
+            }
+            TokenKind::Identifier | TokenKind::LeftParen => self.short_multiply_operand(),
+            _ => None,
+        }
+    }
+
+    /// A call, group or variable: the things a number can be directly followed by.
+    fn short_multiply_operand(&mut self) -> Option<CstExpr> {
+        match self.peek()? {
+            TokenKind::Identifier if self.peek_nth(1) == Some(TokenKind::LeftParen) => self.call(),
+            TokenKind::Identifier => Some(CstExpr::Variable(self.bump())),
+            TokenKind::LeftParen => Some(CstExpr::Group {
+                open: self.bump(),
+                inner: Box::new(self.expression()?),
+                close: self.close()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The right hand side of shorthand multiplication, which takes any exponents with it: `2x^2`
+    /// is `2 * (x ^ 2)`. The exponents can only be negated calls, groups, variables and numbers.
+    fn short_multiply_exponent(&mut self) -> Option<CstExpr> {
+        let mut left = self.short_multiply_operand()?;
+
+        while self.peek() == Some(TokenKind::Caret) {
+            let negations = (1..)
+                .take_while(|&n| self.peek_nth(n) == Some(TokenKind::Minus))
+                .count();
+
+            match self.peek_nth(negations + 1) {
+                Some(TokenKind::Identifier)
+                | Some(TokenKind::LeftParen)
+                | Some(TokenKind::Number) => {}
+                // anything else is left for the regular exponent rule
+                _ => break,
+            }
+
+            let op = self.bump();
+            let right = self.short_multiply_negation()?;
+
+            left = CstExpr::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            };
+        }
+
+        Some(left)
+    }
+
+    fn short_multiply_negation(&mut self) -> Option<CstExpr> {
+        match self.peek()? {
+            TokenKind::Minus => {
+                let op = self.bump();
+                let operand = self.short_multiply_negation()?;
+
+                Some(CstExpr::Unary {
+                    op,
+                    operand: Box::new(operand),
+                })
+            }
+            TokenKind::Number => Some(CstExpr::Number(self.bump())),
+            _ => self.short_multiply_operand(),
+        }
+    }
+
+    fn call(&mut self) -> Option<CstExpr> {
+        let name = self.bump();
+        let open = self.expect(TokenKind::LeftParen)?;
+        let mut args = vec![];
+
+        if let Some(close) = self.expect(TokenKind::RightParen) {
+            return Some(CstExpr::Call {
+                name,
+                open,
+                args,
+                close: Some(close),
+            });
+        }
+
+        loop {
+            let arg = self.expression()?;
+
+            match self.comma_or_close()? {
+                Ok(comma) => args.push((arg, Some(comma))),
+                Err(close) => {
+                    args.push((arg, None));
+
+                    return Some(CstExpr::Call {
+                        name,
+                        open,
+                        args,
+                        close,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Reads the `,` between two arguments, or the `)` after the last one.
+    fn comma_or_close(&mut self) -> Option<Result<SyntaxToken, Option<SyntaxToken>>> {
+        match self.peek() {
+            Some(TokenKind::Comma) => Some(Ok(self.bump())),
+            _ => self.close().map(Err),
+        }
+    }
+}
+
+/// Parses source text into a lossless syntax tree. This never fails: anything that isn't a valid
+/// assignment becomes a [`CstItem::Error`], up to the next `;`.
+///
+/// For text that [`super::parser::parse`] accepts, [`Cst::assignments`] gives the same syntax
+/// tree, apart from spans, which never include the whitespace around a node.
+pub fn parse(text: &str) -> Cst {
+    parse_with(text, false)
+}
+
+/// Parses source text the way Tower Unite does, which lets some mistakes through:
+///
+/// - an assignment without a `;` ends where the next assignment (or the text) starts,
+/// - a `(` that is never closed is closed at the end of the expression,
+/// - a `)` without a matching `(` at the end of an expression is ignored.
+///
+/// The missing tokens are left out of the tree, and stray `)` are kept in
+/// [`CstAssignment::stray`], so the tree still prints back to the original text. Text that
+/// [`parse`] accepts gives the same tree in both.
+pub fn parse_lenient(text: &str) -> Cst {
+    parse_with(text, true)
+}
+
+fn parse_with(text: &str, lenient: bool) -> Cst {
+    let mut tokens = vec![];
+    let mut trivia = vec![];
+
+    for token in lex(text) {
+        if token.kind.is_trivia() {
+            trivia.push(token);
+        } else {
+            tokens.push(SyntaxToken {
+                trivia: std::mem::take(&mut trivia),
+                token,
+            });
+        }
+    }
+
+    let mut parser = CstParser {
+        tokens,
+        position: 0,
+        lenient,
+    };
+    let mut items = vec![];
+
+    while parser.position < parser.tokens.len() {
+        let start = parser.position;
+
+        match parser.assignment() {
+            Some(assignment) => items.push(CstItem::Assignment(assignment)),
+            None => {
+                // the parser stops at the first token it can't use, without consuming it
+                let unexpected = parser.position - start;
+
+                // skip to the end of the broken assignment, and try again after it
+                parser.position = start;
+
+                let mut skipped = vec![parser.bump()];
+
+                while skipped.last().unwrap().token.kind != TokenKind::Semicolon
+                    && parser.position < parser.tokens.len()
+                {
+                    skipped.push(parser.bump());
+                }
+
+                items.push(CstItem::Error {
+                    tokens: skipped,
+                    unexpected,
+                });
+            }
+        }
+    }
+
+    Cst {
+        items,
+        trailing: trivia,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expressions::parser;
+
+    /// Checks that both parsers print the text back exactly as it was.
+    fn assert_round_trip(text: &str) {
+        assert_eq!(parse(text).to_string(), text);
+        assert_eq!(parse_lenient(text).to_string(), text);
+    }
+
+    /// Rebuilds an expression with every span emptied, so that trees can be compared by their
+    /// shape alone.
+    fn without_spans(expr: &Spanned<Arc<Expr>>) -> Spanned<Arc<Expr>> {
+        let stripped = match &*expr.0 {
+            Expr::Group(inner) => Expr::Group(without_spans(inner)),
+            Expr::BinaryExpression(left, op, right) => {
+                Expr::BinaryExpression(without_spans(left), op.clone(), without_spans(right))
+            }
+            Expr::UnaryExpression(op, operand) => {
+                Expr::UnaryExpression(op.clone(), without_spans(operand))
+            }
+            Expr::Call(name, args) => {
+                Expr::Call(name.clone(), args.iter().map(without_spans).collect())
+            }
+            other => other.clone(),
+        };
+
+        (Arc::new(stripped), 0..0)
+    }
+
+    /// Checks that the tree gives the same assignments as the parser, for text it accepts.
+    fn assert_same_assignments(text: &str) {
+        let shapes = |assignments: &[Assignment]| -> Vec<(String, Spanned<Arc<Expr>>)> {
+            assignments
+                .iter()
+                .map(|assignment| {
+                    (
+                        assignment.name.clone(),
+                        without_spans(&assignment.expression),
+                    )
+                })
+                .collect()
+        };
+
+        let expected = parser::parse(text).expect("the text should parse");
+        let tree = parse(text);
+
+        assert!(tree.errors().is_empty());
+        assert_eq!(shapes(&tree.assignments()), shapes(&expected));
+    }
+
+    #[test]
+    fn round_trips_crlf() {
+        let text = "# the middle\r\na = 1;\r\n\r\nx' = a * sin(x)   ;\r\n";
+
+        assert_round_trip(text);
+        assert_same_assignments(text);
+    }
+
+    #[test]
+    fn round_trips_non_ascii_comments() {
+        let text = "# größe ≥ 2 — 半径 🎉\na = 2; # ünïcödé\nx' = a;";
+
+        assert_round_trip(text);
+        assert_same_assignments(text);
+    }
+
+    #[test]
+    fn round_trips_stray_parenthesis() {
+        let text = "a = (1 + 2));\nx' = a;";
+
+        assert_round_trip(text);
+        assert!(!parse(text).errors().is_empty());
+
+        let lenient = parse_lenient(text);
+        let CstItem::Assignment(assignment) = &lenient.items[0] else {
+            panic!("the stray ')' should be part of the assignment");
+        };
+
+        assert_eq!(assignment.stray.len(), 1);
+        assert!(lenient.errors().is_empty());
+    }
+
+    #[test]
+    fn round_trips_unclosed_parenthesis() {
+        let text = "a = min(1, (2 + 3";
+
+        assert_round_trip(text);
+        assert!(!parse(text).errors().is_empty());
+        assert!(parse_lenient(text).errors().is_empty());
+    }
+
+    #[test]
+    fn round_trips_error_items() {
+        let text = "a = 1 +;\nb = = 2;\n  @ $ ;\nx' = a;\n# after";
+        let tree = parse(text);
+
+        assert_round_trip(text);
+        assert_eq!(tree.errors().len(), 3);
+        // the assignment after the errors is still there
+        assert_eq!(tree.assignments().len(), 1);
+    }
+
+    #[test]
+    fn round_trips_empty_and_trivia_only() {
+        assert_round_trip("");
+        assert_round_trip("   \n\t");
+        assert_round_trip("# only a comment");
+    }
+
+    #[test]
+    fn assignments_match_parser() {
+        assert_same_assignments(
+            "a = -2x + 3(y - 1) ^ 2 % 4;\n\
+             b = if(a > 0 & !(a == 1) | a <= -1, a / 2, .5);\n\
+             x' = lerp(fraction, min(a, b), max(a, b));\n\
+             y' = 2sin(time) * ((a));",
+        );
+    }
+
+    #[test]
+    fn lenient_never_changes_strict_results() {
+        let texts = [
+            "a = -2x + 3(y - 1) ^ 2 % 4;\nx' = a;",
+            "a = 2\nb;\nb = 2 b == 3;\ny' = a (b);",
+            "# the middle\r\na = 1;\r\n\r\nx' = a * sin(x)   ;\r\n",
+            "a = if(x > 0 & !(x == 1) | x <= -1, x / 2, .5);\nh = lerp(a, min(a, 1), max(a, 0));",
+            "a = (((1)));\nb = min(a, (2));",
+        ];
+
+        for text in texts {
+            let strict = parse(text);
+            let lenient = parse_lenient(text);
+
+            assert!(strict.errors().is_empty(), "{text:?}");
+            assert!(lenient.errors().is_empty(), "{text:?}");
+            assert_eq!(lenient.assignments(), strict.assignments(), "{text:?}");
+        }
+    }
+}
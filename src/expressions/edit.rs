@@ -0,0 +1,36 @@
+//! Changes to source text, such as the ones that fix a diagnostic.
+
+use super::parser::Span;
+
+/// A change to source text: the characters in `span` are replaced with `replacement`. Insertions
+/// have an empty span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// Applies edits to source text. Edits that start at the same place are applied in the order
+/// they're given, and edits that start inside text another edit already replaced are skipped.
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut sorted: Vec<&TextEdit> = edits.iter().collect();
+    let mut output = String::new();
+    let mut position = 0;
+
+    // the sort is stable, so edits at the same place keep their order
+    sorted.sort_by_key(|edit| edit.span.start);
+
+    for edit in sorted {
+        if edit.span.start < position {
+            continue;
+        }
+
+        output.extend(&chars[position..edit.span.start]);
+        output.push_str(&edit.replacement);
+        position = edit.span.end;
+    }
+
+    output.extend(&chars[position..]);
+    output
+}
@@ -1,42 +1,400 @@
+use super::edit::TextEdit;
+use super::eval::builtin::Builtin;
+use super::eval::resolve::OUTPUT_NAMES;
 use super::parser::Span;
+use super::suggest;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
-pub enum ErrorType {
-    ParseError,
-    EvaluationError,
+/// Identifies a kind of problem. Codes starting with P come from the parser, codes starting with
+/// E from checking or evaluating the expression, codes starting with W from Tower compatibility
+/// mode, and codes starting with I describe how an expression with problems was run anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// Unexpected character.
+    P1,
+    /// Unexpected end of file.
+    P2,
+    /// Unexpected character where a semi-colon was expected.
+    P3,
+    /// Unexpected character where a closing parenthesis was expected.
+    P4,
+    /// Unexpected parser error.
+    P255,
+    /// Function called with the wrong number of arguments.
+    E1,
+    /// Variable used before it's assigned to.
+    E2,
+    /// No such function.
+    E3,
+    /// Assignment to a reserved name.
+    E4,
+    /// Evaluator given invalid parser output.
+    E255,
+    /// Missing semi-colon, accepted by Tower compatibility mode.
+    W1,
+    /// Missing or extra function arguments, accepted by Tower compatibility mode.
+    W2,
+    /// Unclosed parenthesis, accepted by Tower compatibility mode.
+    W3,
+    /// Unmatched closing parenthesis, accepted by Tower compatibility mode.
+    W4,
+    /// Assignment skipped, because it couldn't be parsed or uses a variable that couldn't.
+    I1,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 15] = [
+        ErrorCode::P1,
+        ErrorCode::P2,
+        ErrorCode::P3,
+        ErrorCode::P4,
+        ErrorCode::P255,
+        ErrorCode::E1,
+        ErrorCode::E2,
+        ErrorCode::E3,
+        ErrorCode::E4,
+        ErrorCode::E255,
+        ErrorCode::W1,
+        ErrorCode::W2,
+        ErrorCode::W3,
+        ErrorCode::W4,
+        ErrorCode::I1,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::P1 => "P1",
+            ErrorCode::P2 => "P2",
+            ErrorCode::P3 => "P3",
+            ErrorCode::P4 => "P4",
+            ErrorCode::P255 => "P255",
+            ErrorCode::E1 => "E1",
+            ErrorCode::E2 => "E2",
+            ErrorCode::E3 => "E3",
+            ErrorCode::E4 => "E4",
+            ErrorCode::E255 => "E255",
+            ErrorCode::W1 => "W1",
+            ErrorCode::W2 => "W2",
+            ErrorCode::W3 => "W3",
+            ErrorCode::W4 => "W4",
+            ErrorCode::I1 => "I1",
+        }
+    }
+
+    /// How serious a problem with this code is, unless a diagnostic says otherwise.
+    pub fn severity(&self) -> Severity {
+        match self {
+            ErrorCode::W1 | ErrorCode::W2 | ErrorCode::W3 | ErrorCode::W4 => Severity::Warning,
+            ErrorCode::I1 => Severity::Info,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Reads a code like `E2` (or `e2`).
+impl FromStr for ErrorCode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ErrorCode, ()> {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|code| code.as_str().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+/// How serious a diagnostic is, from most to least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The expression can't run as written.
+    Error,
+    /// The expression runs, but probably not the way it was meant to.
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    /// The name shown in front of a diagnostic, like `error`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+            Severity::Hint => "hint",
+        }
+    }
+}
+
+/// A span of source text with a message describing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Edits to the source text that fix a diagnostic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fix {
+    /// What the fix does, like "Add a ';'".
+    pub message: String,
+    pub edits: Vec<TextEdit>,
+    /// Whether the fix is certain to be what was meant, so that it can be applied along with
+    /// every other safe fix without being looked at first.
+    pub safe: bool,
 }
 
+/// A problem found while parsing, checking or evaluating an expression.
+///
+/// Spans count characters, like the parser's. A [`super::source_map::SourceMap`] of the text
+/// turns them into line and column numbers for display.
 #[derive(Debug, Clone)]
-pub struct Error {
-    pub line_number: u64,
-    pub col_number: u64,
-    pub reason: String,
-    pub error_type: ErrorType,
-    pub id: u8, // we'll use this to show documentation later
-}
-
-pub fn get_position_from_span(span: Span, string: String) -> (u64, u64) {
-    // we don't need the whole span info for the error message,
-    // but we keep it the whole time for things like error highlighting
-    let mut remaining_chars: u64 = span
-        .start
-        .try_into()
-        .expect("text file is longer than 18446744073709551615 characters");
-    let mut line = 0;
-
-    for cur_line in string.lines() {
-        line += 1;
-
-        let len: u64 = cur_line.len().try_into().expect(
-            "if you encounter this error then something is *extremely* wrong with your computer",
-        );
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub severity: Severity,
+    /// A description of the problem, without the code.
+    pub message: String,
+    /// Where the problem is.
+    pub span: Span,
+    /// Shown next to `span`, if there's anything to say about it in particular.
+    pub label: Option<String>,
+    /// Other places that are part of the problem.
+    pub secondary: Vec<Label>,
+    /// Extra information about the problem.
+    pub notes: Vec<String>,
+    /// How to fix the problem.
+    pub help: Option<String>,
+    /// Edits that fix the problem, if it has an obvious fix.
+    pub fix: Option<Fix>,
+}
+
+fn plural(count: usize, word: &str) -> String {
+    match count {
+        1 => format!("{count} {word}"),
+        _ => format!("{count} {word}s"),
+    }
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic with the code's usual severity, and nothing but a message.
+    pub fn new(code: ErrorCode, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            code,
+            severity: code.severity(),
+            message: message.into(),
+            span,
+            label: None,
+            secondary: vec![],
+            notes: vec![],
+            help: None,
+            fix: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
 
-        if remaining_chars > len {
-            remaining_chars -= len + 1;
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Attaches a fix that's certain to be what was meant.
+    pub fn with_fix(mut self, message: impl Into<String>, edits: Vec<TextEdit>) -> Diagnostic {
+        self.fix = Some(Fix {
+            message: message.into(),
+            edits,
+            safe: true,
+        });
+        self
+    }
+
+    /// Attaches a fix that's only likely to be what was meant, which is only applied when asked
+    /// for in particular.
+    pub fn with_suggested_fix(
+        mut self,
+        message: impl Into<String>,
+        edits: Vec<TextEdit>,
+    ) -> Diagnostic {
+        self.fix = Some(Fix {
+            message: message.into(),
+            edits,
+            safe: false,
+        });
+        self
+    }
+
+    /// Suggests the candidate closest to `name` as the help, if any is close enough that it was
+    /// probably what was meant.
+    pub fn with_suggestion<'a>(
+        self,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'a str>,
+    ) -> Diagnostic {
+        match suggest::best_match(name, candidates) {
+            Some(suggestion) if suggestion.eq_ignore_ascii_case(name) => self
+                .with_note("Names are case sensitive.")
+                .with_help(format!("Did you mean '{suggestion}'?")),
+            Some(suggestion) => self.with_help(format!("Did you mean '{suggestion}'?")),
+            None => self,
+        }
+    }
+
+    /// E1, for a call to a function with the wrong number of arguments.
+    pub fn wrong_argument_count(
+        name: &str,
+        expected: usize,
+        actual: usize,
+        span: Span,
+    ) -> Diagnostic {
+        let expected_text = plural(expected, "argument");
+        let actual_text = plural(actual, "argument");
+        let got = if actual < expected {
+            "but only got"
         } else {
-            break;
+            "but got"
+        };
+
+        Diagnostic::new(
+            ErrorCode::E1,
+            format!("Function '{name}' expected {expected_text}, {got} {actual_text}."),
+            span,
+        )
+        .with_label(format!("called with {actual_text}"))
+        .with_note("Tower Unite uses 0 for missing arguments and ignores extra ones. Tower compatibility mode does the same.")
+        .with_help(format!("Call '{name}' with {expected_text}."))
+    }
+
+    /// E2, for a variable that hasn't been assigned to (yet).
+    pub fn unknown_variable(name: &str, span: Span) -> Diagnostic {
+        Diagnostic::new(
+            ErrorCode::E2,
+            format!("Cannot find variable '{name}'. Are you using it too early?"),
+            span,
+        )
+        .with_label("not assigned to yet")
+        .with_help(format!(
+            "Assign to '{name}' before it's used, or check its spelling."
+        ))
+    }
+
+    /// E3, for a call to a function that doesn't exist. This is what's reported at runtime; the
+    /// checker adds the list of functions and a suggestion with [`Diagnostic::with_functions`].
+    pub fn unknown_function(name: &str, span: Span) -> Diagnostic {
+        Diagnostic::new(ErrorCode::E3, format!("No such function '{name}'."), span)
+            .with_label("unknown function")
+    }
+
+    /// Notes the available functions, and suggests the one closest to `name`.
+    pub fn with_functions(self, name: &str) -> Diagnostic {
+        let names = Builtin::ALL
+            .iter()
+            .map(|builtin| builtin.name())
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        self.with_note(format!("The available functions are {names}."))
+            .with_suggestion(name, Builtin::ALL.iter().map(|builtin| builtin.name()))
+    }
+
+    /// E4, for an assignment to an input or function name.
+    pub fn reserved_name(name: &str, span: Span) -> Diagnostic {
+        let error = Diagnostic::new(
+            ErrorCode::E4,
+            format!("Variable '{name}' has the same name as reserved name '{name}'."),
+            span,
+        )
+        .with_label("reserved name")
+        .with_note(format!(
+            "'{name}' is an input or a function, so it can't be assigned to."
+        ));
+
+        // `x = ...` is usually meant to move the point, which is what the output `x'` is for
+        let output = format!("{name}'");
+
+        match OUTPUT_NAMES.contains(&output.as_str()) {
+            true => error.with_help(format!("Did you mean to assign to the output '{output}'?")),
+            false => error.with_help("Give the variable a different name."),
+        }
+    }
+
+    /// E255, for a part of the syntax tree the parser couldn't make sense of.
+    pub fn invalid_parser_output(span: Span) -> Diagnostic {
+        Diagnostic::new(
+            ErrorCode::E255,
+            "Evaluator given invalid parser output.",
+            span,
+        )
+        .with_note("This is a bug in Laser Studio; please report it.")
+    }
+
+    /// The code and message, like `E3: No such function 'foo'.`
+    pub fn reason(&self) -> String {
+        format!("{}: {}", self.code, self.message)
+    }
+
+    /// Moves every span of the diagnostic `offset` characters later, for one found in a piece of
+    /// a larger text.
+    pub fn offset_by(mut self, offset: usize) -> Diagnostic {
+        let shift = |span: &mut Span| *span = span.start + offset..span.end + offset;
+
+        shift(&mut self.span);
+
+        for label in self.secondary.iter_mut() {
+            shift(&mut label.span);
         }
+
+        if let Some(fix) = &mut self.fix {
+            for edit in fix.edits.iter_mut() {
+                shift(&mut edit.span);
+            }
+        }
+
+        self
+    }
+
+    /// Whether two diagnostics describe the same problem at the same place, such as the same
+    /// runtime error happening in different indexes.
+    pub fn is_same_as(&self, other: &Diagnostic) -> bool {
+        self.code == other.code && self.span == other.span && self.message == other.message
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    return (line, remaining_chars);
+    #[test]
+    fn wrong_argument_count_says_whether_too_few_or_too_many() {
+        assert_eq!(
+            Diagnostic::wrong_argument_count("atan2", 2, 1, 0..8).message,
+            "Function 'atan2' expected 2 arguments, but only got 1 argument."
+        );
+        assert_eq!(
+            Diagnostic::wrong_argument_count("sin", 1, 2, 0..9).message,
+            "Function 'sin' expected 1 argument, but got 2 arguments."
+        );
+    }
 }
@@ -1,23 +1,42 @@
-mod context;
-mod data;
+pub mod batch;
+pub mod builtin;
+pub mod bytecode;
+pub mod check;
+pub mod context;
+pub mod data;
+pub mod grid;
+pub mod interpreter;
+#[cfg(feature = "jit")]
+#[allow(unsafe_code)]
+pub mod jit;
+pub mod resolve;
+pub mod vm;
 
 use super::errors::*;
-use super::parser::Assignment;
 use super::parser::BinaryOperation;
-use super::parser::Expr;
 use super::parser::Span;
 use super::parser::Spanned;
 use super::parser::UnaryOperation;
-use ahash::AHashMap;
+use builtin::Builtin;
 use rand::Rng;
+use resolve::{Frame, Resolution, ResolvedExpr, INPUT_NAMES};
 use std::sync::Arc;
 
+/// The reason an assignment couldn't be evaluated. The diagnostic is boxed, since every step of
+/// evaluation returns one of these and most of them succeed.
 pub struct RawEvalError {
-    error: String,
-    span: Span,
-    id: u8,
+    diagnostic: Box<Diagnostic>,
 }
 
+impl From<Diagnostic> for RawEvalError {
+    fn from(diagnostic: Diagnostic) -> RawEvalError {
+        RawEvalError {
+            diagnostic: Box::new(diagnostic),
+        }
+    }
+}
+
+/// The values of the inputs (`x`, `y`, `index`, `time`, etc.) for a single index.
 #[derive(Clone, Copy)]
 pub struct EvalContext {
     pub x: f64,
@@ -32,327 +51,166 @@ pub struct EvalContext {
     pub projection_start_time: f64,
 }
 
-const RESERVED_VARIABLE_NAMES: [&str; 26] = [
-    "x",
-    "y",
-    "index",
-    "count",
-    "fraction",
-    "pi",
-    "tau",
-    "time",
-    "projectionTime",
-    "projectionStartTime",
-    "sin",
-    "cos",
-    "tan",
-    "asin",
-    "acos",
-    "atan",
-    "atan2",
-    "sqrt",
-    "min",
-    "max",
-    "floor",
-    "ceil",
-    "round",
-    "abs",
-    "if",
-    "lerp",
-];
+/// Whether `name` is an input or a function, which can't be assigned to.
+fn is_reserved(name: &str) -> bool {
+    // `rand` has never been reserved, so assigning to it keeps working
+    INPUT_NAMES.contains(&name)
+        || Builtin::from_name(name).is_some_and(|builtin| builtin != Builtin::Rand)
+}
 
 fn eval(
-    spanned_expr: &Spanned<Arc<Expr>>,
-    variables: &mut AHashMap<String, f64>,
-    ctx: EvalContext,
+    spanned_expr: &Spanned<Arc<ResolvedExpr>>,
+    resolution: &Resolution,
+    frame: &Frame,
 ) -> Result<f64, RawEvalError> {
     let expr = (&*spanned_expr.0).clone();
     let span = &spanned_expr.1;
 
     match expr {
-        Expr::Call(func, args) => {
-            match func.as_str() {
-                "if" => function_complex(
-                    "if",
-                    3,
-                    |args| {
-                        if eval(&args[0], variables, ctx)? >= 1.0 {
-                            eval(&args[1], variables, ctx)
-                        } else {
-                            eval(&args[2], variables, ctx)
-                        }
-                    },
-                    span,
-                    args,
-                ),
-                _ => {
-                    let arguments_results = args
-                        .iter()
-                        .map(|a| eval(&a, variables, ctx))
-                        .collect::<Result<Vec<f64>, RawEvalError>>();
+        ResolvedExpr::Call(func, args) => {
+            let builtin = Builtin::from_name(&func);
+
+            // the argument count of `if` is checked before any of the arguments are evaluated
+            if builtin == Some(Builtin::If) {
+                check_arity(Builtin::If, args.len(), span)?;
+
+                return if eval(&args[0], resolution, frame)? >= 1.0 {
+                    eval(&args[1], resolution, frame)
+                } else {
+                    eval(&args[2], resolution, frame)
+                };
+            }
+
+            let arguments = args
+                .iter()
+                .map(|a| eval(a, resolution, frame))
+                .collect::<Result<Vec<f64>, RawEvalError>>()?;
+
+            let builtin = match builtin {
+                Some(builtin) => builtin,
+                None => return Err(Diagnostic::unknown_function(&func, span.clone()).into()),
+            };
 
-                    let arguments = match arguments_results {
-                        Ok(args) => args,
-                        Err(error) => return Err(error),
-                    };
+            check_arity(builtin, arguments.len(), span)?;
 
-                    match func.as_str() {
-                        "sin" => function("sin", 1, |args| f64::sin(args[0]), span, arguments),
-                        "cos" => function("cos", 1, |args| f64::cos(args[0]), span, arguments),
-                        "tan" => function("tan", 1, |args| f64::tan(args[0]), span, arguments),
-                        "asin" => function("asin", 1, |args| f64::asin(args[0]), span, arguments),
-                        "acos" => function("acos", 1, |args| f64::acos(args[0]), span, arguments),
-                        "atan" => function("atan", 1, |args| f64::atan(args[0]), span, arguments),
-                        "atan2" => function(
-                            "atan2",
-                            2,
-                            |args| f64::atan2(args[0], args[1]),
-                            span,
-                            arguments,
-                        ),
-                        "sqrt" => function("sqrt", 1, |args| f64::sqrt(args[0]), span, arguments),
-                        "min" => {
-                            function("min", 2, |args| f64::min(args[0], args[1]), span, arguments)
-                        }
-                        "max" => {
-                            function("max", 2, |args| f64::max(args[0], args[1]), span, arguments)
-                        }
-                        "floor" => {
-                            function("floor", 1, |args| f64::floor(args[0]), span, arguments)
-                        }
-                        "ceil" => function("ceil", 1, |args| f64::ceil(args[0]), span, arguments),
-                        "round" => {
-                            function("round", 1, |args| f64::round(args[0]), span, arguments)
-                        }
-                        "abs" => function("abs", 1, |args| f64::abs(args[0]), span, arguments),
-                        "rand" => function(
-                            "rand",
-                            0,
-                            |_args| {
-                                let mut rng = rand::thread_rng();
+            match builtin {
+                Builtin::Rand => {
+                    let mut rng = rand::thread_rng();
 
-                                rng.gen::<f64>()
-                            },
-                            span,
-                            arguments,
-                        ),
-                        // "if" => function("if", 3, |args| if args[0] >= 1.0 {args[1]} else {args[2]}, span, arguments),
-                        // formula for this is from the steam guide
-                        "lerp" => function(
-                            "lerp",
-                            3,
-                            |args| (args[1] * (1.0 - args[0]) + args[2] * args[0]),
-                            span,
-                            arguments,
-                        ),
-                        _ => {
-                            return Err(RawEvalError {
-                                error: format!("E3: No such function '{func}'."),
-                                span: span.clone(),
-                                id: 3,
-                            })
-                        }
-                    }
+                    Ok(rng.gen::<f64>())
                 }
+                // `if` was handled above, and every other builtin only depends on its arguments
+                _ => Ok(builtin
+                    .apply(&arguments)
+                    .expect("the argument count was checked")),
             }
         }
-        Expr::Number(x) => Ok(x),
-        Expr::Group(x) => Ok(eval(&x, variables, ctx)?),
-        Expr::UnaryExpression(op, a) => match op {
-            UnaryOperation::Negate => Ok(-eval(&a, variables, ctx)?),
-            UnaryOperation::Not => Ok(if eval(&a, variables, ctx)? >= 1.0 {
+        ResolvedExpr::Number(x) => Ok(x),
+        ResolvedExpr::Group(x) => Ok(eval(&x, resolution, frame)?),
+        ResolvedExpr::UnaryExpression(op, a) => match op {
+            UnaryOperation::Negate => Ok(-eval(&a, resolution, frame)?),
+            UnaryOperation::Not => Ok(if eval(&a, resolution, frame)? >= 1.0 {
                 0.0
             } else {
                 1.0
             }),
         },
-        Expr::BinaryExpression(a, op, b) => {
+        ResolvedExpr::BinaryExpression(a, op, b) => {
             match op {
                 // Mathematical Operations
-                BinaryOperation::Add => Ok(eval(&a, variables, ctx)? + eval(&b, variables, ctx)?),
+                BinaryOperation::Add => {
+                    Ok(eval(&a, resolution, frame)? + eval(&b, resolution, frame)?)
+                }
                 BinaryOperation::Subtract => {
-                    Ok(eval(&a, variables, ctx)? - eval(&b, variables, ctx)?)
+                    Ok(eval(&a, resolution, frame)? - eval(&b, resolution, frame)?)
                 }
                 BinaryOperation::Multiply => {
-                    Ok(eval(&a, variables, ctx)? * eval(&b, variables, ctx)?)
+                    Ok(eval(&a, resolution, frame)? * eval(&b, resolution, frame)?)
                 }
                 BinaryOperation::Divide => {
-                    Ok(eval(&a, variables, ctx)? / eval(&b, variables, ctx)?)
+                    Ok(eval(&a, resolution, frame)? / eval(&b, resolution, frame)?)
                 }
                 BinaryOperation::Modulo => {
-                    Ok(eval(&a, variables, ctx)? % eval(&b, variables, ctx)?)
+                    Ok(eval(&a, resolution, frame)? % eval(&b, resolution, frame)?)
                 }
                 BinaryOperation::Exponent => Ok(f64::powf(
-                    eval(&a, variables, ctx)?,
-                    eval(&b, variables, ctx)?,
+                    eval(&a, resolution, frame)?,
+                    eval(&b, resolution, frame)?,
                 )),
 
                 // Logical Operations
                 BinaryOperation::LessThan => {
-                    Ok((eval(&a, variables, ctx)? < eval(&b, variables, ctx)?) as u64 as f64)
+                    Ok((eval(&a, resolution, frame)? < eval(&b, resolution, frame)?) as u64 as f64)
                 }
                 BinaryOperation::GreaterThan => {
-                    Ok((eval(&a, variables, ctx)? > eval(&b, variables, ctx)?) as u64 as f64)
-                }
-                BinaryOperation::LessThanOrEqual => {
-                    Ok((eval(&a, variables, ctx)? <= eval(&b, variables, ctx)?) as u64 as f64)
-                }
-                BinaryOperation::GreaterThanOrEqual => {
-                    Ok((eval(&a, variables, ctx)? >= eval(&b, variables, ctx)?) as u64 as f64)
+                    Ok((eval(&a, resolution, frame)? > eval(&b, resolution, frame)?) as u64 as f64)
                 }
-                BinaryOperation::Equal => {
-                    Ok((eval(&a, variables, ctx)? == eval(&b, variables, ctx)?) as u64 as f64)
-                }
-                BinaryOperation::And => Ok((eval(&a, variables, ctx)? >= 1.0
-                    && eval(&b, variables, ctx)? >= 1.0)
+                BinaryOperation::LessThanOrEqual => Ok((eval(&a, resolution, frame)?
+                    <= eval(&b, resolution, frame)?)
+                    as u64 as f64),
+                BinaryOperation::GreaterThanOrEqual => Ok((eval(&a, resolution, frame)?
+                    >= eval(&b, resolution, frame)?)
+                    as u64 as f64),
+                BinaryOperation::Equal => Ok((eval(&a, resolution, frame)?
+                    == eval(&b, resolution, frame)?)
+                    as u64 as f64),
+                BinaryOperation::And => Ok((eval(&a, resolution, frame)? >= 1.0
+                    && eval(&b, resolution, frame)? >= 1.0)
                     as u64 as f64),
-                BinaryOperation::Or => Ok((eval(&a, variables, ctx)? >= 1.0
-                    || eval(&b, variables, ctx)? >= 1.0)
+                BinaryOperation::Or => Ok((eval(&a, resolution, frame)? >= 1.0
+                    || eval(&b, resolution, frame)? >= 1.0)
                     as u64 as f64),
             }
         }
-        Expr::Variable(name) => {
-            let result = match name.as_str() {
-                "x" => Some(ctx.x),
-                "y" => Some(ctx.y),
-                "index" => Some(ctx.index),
-                "count" => Some(ctx.count),
-                "fraction" => Some(ctx.fraction),
-                "pi" => Some(ctx.pi),
-                "tau" => Some(ctx.tau),
-                "time" => Some(ctx.time),
-                "projectionTime" => Some(ctx.projection_time),
-                "projectionStartTime" => Some(ctx.projection_start_time),
-                _ => match variables.get(&name) {
-                    Some(value) => Some(*value),
-                    None => None,
-                },
-            };
-            match result {
-                Some(value) => Ok(value),
-                None => Err(RawEvalError {
-                    error: format!(
-                        "E2: Cannot find variable '{name}'. Are you using it too early?"
-                    ),
-                    span: span.clone(),
-                    id: 3,
-                }),
-            }
+        ResolvedExpr::Slot(slot) => match frame.get(slot) {
+            Some(value) => Ok(value),
+            None => Err(Diagnostic::unknown_variable(&resolution.names[slot], span.clone()).into()),
+        },
+        ResolvedExpr::Unassigned(name) => {
+            Err(Diagnostic::unknown_variable(&name, span.clone()).into())
         }
-        Expr::Error => Err(RawEvalError {
-            error: "E255: Evaluator given invalid parser output.".to_string(),
-            span: span.clone(),
-            id: 255,
-        }),
+        ResolvedExpr::Error => Err(Diagnostic::invalid_parser_output(span.clone()).into()),
     }
 }
 
-fn function(
-    name: &str,
-    exp_count: u8,
-    c: impl Fn(Vec<f64>) -> f64,
-    span: &Span,
-    arguments: Vec<f64>,
-) -> Result<f64, RawEvalError> {
-    if arguments.len() == exp_count as usize {
-        Ok(c(arguments))
+fn check_arity(builtin: Builtin, count: usize, span: &Span) -> Result<(), RawEvalError> {
+    if count == builtin.arity() {
+        Ok(())
     } else {
-        let actual_count = arguments.len();
-        let exp_count_text = if exp_count == 1 {
-            "argument"
-        } else {
-            "arguments"
-        };
-        let actual_count_text = if actual_count == 1 {
-            "argument"
-        } else {
-            "arguments"
-        };
-
-        Err(RawEvalError {
-            error: format!("E1: Function '{name}' expected {exp_count} {exp_count_text}, but only got {actual_count} {actual_count_text}."),
-            span: span.clone(),
-            id: 1
-        })
+        Err(
+            Diagnostic::wrong_argument_count(builtin.name(), builtin.arity(), count, span.clone())
+                .into(),
+        )
     }
 }
 
-fn function_complex<T>(
-    name: &str,
-    exp_count: u8,
-    mut c: impl FnMut(Vec<T>) -> Result<f64, RawEvalError>,
-    span: &Span,
-    arguments: Vec<T>,
-) -> Result<f64, RawEvalError> {
-    if arguments.len() == exp_count as usize {
-        c(arguments)
-    } else {
-        let actual_count = arguments.len();
-        let exp_count_text = if exp_count == 1 {
-            "argument"
-        } else {
-            "arguments"
-        };
-        let actual_count_text = if actual_count == 1 {
-            "argument"
-        } else {
-            "arguments"
-        };
+/// Runs every assignment in order for a single index, storing the results in `frame`.
+///
+/// Evaluation errors don't stop execution; the assignment that caused one is skipped, and the
+/// error is returned.
+pub fn run(resolution: &Resolution, frame: &mut Frame, ctx: EvalContext) -> Vec<Diagnostic> {
+    let mut errors: Vec<Diagnostic> = vec![];
 
-        Err(RawEvalError {
-            error: format!("E1: Function '{name}' expected {exp_count} {exp_count_text}, but only got {actual_count} {actual_count_text}."),
-            span: span.clone(),
-            id: 1
-        })
-    }
-}
-
-pub fn run(
-    assignments: Vec<Assignment>,
-    text: String,
-    variables: &mut AHashMap<String, f64>,
-    ctx: EvalContext,
-) -> (&mut AHashMap<String, f64>, Vec<Error>) {
-    let mut errors: Vec<Error> = vec![];
+    frame.reset(ctx);
 
-    for assignment in assignments {
-        let eval_result = eval(&assignment.expression, variables, ctx);
+    for assignment in resolution.assignments.iter() {
+        let eval_result = eval(&assignment.expression, resolution, frame);
 
-        if RESERVED_VARIABLE_NAMES
-            .iter()
-            .any(|s| (&assignment.name.as_str() == s))
-        {
-            let loc = get_position_from_span(assignment.span, text.clone());
-
-            errors.push(Error {
-                line_number: loc.0,
-                col_number: loc.1,
-                reason: format!(
-                    "E4: Variable '{}' has the same name as reserved name '{}'.",
-                    assignment.name, assignment.name
-                ),
-                error_type: ErrorType::EvaluationError,
-                id: 4,
-            });
-        } else {
-            match eval_result {
+        match assignment.slot {
+            None => {
+                errors.push(Diagnostic::reserved_name(
+                    &assignment.name,
+                    assignment.span.clone(),
+                ));
+            }
+            Some(slot) => match eval_result {
                 Ok(value) => {
-                    variables.insert(assignment.name, value);
+                    frame.set(slot, value);
                 }
-                Err(error) => {
-                    let loc = get_position_from_span(error.span, text.clone());
-
-                    errors.push(Error {
-                        line_number: loc.0,
-                        col_number: loc.1,
-                        reason: error.error,
-                        error_type: ErrorType::EvaluationError,
-                        id: error.id,
-                    });
-                }
-            }
+                Err(error) => errors.push(*error.diagnostic),
+            },
         }
     }
 
-    (variables, errors)
+    errors
 }
@@ -0,0 +1,657 @@
+use super::builtin::{lerp, Builtin};
+use super::context::{ExecutionContext, RetrievalError};
+use super::data::CalcuatedPoint;
+use super::grid::Grid;
+use super::resolve::{self, INPUT_NAMES, OUTPUT_DEFAULTS, OUTPUT_NAMES};
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::{
+    self, Assignment, BinaryOperation, Expr, Span, Spanned, UnaryOperation,
+};
+use ahash::AHashMap;
+use rand::Rng;
+use rayon::prelude::*;
+use std::sync::Arc;
+
+/// The fewest indexes evaluated together, below which splitting the work between threads costs
+/// more than it saves.
+const MIN_CHUNK_SIZE: usize = 32;
+
+/// The most indexes evaluated together, so that the columns of a chunk stay in cache.
+const MAX_CHUNK_SIZE: usize = 1024;
+
+/// How many indexes are evaluated together: enough chunks for every thread to have one, as long
+/// as they stay within [`MIN_CHUNK_SIZE`] and [`MAX_CHUNK_SIZE`].
+fn chunk_size(count: usize) -> usize {
+    count
+        .div_ceil(rayon::current_num_threads())
+        .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+/// A node of an assignment's expression. Children are referred to by their index in the
+/// assignment's nodes, and always come before their parents.
+enum Node {
+    Number(f64),
+    Input(usize),
+    /// A variable, and its slot if it's ever assigned to.
+    Variable(String, Option<usize>),
+    Unary(UnaryOperation, usize),
+    Binary(BinaryOperation, usize, usize),
+    Call(String, Vec<usize>),
+    Error,
+}
+
+/// An assignment with its expression flattened into nodes, each of which is evaluated into a
+/// column of its own.
+struct Planned {
+    name: String,
+    span: Span,
+    /// `None` for a reserved name, which can't be assigned to.
+    slot: Option<usize>,
+    /// The last node is the whole expression.
+    nodes: Vec<(Node, Span)>,
+}
+
+/// Adds the nodes of an expression, returning the index of the one for the whole expression.
+fn plan(
+    spanned_expr: &Spanned<Arc<Expr>>,
+    slots: &AHashMap<String, usize>,
+    nodes: &mut Vec<(Node, Span)>,
+) -> usize {
+    let node = match &*spanned_expr.0 {
+        Expr::Number(value) => Node::Number(*value),
+        Expr::Group(inner) => return plan(inner, slots, nodes),
+        Expr::Variable(name) => match INPUT_NAMES.iter().position(|input| input == name) {
+            Some(input) => Node::Input(input),
+            None => Node::Variable(name.clone(), slots.get(name).copied()),
+        },
+        Expr::UnaryExpression(op, operand) => Node::Unary(op.clone(), plan(operand, slots, nodes)),
+        Expr::BinaryExpression(left, op, right) => {
+            let left = plan(left, slots, nodes);
+            let right = plan(right, slots, nodes);
+
+            Node::Binary(op.clone(), left, right)
+        }
+        Expr::Call(name, args) => Node::Call(
+            name.clone(),
+            args.iter().map(|arg| plan(arg, slots, nodes)).collect(),
+        ),
+        Expr::Error => Node::Error,
+    };
+
+    nodes.push((node, spanned_expr.1.clone()));
+    nodes.len() - 1
+}
+
+/// The columns left behind by evaluating a chunk of indexes.
+struct ChunkResult {
+    values: Vec<Vec<f64>>,
+    defined: Vec<Vec<bool>>,
+    errors: Vec<Vec<Diagnostic>>,
+}
+
+/// Evaluates every index in a chunk at once, one assignment at a time.
+///
+/// Every node is evaluated into a column with one entry per index, and the columns are allocated
+/// once per chunk and reused for every assignment. Arithmetic is done over the whole column
+/// regardless of which indexes are still running, and a mask keeps track of the indexes whose
+/// results are still wanted: an index is removed from the mask when it runs into an error, or
+/// when it takes the other side of an `if`, `&` or `|`.
+struct Chunk {
+    len: usize,
+    inputs: Vec<Vec<f64>>,
+    values: Vec<Vec<f64>>,
+    defined: Vec<Vec<bool>>,
+    errors: Vec<Vec<Diagnostic>>,
+    /// A column for every node of the largest expression.
+    columns: Vec<Vec<f64>>,
+    /// The indexes that take either side of the `if`, `&` or `|` at each node.
+    branches: Vec<(Vec<bool>, Vec<bool>)>,
+}
+
+fn map1(output: &mut [f64], a: &[f64], f: impl Fn(f64) -> f64) {
+    for (output, a) in output.iter_mut().zip(a) {
+        *output = f(*a);
+    }
+}
+
+fn map2(output: &mut [f64], a: &[f64], b: &[f64], f: impl Fn(f64, f64) -> f64) {
+    for ((output, a), b) in output.iter_mut().zip(a).zip(b) {
+        *output = f(*a, *b);
+    }
+}
+
+fn map3(output: &mut [f64], a: &[f64], b: &[f64], c: &[f64], f: impl Fn(f64, f64, f64) -> f64) {
+    for (((output, a), b), c) in output.iter_mut().zip(a).zip(b).zip(c) {
+        *output = f(*a, *b, *c);
+    }
+}
+
+/// Records an error for each of the indexes still running that `failed` is true for, and stops
+/// them. The error is only made if there's an index to record it for.
+fn fail(
+    errors: &mut [Vec<Diagnostic>],
+    mask: &mut [bool],
+    failed: impl Fn(usize) -> bool,
+    error: impl FnOnce() -> Diagnostic,
+) {
+    if !(0..mask.len()).any(|lane| mask[lane] && failed(lane)) {
+        return;
+    }
+
+    let error = error();
+
+    for lane in 0..mask.len() {
+        if mask[lane] && failed(lane) {
+            mask[lane] = false;
+            errors[lane].push(error.clone());
+        }
+    }
+}
+
+fn fail_all(errors: &mut [Vec<Diagnostic>], mask: &mut [bool], error: impl FnOnce() -> Diagnostic) {
+    fail(errors, mask, |_| true, error);
+}
+
+impl Chunk {
+    fn new(inputs: Vec<Vec<f64>>, variable_count: usize, node_count: usize) -> Chunk {
+        let len = inputs[0].len();
+
+        Chunk {
+            len,
+            inputs,
+            values: vec![vec![0.0; len]; variable_count],
+            defined: vec![vec![false; len]; variable_count],
+            errors: vec![vec![]; len],
+            columns: vec![vec![0.0; len]; node_count],
+            branches: vec![(vec![false; len], vec![false; len]); node_count],
+        }
+    }
+
+    /// Evaluates a node into its column.
+    fn eval(&mut self, nodes: &[(Node, Span)], node: usize, mask: &mut [bool]) {
+        let (kind, span) = &nodes[node];
+
+        // the children only ever use their own columns
+        let mut column = std::mem::take(&mut self.columns[node]);
+
+        match kind {
+            Node::Number(value) => column.fill(*value),
+            Node::Input(input) => column.copy_from_slice(&self.inputs[*input]),
+            Node::Variable(name, slot) => {
+                let error = || Diagnostic::unknown_variable(name, span.clone());
+
+                match slot {
+                    Some(slot) => {
+                        let defined = &self.defined[*slot];
+                        fail(&mut self.errors, mask, |lane| !defined[lane], error);
+                        column.copy_from_slice(&self.values[*slot]);
+                    }
+                    None => {
+                        fail_all(&mut self.errors, mask, error);
+                        column.fill(0.0);
+                    }
+                }
+            }
+            Node::Unary(op, operand) => {
+                self.eval(nodes, *operand, mask);
+                let a = &self.columns[*operand];
+
+                match op {
+                    UnaryOperation::Negate => map1(&mut column, a, |a| -a),
+                    UnaryOperation::Not => {
+                        map1(&mut column, a, |a| if a >= 1.0 { 0.0 } else { 1.0 })
+                    }
+                }
+            }
+            Node::Binary(BinaryOperation::And, left, right) => {
+                self.eval_logical(nodes, node, &mut column, (*left, *right), false, mask)
+            }
+            Node::Binary(BinaryOperation::Or, left, right) => {
+                self.eval_logical(nodes, node, &mut column, (*left, *right), true, mask)
+            }
+            Node::Binary(op, left, right) => {
+                self.eval(nodes, *left, mask);
+                self.eval(nodes, *right, mask);
+
+                let (a, b) = (&self.columns[*left], &self.columns[*right]);
+                let output = &mut column;
+
+                match op {
+                    BinaryOperation::Add => map2(output, a, b, |a, b| a + b),
+                    BinaryOperation::Subtract => map2(output, a, b, |a, b| a - b),
+                    BinaryOperation::Multiply => map2(output, a, b, |a, b| a * b),
+                    BinaryOperation::Divide => map2(output, a, b, |a, b| a / b),
+                    BinaryOperation::Modulo => map2(output, a, b, |a, b| a % b),
+                    BinaryOperation::Exponent => map2(output, a, b, f64::powf),
+                    BinaryOperation::LessThan => map2(output, a, b, |a, b| (a < b) as u64 as f64),
+                    BinaryOperation::GreaterThan => {
+                        map2(output, a, b, |a, b| (a > b) as u64 as f64)
+                    }
+                    BinaryOperation::LessThanOrEqual => {
+                        map2(output, a, b, |a, b| (a <= b) as u64 as f64)
+                    }
+                    BinaryOperation::GreaterThanOrEqual => {
+                        map2(output, a, b, |a, b| (a >= b) as u64 as f64)
+                    }
+                    BinaryOperation::Equal => map2(output, a, b, |a, b| (a == b) as u64 as f64),
+                    BinaryOperation::And | BinaryOperation::Or => unreachable!(),
+                }
+            }
+            Node::Call(name, args) if name == "if" => {
+                // the argument count is checked before any of the arguments are evaluated
+                if args.len() != 3 {
+                    fail_all(&mut self.errors, mask, || {
+                        Diagnostic::wrong_argument_count("if", 3, args.len(), span.clone())
+                    });
+                    column.fill(0.0);
+                } else {
+                    self.eval_if(nodes, node, &mut column, (args[0], args[1], args[2]), mask);
+                }
+            }
+            Node::Call(name, args) => {
+                // every argument is evaluated before the function is looked up
+                for arg in args.iter() {
+                    self.eval(nodes, *arg, mask);
+                }
+
+                match Builtin::from_name(name) {
+                    None => {
+                        fail_all(&mut self.errors, mask, || {
+                            Diagnostic::unknown_function(name, span.clone())
+                        });
+                        column.fill(0.0);
+                    }
+                    Some(builtin) if builtin.arity() != args.len() => {
+                        fail_all(&mut self.errors, mask, || {
+                            Diagnostic::wrong_argument_count(
+                                name,
+                                builtin.arity(),
+                                args.len(),
+                                span.clone(),
+                            )
+                        });
+                        column.fill(0.0);
+                    }
+                    Some(builtin) => self.call(builtin, args, &mut column, mask),
+                }
+            }
+            Node::Error => {
+                fail_all(&mut self.errors, mask, || {
+                    Diagnostic::invalid_parser_output(span.clone())
+                });
+                column.fill(0.0);
+            }
+        }
+
+        self.columns[node] = column;
+    }
+
+    /// Calculates a builtin function, whose arguments have already been evaluated and checked.
+    fn call(&self, builtin: Builtin, args: &[usize], output: &mut [f64], mask: &[bool]) {
+        let args: Vec<&[f64]> = args
+            .iter()
+            .map(|arg| self.columns[*arg].as_slice())
+            .collect();
+
+        match (builtin, args.as_slice()) {
+            (Builtin::Rand, _) => {
+                let mut rng = rand::thread_rng();
+
+                for (output, running) in output.iter_mut().zip(mask) {
+                    *output = if *running { rng.gen::<f64>() } else { 0.0 };
+                }
+            }
+            (Builtin::Lerp, [fraction, a, b]) => map3(output, fraction, a, b, lerp),
+            (_, [a]) => map1(
+                output,
+                a,
+                builtin.unary().expect("the argument count was checked"),
+            ),
+            (_, [a, b]) => map2(
+                output,
+                a,
+                b,
+                builtin.binary().expect("the argument count was checked"),
+            ),
+            // `if` is evaluated lazily, by `eval_if`
+            _ => unreachable!(),
+        }
+    }
+
+    /// Evaluates a node that only some indexes need, skipping it if none of them do.
+    fn eval_masked(&mut self, nodes: &[(Node, Span)], node: usize, mask: &mut [bool]) {
+        if mask.iter().any(|running| *running) {
+            self.eval(nodes, node, mask);
+        } else {
+            self.columns[node].fill(0.0);
+        }
+    }
+
+    fn eval_if(
+        &mut self,
+        nodes: &[(Node, Span)],
+        node: usize,
+        output: &mut [f64],
+        (condition, then, otherwise): (usize, usize, usize),
+        mask: &mut [bool],
+    ) {
+        self.eval(nodes, condition, mask);
+
+        let (mut then_mask, mut else_mask) = std::mem::take(&mut self.branches[node]);
+
+        for lane in 0..self.len {
+            let taken = self.columns[condition][lane] >= 1.0;
+
+            then_mask[lane] = mask[lane] && taken;
+            else_mask[lane] = mask[lane] && !taken;
+        }
+
+        self.eval_masked(nodes, then, &mut then_mask);
+        self.eval_masked(nodes, otherwise, &mut else_mask);
+
+        for lane in 0..self.len {
+            mask[lane] = then_mask[lane] || else_mask[lane];
+
+            output[lane] = match self.columns[condition][lane] >= 1.0 {
+                true => self.columns[then][lane],
+                false => self.columns[otherwise][lane],
+            };
+        }
+
+        self.branches[node] = (then_mask, else_mask);
+    }
+
+    /// Evaluates `&` (or `|` when `is_or` is set). The right hand side is only evaluated for the
+    /// indexes where the left hand side doesn't already decide the result.
+    fn eval_logical(
+        &mut self,
+        nodes: &[(Node, Span)],
+        node: usize,
+        output: &mut [f64],
+        (left, right): (usize, usize),
+        is_or: bool,
+        mask: &mut [bool],
+    ) {
+        self.eval(nodes, left, mask);
+
+        let (mut right_mask, unused) = std::mem::take(&mut self.branches[node]);
+
+        for lane in 0..self.len {
+            right_mask[lane] = mask[lane] && (self.columns[left][lane] >= 1.0) != is_or;
+        }
+
+        self.eval_masked(nodes, right, &mut right_mask);
+
+        for lane in 0..self.len {
+            let decided = (self.columns[left][lane] >= 1.0) == is_or;
+
+            if !decided && mask[lane] && !right_mask[lane] {
+                // the right hand side failed
+                mask[lane] = false;
+            }
+
+            output[lane] = if decided {
+                is_or as u64 as f64
+            } else {
+                (self.columns[right][lane] >= 1.0) as u64 as f64
+            };
+        }
+
+        self.branches[node] = (right_mask, unused);
+    }
+
+    fn run(&mut self, assignments: &[Planned]) {
+        let mut mask = vec![true; self.len];
+
+        for assignment in assignments {
+            mask.fill(true);
+
+            let Some(slot) = assignment.slot else {
+                // nothing assigned to a reserved name is ever stored, so there's no need to
+                // evaluate it
+                fail_all(&mut self.errors, &mut mask, || {
+                    Diagnostic::reserved_name(&assignment.name, assignment.span.clone())
+                });
+                continue;
+            };
+
+            let root = assignment.nodes.len() - 1;
+            self.eval(&assignment.nodes, root, &mut mask);
+
+            let results = self.columns[root].iter().zip(mask.iter());
+            let stored = self.values[slot]
+                .iter_mut()
+                .zip(self.defined[slot].iter_mut());
+
+            for ((value, defined), (result, running)) in stored.zip(results) {
+                if *running {
+                    *value = *result;
+                    *defined = true;
+                }
+            }
+        }
+    }
+}
+
+/// An ExecutionContext that evaluates each assignment for many indexes at once, over columns of
+/// values rather than one index at a time.
+#[derive(Default)]
+pub struct Batch {
+    assignments: Vec<Planned>,
+    variable_names: Vec<String>,
+    slots: AHashMap<String, usize>,
+    values: Vec<Vec<f64>>,
+    defined: Vec<Vec<bool>>,
+    errors: Vec<Vec<Diagnostic>>,
+}
+
+impl ExecutionContext for Batch {
+    fn load(&mut self, expression: String) -> Result<(), Vec<Diagnostic>> {
+        self.values = vec![];
+        self.defined = vec![];
+        self.errors = vec![];
+
+        match parser::parse(&expression) {
+            Ok(assignments) => {
+                self.load_parsed(assignments);
+                Ok(())
+            }
+            Err(errors) => {
+                self.assignments = vec![];
+                Err(errors)
+            }
+        }
+    }
+
+    fn load_parsed(&mut self, assignments: Vec<Assignment>) {
+        self.values = vec![];
+        self.defined = vec![];
+        self.errors = vec![];
+
+        self.variable_names = resolve::variable_names(&assignments);
+
+        self.slots = self
+            .variable_names
+            .iter()
+            .enumerate()
+            .map(|(slot, name)| (name.clone(), slot))
+            .collect();
+
+        self.assignments = assignments
+            .into_iter()
+            .map(|assignment| {
+                let mut nodes = vec![];
+                plan(&assignment.expression, &self.slots, &mut nodes);
+
+                Planned {
+                    slot: self.slots.get(&assignment.name).copied(),
+                    name: assignment.name,
+                    span: assignment.span,
+                    nodes,
+                }
+            })
+            .collect();
+    }
+
+    fn execute(
+        &mut self,
+        grid: Grid,
+        time: f64,
+        projection_start_time: f64,
+    ) -> Result<(Vec<CalcuatedPoint>, bool), Box<Diagnostic>> {
+        let base_ctx = grid.base_context(time, projection_start_time);
+        let count = grid.count() as usize;
+        let variable_count = self.variable_names.len();
+
+        let chunk_size = chunk_size(count);
+        let node_count = self
+            .assignments
+            .iter()
+            .map(|assignment| assignment.nodes.len())
+            .max()
+            .unwrap_or(0);
+
+        let chunks: Vec<ChunkResult> = (0..count)
+            .step_by(chunk_size)
+            .collect::<Vec<usize>>()
+            .into_par_iter()
+            .map(|start| {
+                let len = chunk_size.min(count - start);
+                let mut inputs = vec![vec![0.0; len]; INPUT_NAMES.len()];
+
+                for lane in 0..len {
+                    let ctx = grid.context_for(base_ctx, (start + lane) as u16);
+
+                    for (input, value) in resolve::inputs(ctx).into_iter().enumerate() {
+                        inputs[input][lane] = value;
+                    }
+                }
+
+                let mut chunk = Chunk::new(inputs, variable_count, node_count);
+
+                chunk.run(&self.assignments);
+
+                ChunkResult {
+                    values: chunk.values,
+                    defined: chunk.defined,
+                    errors: chunk.errors,
+                }
+            })
+            .collect();
+
+        self.values = vec![Vec::with_capacity(count); variable_count];
+        self.defined = vec![Vec::with_capacity(count); variable_count];
+        self.errors = Vec::with_capacity(count);
+
+        for chunk in chunks {
+            for (slot, values) in chunk.values.into_iter().enumerate() {
+                self.values[slot].extend(values);
+            }
+            for (slot, defined) in chunk.defined.into_iter().enumerate() {
+                self.defined[slot].extend(defined);
+            }
+            self.errors.extend(chunk.errors);
+        }
+
+        let outputs = OUTPUT_NAMES.map(|output| self.slots.get(output).copied());
+
+        let points = (0..count)
+            .map(|index| {
+                let mut point = OUTPUT_DEFAULTS;
+
+                for (output, slot) in point.iter_mut().zip(outputs.iter()) {
+                    if let Some(slot) = slot {
+                        if self.defined[*slot][index] {
+                            *output = self.values[*slot][index];
+                        }
+                    }
+                }
+
+                let [x, y, h, s, v] = point;
+
+                CalcuatedPoint {
+                    x,
+                    y,
+                    h,
+                    s,
+                    v,
+                    index: index as u16,
+                }
+            })
+            .collect();
+
+        let encountered_error = self.errors.iter().any(|errors| errors.len() > 0);
+
+        Ok((points, encountered_error))
+    }
+
+    fn retrieve_errors(&mut self, i: u16) -> Result<Vec<Diagnostic>, RetrievalError> {
+        match self.errors.get(i as usize) {
+            Some(errors) => Ok(errors.clone()),
+            None => Err(RetrievalError::MissingValue),
+        }
+    }
+
+    fn retrieve_variables(&mut self, i: u16) -> Result<AHashMap<String, f64>, RetrievalError> {
+        let i = i as usize;
+
+        if i >= self.errors.len() {
+            return Err(RetrievalError::MissingValue);
+        }
+
+        let mut variables = AHashMap::new();
+
+        for (slot, name) in self.variable_names.iter().enumerate() {
+            if self.defined[slot][i] {
+                variables.insert(name.clone(), self.values[slot][i]);
+            }
+        }
+
+        Ok(variables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expressions::eval::interpreter::Interpreter;
+
+    #[test]
+    fn chunks_fit_the_grid() {
+        assert_eq!(chunk_size(4), MIN_CHUNK_SIZE);
+        assert!(chunk_size(400) <= 400.max(MIN_CHUNK_SIZE));
+        assert_eq!(chunk_size(1_000_000), MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn largest_grid_matches_the_interpreter() {
+        // every index has its own values, so the chunks have to be put back together in order
+        let expression = "a = if(index % 3 == 0, x * 2, y / 3);\n\
+                          b = a > 0 & index < 200 | fraction > 0.9;\n\
+                          x' = lerp(fraction, a, index);\n\
+                          y' = if(b, sin(a), cos(a));";
+
+        let grid = Grid {
+            x_size: 20,
+            y_size: 20,
+        };
+
+        let mut batch = Batch::default();
+        let mut interpreter = Interpreter::default();
+        batch.load(expression.to_string()).unwrap();
+        interpreter.load(expression.to_string()).unwrap();
+
+        let (expected, _) = interpreter.execute(grid, 3.0, 1.0).unwrap();
+        let (actual, encountered_error) = batch.execute(grid, 3.0, 1.0).unwrap();
+
+        assert!(!encountered_error);
+        assert_eq!(expected.len(), actual.len());
+
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            assert_eq!(
+                (expected.x, expected.y, expected.index),
+                (actual.x, actual.y, actual.index)
+            );
+        }
+    }
+}
@@ -0,0 +1,163 @@
+/// A function that can be called by name in an expression.
+///
+/// Every backend matches on this exhaustively, so adding a builtin here points out everywhere
+/// that needs to learn how to calculate it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Sqrt,
+    Min,
+    Max,
+    Floor,
+    Ceil,
+    Round,
+    Abs,
+    Rand,
+    If,
+    Lerp,
+}
+
+impl Builtin {
+    /// Every builtin function, in the order the reference lists them.
+    pub const ALL: [Builtin; 17] = [
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Tan,
+        Builtin::Asin,
+        Builtin::Acos,
+        Builtin::Atan,
+        Builtin::Atan2,
+        Builtin::Sqrt,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::Floor,
+        Builtin::Ceil,
+        Builtin::Round,
+        Builtin::Abs,
+        Builtin::Rand,
+        Builtin::If,
+        Builtin::Lerp,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Builtin::ALL
+            .into_iter()
+            .find(|builtin| builtin.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Tan => "tan",
+            Builtin::Asin => "asin",
+            Builtin::Acos => "acos",
+            Builtin::Atan => "atan",
+            Builtin::Atan2 => "atan2",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Floor => "floor",
+            Builtin::Ceil => "ceil",
+            Builtin::Round => "round",
+            Builtin::Abs => "abs",
+            Builtin::Rand => "rand",
+            Builtin::If => "if",
+            Builtin::Lerp => "lerp",
+        }
+    }
+
+    /// The number of arguments the function takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Rand => 0,
+            Builtin::Sin
+            | Builtin::Cos
+            | Builtin::Tan
+            | Builtin::Asin
+            | Builtin::Acos
+            | Builtin::Atan
+            | Builtin::Sqrt
+            | Builtin::Floor
+            | Builtin::Ceil
+            | Builtin::Round
+            | Builtin::Abs => 1,
+            Builtin::Atan2 | Builtin::Min | Builtin::Max => 2,
+            Builtin::If | Builtin::Lerp => 3,
+        }
+    }
+
+    /// The calculation behind a builtin that takes one argument.
+    pub fn unary(self) -> Option<fn(f64) -> f64> {
+        match self {
+            Builtin::Sin => Some(f64::sin),
+            Builtin::Cos => Some(f64::cos),
+            Builtin::Tan => Some(f64::tan),
+            Builtin::Asin => Some(f64::asin),
+            Builtin::Acos => Some(f64::acos),
+            Builtin::Atan => Some(f64::atan),
+            Builtin::Sqrt => Some(f64::sqrt),
+            Builtin::Floor => Some(f64::floor),
+            Builtin::Ceil => Some(f64::ceil),
+            Builtin::Round => Some(f64::round),
+            Builtin::Abs => Some(f64::abs),
+            _ => None,
+        }
+    }
+
+    /// The calculation behind a builtin that takes two arguments.
+    pub fn binary(self) -> Option<fn(f64, f64) -> f64> {
+        match self {
+            Builtin::Atan2 => Some(f64::atan2),
+            Builtin::Min => Some(f64::min),
+            Builtin::Max => Some(f64::max),
+            _ => None,
+        }
+    }
+
+    /// Calculates a builtin whose result only depends on its arguments, or returns `None` for
+    /// `rand` and `if`, or when given the wrong number of arguments.
+    pub fn apply(self, args: &[f64]) -> Option<f64> {
+        match args {
+            [a] => self.unary().map(|function| function(*a)),
+            [a, b] => self.binary().map(|function| function(*a, *b)),
+            [fraction, a, b] if self == Builtin::Lerp => Some(lerp(*fraction, *a, *b)),
+            _ => None,
+        }
+    }
+}
+
+// formula for this is from the steam guide
+pub fn lerp(fraction: f64, a: f64, b: f64) -> f64 {
+    a * (1.0 - fraction) + b * fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for builtin in Builtin::ALL {
+            assert_eq!(Builtin::from_name(builtin.name()), Some(builtin));
+        }
+
+        assert_eq!(Builtin::from_name("sine"), None);
+    }
+
+    #[test]
+    fn pure_builtins_take_their_arity() {
+        for builtin in Builtin::ALL {
+            let args = vec![0.5; builtin.arity()];
+            let pure = !matches!(builtin, Builtin::Rand | Builtin::If);
+
+            assert_eq!(builtin.apply(&args).is_some(), pure, "{builtin:?}");
+        }
+    }
+}
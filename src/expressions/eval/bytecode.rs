@@ -0,0 +1,706 @@
+use super::builtin::{lerp, Builtin};
+use super::resolve::{inputs, variable_names, INPUT_NAMES, OUTPUT_DEFAULTS, OUTPUT_NAMES};
+use super::EvalContext;
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::{
+    Assignment, BinaryOperation, Expr, Span, Spanned, UnaryOperation,
+};
+use ahash::AHashMap;
+use rand::Rng;
+use std::sync::Arc;
+
+pub type Register = u16;
+pub type ErrorId = u32;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Instruction {
+    Set {
+        dst: Register,
+        value: f64,
+    },
+    Move {
+        dst: Register,
+        src: Register,
+    },
+
+    Negate {
+        dst: Register,
+        src: Register,
+    },
+    Not {
+        dst: Register,
+        src: Register,
+    },
+    /// Stores 1 if `src` is at least 1, otherwise 0.
+    Truthy {
+        dst: Register,
+        src: Register,
+    },
+
+    Add {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Subtract {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Multiply {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Divide {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Modulo {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Exponent {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    LessThan {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    GreaterThan {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    LessThanOrEqual {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    GreaterThanOrEqual {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Equal {
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+
+    Call1 {
+        function: fn(f64) -> f64,
+        dst: Register,
+        a: Register,
+    },
+    Call2 {
+        function: fn(f64, f64) -> f64,
+        dst: Register,
+        a: Register,
+        b: Register,
+    },
+    Lerp {
+        dst: Register,
+        fraction: Register,
+        a: Register,
+        b: Register,
+    },
+    Rand {
+        dst: Register,
+    },
+
+    Jump {
+        target: u32,
+    },
+    /// Jumps unless `cond` is at least 1 (so NaN jumps, like the interpreter's `if`).
+    JumpIfFalse {
+        cond: Register,
+        target: u32,
+    },
+    /// Jumps if `cond` is at least 1.
+    JumpIfTrue {
+        cond: Register,
+        target: u32,
+    },
+
+    /// Fails with `error` if the variable hasn't been assigned to yet.
+    CheckDefined {
+        variable: u16,
+        error: ErrorId,
+    },
+    Fail {
+        error: ErrorId,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    Variable(u16),
+    /// Assigning to a reserved name always fails with the given error.
+    Reserved(ErrorId),
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledAssignment {
+    pub start: u32,
+    pub end: u32,
+    pub result: Register,
+    pub target: Target,
+}
+
+/// A compiled expression.
+///
+/// Registers are laid out as the inputs, then one register per user variable, then the constant
+/// pool, then the temporaries used while evaluating each assignment. Every error the program can
+/// raise is built ahead of time.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub assignments: Vec<CompiledAssignment>,
+    pub variable_names: Vec<String>,
+    pub constants: Vec<f64>,
+    pub errors: Vec<Diagnostic>,
+    pub register_count: usize,
+    outputs: [Option<u16>; 5],
+}
+
+struct Compiler {
+    instructions: Vec<Instruction>,
+    variables: AHashMap<String, u16>,
+    constants: AHashMap<u64, Register>,
+    errors: Vec<Diagnostic>,
+    temporary_base: Register,
+    next_temporary: Register,
+    register_count: usize,
+}
+
+fn collect_constants(expr: &Expr, constants: &mut Vec<f64>) {
+    match expr {
+        Expr::Number(value) => {
+            if !constants.iter().any(|c| c.to_bits() == value.to_bits()) {
+                constants.push(*value);
+            }
+        }
+        Expr::Group(inner) | Expr::UnaryExpression(_, inner) => {
+            collect_constants(&inner.0, constants)
+        }
+        Expr::BinaryExpression(a, _, b) => {
+            collect_constants(&a.0, constants);
+            collect_constants(&b.0, constants);
+        }
+        Expr::Call(_, args) => args
+            .iter()
+            .for_each(|arg| collect_constants(&arg.0, constants)),
+        Expr::Variable(_) | Expr::Error => (),
+    }
+}
+
+impl Compiler {
+    fn error(&mut self, error: Diagnostic) -> ErrorId {
+        self.errors.push(error);
+        (self.errors.len() - 1) as ErrorId
+    }
+
+    fn arity_error(&mut self, name: &str, expected: usize, actual: usize, span: &Span) -> ErrorId {
+        self.error(Diagnostic::wrong_argument_count(
+            name,
+            expected,
+            actual,
+            span.clone(),
+        ))
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    fn position(&self) -> u32 {
+        self.instructions.len() as u32
+    }
+
+    fn patch(&mut self, at: u32, to: u32) {
+        match &mut self.instructions[at as usize] {
+            Instruction::Jump { target }
+            | Instruction::JumpIfFalse { target, .. }
+            | Instruction::JumpIfTrue { target, .. } => *target = to,
+            _ => unreachable!("tried to patch an instruction that isn't a jump"),
+        }
+    }
+
+    fn temporary(&mut self) -> Register {
+        let register = self.next_temporary;
+        self.next_temporary += 1;
+        self.register_count = self.register_count.max(self.next_temporary as usize);
+        register
+    }
+
+    fn variable_register(&self, variable: u16) -> Register {
+        INPUT_NAMES.len() as Register + variable
+    }
+
+    fn compile(&mut self, spanned_expr: &Spanned<Arc<Expr>>) -> Register {
+        let span = &spanned_expr.1;
+
+        match &*spanned_expr.0 {
+            Expr::Number(value) => self.constants[&value.to_bits()],
+            Expr::Group(inner) => self.compile(inner),
+            Expr::Variable(name) => {
+                if let Some(input) = INPUT_NAMES.iter().position(|input| input == name) {
+                    return input as Register;
+                }
+
+                let error = self.error(Diagnostic::unknown_variable(name, span.clone()));
+
+                match self.variables.get(name).copied() {
+                    Some(variable) => {
+                        self.emit(Instruction::CheckDefined { variable, error });
+                        self.variable_register(variable)
+                    }
+                    None => {
+                        // never assigned anywhere, so reading it always fails
+                        self.emit(Instruction::Fail { error });
+                        self.temporary()
+                    }
+                }
+            }
+            Expr::UnaryExpression(op, operand) => {
+                let mark = self.next_temporary;
+                let src = self.compile(operand);
+                self.next_temporary = mark;
+                let dst = self.temporary();
+
+                self.emit(match op {
+                    UnaryOperation::Negate => Instruction::Negate { dst, src },
+                    UnaryOperation::Not => Instruction::Not { dst, src },
+                });
+
+                dst
+            }
+            Expr::BinaryExpression(left, BinaryOperation::And, right) => {
+                self.compile_logical(left, right, false)
+            }
+            Expr::BinaryExpression(left, BinaryOperation::Or, right) => {
+                self.compile_logical(left, right, true)
+            }
+            Expr::BinaryExpression(left, op, right) => {
+                let mark = self.next_temporary;
+                let a = self.compile(left);
+                let b = self.compile(right);
+                self.next_temporary = mark;
+                let dst = self.temporary();
+
+                self.emit(match op {
+                    BinaryOperation::Add => Instruction::Add { dst, a, b },
+                    BinaryOperation::Subtract => Instruction::Subtract { dst, a, b },
+                    BinaryOperation::Multiply => Instruction::Multiply { dst, a, b },
+                    BinaryOperation::Divide => Instruction::Divide { dst, a, b },
+                    BinaryOperation::Modulo => Instruction::Modulo { dst, a, b },
+                    BinaryOperation::Exponent => Instruction::Exponent { dst, a, b },
+                    BinaryOperation::LessThan => Instruction::LessThan { dst, a, b },
+                    BinaryOperation::GreaterThan => Instruction::GreaterThan { dst, a, b },
+                    BinaryOperation::LessThanOrEqual => Instruction::LessThanOrEqual { dst, a, b },
+                    BinaryOperation::GreaterThanOrEqual => {
+                        Instruction::GreaterThanOrEqual { dst, a, b }
+                    }
+                    BinaryOperation::Equal => Instruction::Equal { dst, a, b },
+                    BinaryOperation::And | BinaryOperation::Or => unreachable!(),
+                });
+
+                dst
+            }
+            Expr::Call(name, args) if name == "if" => {
+                // the argument count is checked before any of the arguments are evaluated
+                if args.len() != 3 {
+                    let error = self.arity_error(name, 3, args.len(), span);
+                    self.emit(Instruction::Fail { error });
+                    return self.temporary();
+                }
+
+                let dst = self.temporary();
+                let mark = self.next_temporary;
+
+                let cond = self.compile(&args[0]);
+                let jump_to_else = self.position();
+                self.emit(Instruction::JumpIfFalse { cond, target: 0 });
+                self.next_temporary = mark;
+
+                let src = self.compile(&args[1]);
+                self.emit(Instruction::Move { dst, src });
+                let jump_to_end = self.position();
+                self.emit(Instruction::Jump { target: 0 });
+                self.next_temporary = mark;
+
+                let else_position = self.position();
+                self.patch(jump_to_else, else_position);
+
+                let src = self.compile(&args[2]);
+                self.emit(Instruction::Move { dst, src });
+                self.next_temporary = mark;
+
+                let end_position = self.position();
+                self.patch(jump_to_end, end_position);
+
+                dst
+            }
+            Expr::Call(name, args) => {
+                // every argument is evaluated before the function is looked up
+                let mark = self.next_temporary;
+                let registers: Vec<Register> = args.iter().map(|arg| self.compile(arg)).collect();
+                self.next_temporary = mark;
+                let dst = self.temporary();
+
+                let builtin = match Builtin::from_name(name) {
+                    Some(builtin) => builtin,
+                    None => {
+                        let error = self.error(Diagnostic::unknown_function(name, span.clone()));
+                        self.emit(Instruction::Fail { error });
+                        return dst;
+                    }
+                };
+                let expected = builtin.arity();
+
+                if registers.len() != expected {
+                    let error = self.arity_error(name, expected, registers.len(), span);
+                    self.emit(Instruction::Fail { error });
+                    return dst;
+                }
+
+                self.emit(match (builtin.unary(), builtin.binary()) {
+                    (Some(function), _) => Instruction::Call1 {
+                        function,
+                        dst,
+                        a: registers[0],
+                    },
+                    (_, Some(function)) => Instruction::Call2 {
+                        function,
+                        dst,
+                        a: registers[0],
+                        b: registers[1],
+                    },
+                    _ if builtin == Builtin::Lerp => Instruction::Lerp {
+                        dst,
+                        fraction: registers[0],
+                        a: registers[1],
+                        b: registers[2],
+                    },
+                    _ => Instruction::Rand { dst },
+                });
+
+                dst
+            }
+            Expr::Error => {
+                let error = self.error(Diagnostic::invalid_parser_output(span.clone()));
+                self.emit(Instruction::Fail { error });
+                self.temporary()
+            }
+        }
+    }
+
+    /// Compiles `&` (or `|` when `is_or` is set), which only evaluates the right hand side when
+    /// the left hand side doesn't already decide the result.
+    fn compile_logical(
+        &mut self,
+        left: &Spanned<Arc<Expr>>,
+        right: &Spanned<Arc<Expr>>,
+        is_or: bool,
+    ) -> Register {
+        let dst = self.temporary();
+        let mark = self.next_temporary;
+
+        let cond = self.compile(left);
+        let jump_to_short = self.position();
+        self.emit(match is_or {
+            true => Instruction::JumpIfTrue { cond, target: 0 },
+            false => Instruction::JumpIfFalse { cond, target: 0 },
+        });
+        self.next_temporary = mark;
+
+        let src = self.compile(right);
+        self.emit(Instruction::Truthy { dst, src });
+        let jump_to_end = self.position();
+        self.emit(Instruction::Jump { target: 0 });
+        self.next_temporary = mark;
+
+        let short_position = self.position();
+        self.patch(jump_to_short, short_position);
+        self.emit(Instruction::Set {
+            dst,
+            value: if is_or { 1.0 } else { 0.0 },
+        });
+
+        let end_position = self.position();
+        self.patch(jump_to_end, end_position);
+
+        dst
+    }
+}
+
+/// Compiles parsed assignments into a [`Program`].
+pub fn compile(assignments: &[Assignment]) -> Program {
+    let variable_names = variable_names(assignments);
+
+    let mut constants: Vec<f64> = vec![];
+
+    for assignment in assignments {
+        collect_constants(&assignment.expression.0, &mut constants);
+    }
+
+    let constant_base = INPUT_NAMES.len() + variable_names.len();
+    let temporary_base = (constant_base + constants.len()) as Register;
+
+    let mut compiler = Compiler {
+        instructions: vec![],
+        variables: variable_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i as u16))
+            .collect(),
+        constants: constants
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (value.to_bits(), (constant_base + i) as Register))
+            .collect(),
+        errors: vec![],
+        temporary_base,
+        next_temporary: temporary_base,
+        register_count: temporary_base as usize,
+    };
+
+    let mut compiled = vec![];
+
+    for assignment in assignments {
+        let start = compiler.position();
+
+        let target = match compiler.variables.get(&assignment.name) {
+            Some(variable) => Target::Variable(*variable),
+            None => Target::Reserved(compiler.error(Diagnostic::reserved_name(
+                &assignment.name,
+                assignment.span.clone(),
+            ))),
+        };
+
+        // nothing assigned to a reserved name is ever stored, so there's no need to compile it
+        let result = match target {
+            Target::Variable(_) => {
+                compiler.next_temporary = compiler.temporary_base;
+                compiler.compile(&assignment.expression)
+            }
+            Target::Reserved(_) => 0,
+        };
+
+        compiled.push(CompiledAssignment {
+            start,
+            end: compiler.position(),
+            result,
+            target,
+        });
+    }
+
+    let outputs = OUTPUT_NAMES.map(|name| compiler.variables.get(name).copied());
+
+    Program {
+        instructions: compiler.instructions,
+        assignments: compiled,
+        variable_names,
+        constants,
+        errors: compiler.errors,
+        register_count: compiler.register_count,
+        outputs,
+    }
+}
+
+impl Program {
+    /// Creates a register file for this program, with the constant pool already filled in.
+    /// It can be reused for any number of calls to [`Program::run`].
+    pub fn registers(&self) -> Vec<f64> {
+        let mut registers = vec![0.0; self.register_count];
+        let constant_base = INPUT_NAMES.len() + self.variable_names.len();
+
+        registers[constant_base..constant_base + self.constants.len()]
+            .copy_from_slice(&self.constants);
+
+        registers
+    }
+
+    pub fn variable_register(&self, variable: usize) -> usize {
+        INPUT_NAMES.len() + variable
+    }
+
+    /// Runs the program for a single index. `defined` tracks which variables have been assigned
+    /// to, and must be as long as `variable_names`.
+    pub fn run(
+        &self,
+        ctx: EvalContext,
+        registers: &mut [f64],
+        defined: &mut [bool],
+        errors: &mut Vec<Diagnostic>,
+    ) {
+        registers[..INPUT_NAMES.len()].copy_from_slice(&inputs(ctx));
+        defined.fill(false);
+
+        for assignment in self.assignments.iter() {
+            match assignment.target {
+                Target::Reserved(error) => errors.push(self.errors[error as usize].clone()),
+                Target::Variable(variable) => {
+                    match self.execute(assignment.start, assignment.end, registers, defined) {
+                        Ok(()) => {
+                            registers[self.variable_register(variable as usize)] =
+                                registers[assignment.result as usize];
+                            defined[variable as usize] = true;
+                        }
+                        Err(error) => errors.push(self.errors[error as usize].clone()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the outputs after a call to [`Program::run`], falling back to their default values.
+    pub fn outputs(&self, registers: &[f64], defined: &[bool]) -> [f64; 5] {
+        let mut outputs = OUTPUT_DEFAULTS;
+
+        for (output, variable) in outputs.iter_mut().zip(self.outputs.iter()) {
+            if let Some(variable) = variable {
+                if defined[*variable as usize] {
+                    *output = registers[self.variable_register(*variable as usize)];
+                }
+            }
+        }
+
+        outputs
+    }
+
+    fn execute(
+        &self,
+        start: u32,
+        end: u32,
+        registers: &mut [f64],
+        defined: &[bool],
+    ) -> Result<(), ErrorId> {
+        let mut pc = start as usize;
+        let end = end as usize;
+
+        while pc < end {
+            match self.instructions[pc] {
+                Instruction::Set { dst, value } => registers[dst as usize] = value,
+                Instruction::Move { dst, src } => registers[dst as usize] = registers[src as usize],
+                Instruction::Negate { dst, src } => {
+                    registers[dst as usize] = -registers[src as usize]
+                }
+                Instruction::Not { dst, src } => {
+                    registers[dst as usize] = if registers[src as usize] >= 1.0 {
+                        0.0
+                    } else {
+                        1.0
+                    }
+                }
+                Instruction::Truthy { dst, src } => {
+                    registers[dst as usize] = (registers[src as usize] >= 1.0) as u64 as f64
+                }
+                Instruction::Add { dst, a, b } => {
+                    registers[dst as usize] = registers[a as usize] + registers[b as usize]
+                }
+                Instruction::Subtract { dst, a, b } => {
+                    registers[dst as usize] = registers[a as usize] - registers[b as usize]
+                }
+                Instruction::Multiply { dst, a, b } => {
+                    registers[dst as usize] = registers[a as usize] * registers[b as usize]
+                }
+                Instruction::Divide { dst, a, b } => {
+                    registers[dst as usize] = registers[a as usize] / registers[b as usize]
+                }
+                Instruction::Modulo { dst, a, b } => {
+                    registers[dst as usize] = registers[a as usize] % registers[b as usize]
+                }
+                Instruction::Exponent { dst, a, b } => {
+                    registers[dst as usize] =
+                        f64::powf(registers[a as usize], registers[b as usize])
+                }
+                Instruction::LessThan { dst, a, b } => {
+                    registers[dst as usize] =
+                        (registers[a as usize] < registers[b as usize]) as u64 as f64
+                }
+                Instruction::GreaterThan { dst, a, b } => {
+                    registers[dst as usize] =
+                        (registers[a as usize] > registers[b as usize]) as u64 as f64
+                }
+                Instruction::LessThanOrEqual { dst, a, b } => {
+                    registers[dst as usize] =
+                        (registers[a as usize] <= registers[b as usize]) as u64 as f64
+                }
+                Instruction::GreaterThanOrEqual { dst, a, b } => {
+                    registers[dst as usize] =
+                        (registers[a as usize] >= registers[b as usize]) as u64 as f64
+                }
+                Instruction::Equal { dst, a, b } => {
+                    registers[dst as usize] =
+                        (registers[a as usize] == registers[b as usize]) as u64 as f64
+                }
+                Instruction::Call1 { function, dst, a } => {
+                    let a = registers[a as usize];
+
+                    registers[dst as usize] = function(a)
+                }
+                Instruction::Call2 {
+                    function,
+                    dst,
+                    a,
+                    b,
+                } => {
+                    let a = registers[a as usize];
+                    let b = registers[b as usize];
+
+                    registers[dst as usize] = function(a, b)
+                }
+                Instruction::Lerp {
+                    dst,
+                    fraction,
+                    a,
+                    b,
+                } => {
+                    registers[dst as usize] = lerp(
+                        registers[fraction as usize],
+                        registers[a as usize],
+                        registers[b as usize],
+                    )
+                }
+                Instruction::Rand { dst } => {
+                    let mut rng = rand::thread_rng();
+
+                    registers[dst as usize] = rng.gen::<f64>()
+                }
+                Instruction::Jump { target } => {
+                    pc = target as usize;
+                    continue;
+                }
+                Instruction::JumpIfFalse { cond, target } => {
+                    if !(registers[cond as usize] >= 1.0) {
+                        pc = target as usize;
+                        continue;
+                    }
+                }
+                Instruction::JumpIfTrue { cond, target } => {
+                    if registers[cond as usize] >= 1.0 {
+                        pc = target as usize;
+                        continue;
+                    }
+                }
+                Instruction::CheckDefined { variable, error } => {
+                    if !defined[variable as usize] {
+                        return Err(error);
+                    }
+                }
+                Instruction::Fail { error } => return Err(error),
+            }
+
+            pc += 1;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use super::builtin::Builtin;
+use super::is_reserved;
+use super::resolve::INPUT_NAMES;
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::{Assignment, Expr, Spanned};
+use std::sync::Arc;
+
+/// The number of arguments a builtin function takes, or `None` if there is no such function.
+pub fn arity(name: &str) -> Option<usize> {
+    Builtin::from_name(name).map(Builtin::arity)
+}
+
+struct Checker<'a> {
+    assignments: &'a [Assignment],
+    /// The index of the assignment being checked.
+    current: usize,
+    /// The variables that have been assigned to by the assignments checked so far.
+    assigned: Vec<&'a str>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, error: Diagnostic) {
+        self.errors.push(error);
+    }
+
+    fn check_expr(&mut self, spanned_expr: &'a Spanned<Arc<Expr>>) {
+        let span = &spanned_expr.1;
+
+        match &*spanned_expr.0 {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => {
+                let defined =
+                    INPUT_NAMES.contains(&name.as_str()) || self.assigned.contains(&name.as_str());
+
+                if !defined {
+                    let mut error = Diagnostic::unknown_variable(name, span.clone());
+
+                    // a variable that's assigned to later on is most likely just used too early
+                    let later = self.assignments[self.current + 1..]
+                        .iter()
+                        .find(|assignment| assignment.name == *name);
+
+                    if let Some(later) = later {
+                        error = error
+                            .with_secondary(
+                                later.span.clone(),
+                                format!("'{name}' is assigned here, after it's used"),
+                            )
+                            .with_help(format!("Move the assignment to '{name}' before this one."));
+                    } else {
+                        let candidates = INPUT_NAMES.iter().chain(self.assigned.iter()).copied();
+                        error = error.with_suggestion(name, candidates);
+                    }
+
+                    self.report(error);
+                }
+            }
+            Expr::Group(inner) => self.check_expr(inner),
+            Expr::BinaryExpression(left, _, right) => {
+                self.check_expr(left);
+                self.check_expr(right);
+            }
+            Expr::UnaryExpression(_, operand) => self.check_expr(operand),
+            Expr::Call(name, args) => {
+                // every argument is checked, including the branches of an `if` that may never
+                // be taken at runtime
+                for arg in args.iter() {
+                    self.check_expr(arg);
+                }
+
+                match arity(name) {
+                    Some(expected) if expected != args.len() => self.report(
+                        Diagnostic::wrong_argument_count(name, expected, args.len(), span.clone()),
+                    ),
+                    Some(_) => {}
+                    None => self.report(
+                        Diagnostic::unknown_function(name, span.clone()).with_functions(name),
+                    ),
+                }
+            }
+            // the parser reports these itself
+            Expr::Error => {}
+        }
+    }
+}
+
+/// Finds every unknown function (E3), call with the wrong number of arguments (E1), variable used
+/// before it's assigned to (E2) and assignment to a reserved name (E4) without evaluating
+/// anything.
+///
+/// Each problem is reported once, no matter how many indexes it would happen in at runtime, and
+/// every branch of an `if` is checked, whether or not it would be taken.
+pub fn check(assignments: &[Assignment]) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        assignments,
+        current: 0,
+        assigned: vec![],
+        errors: vec![],
+    };
+
+    for (current, assignment) in assignments.iter().enumerate() {
+        checker.current = current;
+        checker.check_expr(&assignment.expression);
+
+        if is_reserved(&assignment.name) {
+            checker.report(Diagnostic::reserved_name(
+                &assignment.name,
+                assignment.span.clone(),
+            ));
+        } else {
+            checker.assigned.push(&assignment.name);
+        }
+    }
+
+    checker.errors
+}
@@ -1,23 +1,293 @@
+use super::batch::Batch;
 use super::data::CalcuatedPoint;
-use crate::expressions::errors;
+use super::grid::Grid;
+use super::interpreter::Interpreter;
+#[cfg(feature = "jit")]
+use super::jit::Jit;
+use super::vm::Vm;
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::Assignment;
 use ahash::AHashMap;
 use std::result::Result;
 
+#[derive(Debug)]
 pub enum RetrievalError {
     MissingName,
     MissingValue,
 }
 
-pub trait ExecutionContext {
+pub trait ExecutionContext: Send {
     /// Loads an expression, generating the data required (AST, shader, etc.) for the current
     /// ExecutionContext ahead of execution.
-    fn load(&mut self, expression: String) -> Result<(), errors::Error>;
-    /// Executes an expression, returning a vector of calculated points and boolean indicating if
-    /// an error that did not stop execution was detected.
-    fn execute(&mut self) -> Result<(Vec<CalcuatedPoint>, bool), errors::Error>;
+    fn load(&mut self, expression: String) -> Result<(), Vec<Diagnostic>>;
+    /// Loads an expression that has already been parsed, such as one parsed in Tower
+    /// compatibility mode.
+    fn load_parsed(&mut self, assignments: Vec<Assignment>);
+    /// Executes the loaded expression for every index in the grid, returning a vector of
+    /// calculated points and boolean indicating if an error that did not stop execution was
+    /// detected.
+    fn execute(
+        &mut self,
+        grid: Grid,
+        time: f64,
+        projection_start_time: f64,
+    ) -> Result<(Vec<CalcuatedPoint>, bool), Box<Diagnostic>>;
 
     /// Retrieves any errors that may have occured at the specified index, i.
-    fn retrieve_errors(&mut self, i: u16) -> Result<Vec<Vec<errors::Error>>, RetrievalError>;
+    fn retrieve_errors(&mut self, i: u16) -> Result<Vec<Diagnostic>, RetrievalError>;
     /// Retrieves any declared variables tbat were generated at the specified index, i.
-    fn retrieve_variables(&mut self, i: u16) -> Result<Vec<AHashMap<String, f64>>, RetrievalError>;
+    fn retrieve_variables(&mut self, i: u16) -> Result<AHashMap<String, f64>, RetrievalError>;
+}
+
+/// The available ExecutionContext implementations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Interpreter,
+    Bytecode,
+    Vectorized,
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+impl Backend {
+    pub const ALL: &'static [Backend] = &[
+        Backend::Interpreter,
+        Backend::Bytecode,
+        Backend::Vectorized,
+        #[cfg(feature = "jit")]
+        Backend::Jit,
+    ];
+
+    /// The name shown in the user interface.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Interpreter => "Interpreter",
+            Backend::Bytecode => "Bytecode VM",
+            Backend::Vectorized => "Vectorized",
+            #[cfg(feature = "jit")]
+            Backend::Jit => "Cranelift JIT",
+        }
+    }
+
+    /// The name used to select the backend from the command line.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Backend::Interpreter => "interpreter",
+            Backend::Bytecode => "bytecode",
+            Backend::Vectorized => "vectorized",
+            #[cfg(feature = "jit")]
+            Backend::Jit => "jit",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Backend> {
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.id() == id)
+    }
+
+    /// Creates a new, empty ExecutionContext for this backend.
+    pub fn create(&self) -> Box<dyn ExecutionContext> {
+        match self {
+            Backend::Interpreter => Box::new(Interpreter::default()),
+            Backend::Bytecode => Box::new(Vm::default()),
+            Backend::Vectorized => Box::new(Batch::default()),
+            #[cfg(feature = "jit")]
+            Backend::Jit => Box::new(Jit::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: Grid = Grid {
+        x_size: 5,
+        y_size: 4,
+    };
+
+    /// Everything a backend produces for an expression, for comparing with the interpreter.
+    #[derive(Debug)]
+    struct Run {
+        points: Vec<[f64; 5]>,
+        encountered_error: bool,
+        errors: Vec<Vec<Diagnostic>>,
+        variables: Vec<Vec<(String, f64)>>,
+    }
+
+    fn run(backend: Backend, expression: &str) -> Result<Run, Box<Diagnostic>> {
+        let mut context = backend.create();
+
+        if let Err(errors) = context.load(expression.to_string()) {
+            panic!("{expression:?} doesn't parse: {errors:?}");
+        }
+
+        let (points, encountered_error) = context.execute(GRID, 12.5, 2.0)?;
+        let mut errors = vec![];
+        let mut variables = vec![];
+
+        for index in 0..GRID.count() {
+            errors.push(context.retrieve_errors(index).unwrap_or_default());
+
+            let mut sorted: Vec<(String, f64)> = context
+                .retrieve_variables(index)
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            sorted.sort_by(|a, b| a.0.cmp(&b.0));
+            variables.push(sorted);
+        }
+
+        Ok(Run {
+            points: points
+                .iter()
+                .map(|point| [point.x, point.y, point.h, point.s, point.v])
+                .collect(),
+            encountered_error,
+            errors,
+            variables,
+        })
+    }
+
+    // NaN from dividing zero by zero has to compare equal to itself
+    fn same_value(a: f64, b: f64) -> bool {
+        a == b || (a.is_nan() && b.is_nan())
+    }
+
+    fn same_errors(a: &[Diagnostic], b: &[Diagnostic]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is_same_as(b))
+    }
+
+    /// Runs an expression with every backend, checking they all give the same result as the
+    /// interpreter, which is returned.
+    fn assert_backends_agree(expression: &str) -> Result<Run, Box<Diagnostic>> {
+        let expected = run(Backend::Interpreter, expression);
+
+        for &backend in Backend::ALL {
+            let actual = run(backend, expression);
+            let context = format!("{} running {expression:?}", backend.name());
+
+            let (expected, actual) = match (&expected, &actual) {
+                (Ok(expected), Ok(actual)) => (expected, actual),
+                (Err(expected), Err(actual)) => {
+                    assert!(expected.is_same_as(actual), "{context}: {actual:?}");
+                    continue;
+                }
+                _ => panic!("{context}: expected {expected:?}, got {actual:?}"),
+            };
+
+            assert_eq!(expected.points.len(), actual.points.len(), "{context}");
+
+            for (expected, actual) in expected.points.iter().zip(actual.points.iter()) {
+                assert!(
+                    expected.iter().zip(actual).all(|(a, b)| same_value(*a, *b)),
+                    "{context}: expected {expected:?}, got {actual:?}"
+                );
+            }
+
+            assert_eq!(
+                expected.encountered_error, actual.encountered_error,
+                "{context}"
+            );
+
+            for (expected, actual) in expected.errors.iter().zip(actual.errors.iter()) {
+                assert!(
+                    same_errors(expected, actual),
+                    "{context}: expected {expected:?}, got {actual:?}"
+                );
+            }
+
+            for (expected, actual) in expected.variables.iter().zip(actual.variables.iter()) {
+                let same = expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(actual)
+                        .all(|(a, b)| a.0 == b.0 && same_value(a.1, b.1));
+
+                assert!(same, "{context}: expected {expected:?}, got {actual:?}");
+            }
+        }
+
+        expected
+    }
+
+    /// The codes of the errors at each index. An index that runs into an error can report more than
+    /// one, since whatever uses the assignment that failed fails too.
+    fn error_codes(run: &Run) -> Vec<Vec<&str>> {
+        run.errors
+            .iter()
+            .map(|errors| errors.iter().map(|error| error.code.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn arithmetic_and_builtins() {
+        assert_backends_agree(
+            "a = x * 2 + y / 3 - index % 4;\n\
+             b = -a ^ 2;\n\
+             x' = sin(a) + cos(b) * atan2(x, y);\n\
+             y' = lerp(fraction, min(a, b), max(a, b));\n\
+             h = sqrt(abs(a)) + floor(b) + ceil(a) + round(b);\n\
+             s = time + projectionTime + projectionStartTime + pi + tau + count;",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn comparisons_and_if_branches() {
+        assert_backends_agree(
+            "a = if(x > 0, x, -x);\n\
+             b = if(y <= 0 & !(index == 3), 1, if(a >= 2 | y < -50, 2, 3));\n\
+             x' = a;\n\
+             y' = b;",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn variables_assigned_only_in_some_indexes() {
+        // `b` is only ever assigned a value, so every index has it, but `a` is reassigned
+        assert_backends_agree("a = 1;\nb = if(x > 0, a, 2);\na = a + b;\nx' = a;").unwrap();
+    }
+
+    #[test]
+    fn runtime_unknown_variable() {
+        // E2 only in the indexes that take the branch using `b` before it's assigned
+        let run = assert_backends_agree("a = if(x > 0, b, 1);\nb = 2;\nx' = a;").unwrap();
+        let codes = error_codes(&run);
+
+        assert!(run.encountered_error);
+        assert!(codes.iter().any(|codes| codes.contains(&"E2")), "{codes:?}");
+        assert!(codes.contains(&vec![]));
+    }
+
+    #[test]
+    fn runtime_unknown_function() {
+        let run = assert_backends_agree("a = if(y > 0, foo(1), 2);\nx' = a;").unwrap();
+        let codes = error_codes(&run);
+
+        assert!(run.encountered_error);
+        assert!(codes.iter().any(|codes| codes.contains(&"E3")), "{codes:?}");
+        assert!(codes.contains(&vec![]));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_backends_agree("x' = 1 / (x - x);\ny' = 0 / (y - y);\nh = -1 / 0;\ns = 5 % 0;")
+            .unwrap();
+    }
+
+    #[test]
+    fn rand_is_in_range() {
+        // the values themselves are random, so only what's done with them can be compared
+        let run =
+            assert_backends_agree("x' = floor(rand());\ny' = rand() >= 0 & rand() < 1;").unwrap();
+
+        assert!(run
+            .points
+            .iter()
+            .all(|point| point[0] == 0.0 && point[1] == 1.0));
+    }
 }
@@ -1,4 +1,8 @@
-#[derive(Debug)]
+use serde::Serialize;
+
+/// The outputs of an expression for a single index. Outputs the expression didn't assign are
+/// given their default values.
+#[derive(Debug, Clone, Serialize)]
 pub struct CalcuatedPoint {
     pub x: f64,
     pub y: f64,
@@ -0,0 +1,143 @@
+use super::data::CalcuatedPoint;
+use super::resolve::{self, Frame, Resolution};
+use super::{run, EvalContext};
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::Assignment;
+use rayon::prelude::*;
+
+/// A rectangular grid of lasers, the same shape the render tab draws.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub x_size: u16,
+    pub y_size: u16,
+}
+
+/// Everything produced by running an expression once for every index in a grid.
+pub struct GridResult {
+    pub points: Vec<CalcuatedPoint>,
+    pub errors: Vec<Vec<Diagnostic>>,
+    /// The value of every slot for each index. [`Resolution::variables`] maps them back to names.
+    pub frames: Vec<Frame>,
+}
+
+impl Grid {
+    pub fn count(&self) -> u16 {
+        self.x_size * self.y_size
+    }
+
+    /// Builds the inputs shared by every index, for the given time and projection start time
+    /// (both in seconds).
+    pub fn base_context(&self, time: f64, projection_start_time: f64) -> EvalContext {
+        EvalContext {
+            x: 0.0,
+            y: 0.0,
+            index: 0.0,
+            count: self.count() as f64,
+            fraction: 0.0,
+            pi: std::f64::consts::PI,
+            tau: std::f64::consts::TAU,
+            time,
+            projection_time: time - projection_start_time,
+            projection_start_time,
+        }
+    }
+
+    /// Fills in the per-index inputs (`x`, `y`, `index` and `fraction`) of a base context.
+    pub fn context_for(&self, base_ctx: EvalContext, index: u16) -> EvalContext {
+        let mut ctx = base_ctx;
+
+        let f_index = index as f64;
+        let x_size = self.x_size as f64;
+        let y_size = self.y_size as f64;
+
+        ctx.index = f_index;
+        ctx.x = -100.0 + (f_index % x_size) * (200.0 / (x_size - 1.0));
+        ctx.y = 100.0 - (200.0 / (y_size - 1.0)) * f64::floor(f_index / x_size);
+        ctx.fraction = f_index / (ctx.count - 1.0);
+
+        ctx
+    }
+
+    /// Runs the assignments for every index in the grid, in parallel.
+    pub fn run(
+        &self,
+        assignments: &[Assignment],
+        time: f64,
+        projection_start_time: f64,
+    ) -> GridResult {
+        let resolution = resolve::resolve(assignments);
+
+        self.run_resolved(&resolution, time, projection_start_time)
+    }
+
+    /// Runs assignments that have already been resolved for every index in the grid, in
+    /// parallel.
+    pub fn run_resolved(
+        &self,
+        resolution: &Resolution,
+        time: f64,
+        projection_start_time: f64,
+    ) -> GridResult {
+        let base_ctx = self.base_context(time, projection_start_time);
+
+        let results: Vec<(Frame, Vec<Diagnostic>)> = (0..self.count())
+            .into_par_iter()
+            .map(|index| {
+                let ctx = self.context_for(base_ctx, index);
+                let mut frame = resolution.frame();
+
+                let errors = run(resolution, &mut frame, ctx);
+                (frame, errors)
+            })
+            .collect();
+
+        let points = results
+            .par_iter()
+            .enumerate()
+            .map(|(index, (frame, _errors))| resolution.point(frame, index as u16))
+            .collect();
+
+        let mut errors = Vec::with_capacity(results.len());
+        let mut frames = Vec::with_capacity(results.len());
+
+        for (frame, index_errors) in results {
+            errors.push(index_errors);
+            frames.push(frame);
+        }
+
+        GridResult {
+            points,
+            errors,
+            frames,
+        }
+    }
+}
+
+/// Checks that two programs produce the same points, for testing changes to a program that
+/// shouldn't change what it does. Programs using `rand` can't be compared this way.
+#[cfg(test)]
+pub(crate) fn assert_same_points(expected: &[Assignment], actual: &[Assignment]) {
+    let grid = Grid {
+        x_size: 5,
+        y_size: 4,
+    };
+
+    let expected = grid.run(expected, 12.5, 2.0).points;
+    let actual = grid.run(actual, 12.5, 2.0).points;
+
+    // NaN from dividing zero by zero has to compare equal to itself
+    let same = |a: f64, b: f64| a == b || (a.is_nan() && b.is_nan());
+
+    assert_eq!(expected.len(), actual.len());
+
+    for (expected, actual) in expected.iter().zip(actual.iter()) {
+        assert!(
+            same(expected.x, actual.x)
+                && same(expected.y, actual.y)
+                && same(expected.h, actual.h)
+                && same(expected.s, actual.s)
+                && same(expected.v, actual.v),
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+}
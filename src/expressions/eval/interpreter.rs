@@ -0,0 +1,70 @@
+use super::context::{ExecutionContext, RetrievalError};
+use super::data::CalcuatedPoint;
+use super::grid::Grid;
+use super::resolve::{self, Frame, Resolution};
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::{self, Assignment};
+use ahash::AHashMap;
+
+/// An ExecutionContext that walks the syntax tree directly, using [`super::run`]. Variables are
+/// resolved to slots when the expression is loaded.
+#[derive(Default)]
+pub struct Interpreter {
+    resolution: Resolution,
+    errors: Vec<Vec<Diagnostic>>,
+    frames: Vec<Frame>,
+}
+
+impl ExecutionContext for Interpreter {
+    fn load(&mut self, expression: String) -> Result<(), Vec<Diagnostic>> {
+        self.errors = vec![];
+        self.frames = vec![];
+
+        match parser::parse(&expression) {
+            Ok(assignments) => {
+                self.load_parsed(assignments);
+                Ok(())
+            }
+            Err(errors) => {
+                self.resolution = Resolution::default();
+                Err(errors)
+            }
+        }
+    }
+
+    fn load_parsed(&mut self, assignments: Vec<Assignment>) {
+        self.errors = vec![];
+        self.frames = vec![];
+        self.resolution = resolve::resolve(&assignments);
+    }
+
+    fn execute(
+        &mut self,
+        grid: Grid,
+        time: f64,
+        projection_start_time: f64,
+    ) -> Result<(Vec<CalcuatedPoint>, bool), Box<Diagnostic>> {
+        let result = grid.run_resolved(&self.resolution, time, projection_start_time);
+
+        self.errors = result.errors;
+        self.frames = result.frames;
+
+        let encountered_error = self.errors.iter().any(|errors| errors.len() > 0);
+
+        Ok((result.points, encountered_error))
+    }
+
+    fn retrieve_errors(&mut self, i: u16) -> Result<Vec<Diagnostic>, RetrievalError> {
+        match self.errors.get(i as usize) {
+            Some(errors) => Ok(errors.clone()),
+            None => Err(RetrievalError::MissingValue),
+        }
+    }
+
+    fn retrieve_variables(&mut self, i: u16) -> Result<AHashMap<String, f64>, RetrievalError> {
+        match self.frames.get(i as usize) {
+            Some(frame) => Ok(self.resolution.variables(frame)),
+            None => Err(RetrievalError::MissingValue),
+        }
+    }
+}
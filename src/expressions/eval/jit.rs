@@ -0,0 +1,597 @@
+use super::builtin::Builtin;
+use super::context::{ExecutionContext, RetrievalError};
+use super::data::CalcuatedPoint;
+use super::grid::Grid;
+use super::resolve::{self, Frame, Resolution, ResolvedExpr, INPUT_NAMES};
+use super::run;
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::{self, Assignment, BinaryOperation, Spanned, UnaryOperation};
+use ahash::AHashMap;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Linkage, Module};
+use rand::Rng;
+use rayon::prelude::*;
+use std::sync::Arc;
+
+/// A compiled expression. It takes the values of a [`Frame`], reads the inputs from their slots,
+/// writes the value of every user variable to theirs, and returns [`SUCCESS`] unless it ran into
+/// an error.
+type CompiledFunction = unsafe extern "C" fn(*mut f64) -> u32;
+
+const SUCCESS: u32 = 0;
+const FAILURE: u32 = 1;
+
+// builtins without an exact equivalent instruction call back into Rust, so their results are
+// identical to the interpreter's
+extern "C" fn builtin_sin(a: f64) -> f64 {
+    f64::sin(a)
+}
+
+extern "C" fn builtin_cos(a: f64) -> f64 {
+    f64::cos(a)
+}
+
+extern "C" fn builtin_tan(a: f64) -> f64 {
+    f64::tan(a)
+}
+
+extern "C" fn builtin_asin(a: f64) -> f64 {
+    f64::asin(a)
+}
+
+extern "C" fn builtin_acos(a: f64) -> f64 {
+    f64::acos(a)
+}
+
+extern "C" fn builtin_atan(a: f64) -> f64 {
+    f64::atan(a)
+}
+
+extern "C" fn builtin_round(a: f64) -> f64 {
+    f64::round(a)
+}
+
+extern "C" fn builtin_atan2(a: f64, b: f64) -> f64 {
+    f64::atan2(a, b)
+}
+
+extern "C" fn builtin_min(a: f64, b: f64) -> f64 {
+    f64::min(a, b)
+}
+
+extern "C" fn builtin_max(a: f64, b: f64) -> f64 {
+    f64::max(a, b)
+}
+
+extern "C" fn builtin_exponent(a: f64, b: f64) -> f64 {
+    f64::powf(a, b)
+}
+
+extern "C" fn builtin_modulo(a: f64, b: f64) -> f64 {
+    a % b
+}
+
+extern "C" fn builtin_rand() -> f64 {
+    let mut rng = rand::thread_rng();
+
+    rng.gen::<f64>()
+}
+
+/// The Rust function the compiled code calls for a builtin, or `None` for the builtins that are
+/// lowered to instructions instead.
+fn builtin_symbol(builtin: Builtin) -> Option<*const u8> {
+    let unary = |function: extern "C" fn(f64) -> f64| Some(function as *const u8);
+    let binary = |function: extern "C" fn(f64, f64) -> f64| Some(function as *const u8);
+
+    match builtin {
+        Builtin::Sin => unary(builtin_sin),
+        Builtin::Cos => unary(builtin_cos),
+        Builtin::Tan => unary(builtin_tan),
+        Builtin::Asin => unary(builtin_asin),
+        Builtin::Acos => unary(builtin_acos),
+        Builtin::Atan => unary(builtin_atan),
+        Builtin::Round => unary(builtin_round),
+        Builtin::Atan2 => binary(builtin_atan2),
+        Builtin::Min => binary(builtin_min),
+        Builtin::Max => binary(builtin_max),
+        Builtin::Rand => Some(builtin_rand as *const u8),
+        Builtin::Sqrt | Builtin::Floor | Builtin::Ceil | Builtin::Abs => None,
+        Builtin::If | Builtin::Lerp => None,
+    }
+}
+
+// `^` and `%` aren't callable by name, so they're kept apart from the other builtins
+const OPERATOR_BUILTINS: [(&str, extern "C" fn(f64, f64) -> f64); 2] =
+    [("^", builtin_exponent), ("%", builtin_modulo)];
+
+/// The machine code for an expression, along with the module that owns it.
+struct Compiled {
+    module: Option<JITModule>,
+    function: CompiledFunction,
+}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // the function pointer is dropped along with the module, so nothing can call into the
+            // freed memory afterwards
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// An ExecutionContext that compiles the expression to native code with Cranelift.
+///
+/// The compiled code only handles the case where nothing goes wrong; as soon as it would raise an
+/// error, it gives up and the interpreter runs that index instead. If the expression can't be
+/// compiled at all (for example, on an unsupported platform), every index is interpreted.
+#[derive(Default)]
+pub struct Jit {
+    resolution: Resolution,
+    compiled: Option<Compiled>,
+    errors: Vec<Vec<Diagnostic>>,
+    frames: Vec<Frame>,
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    inputs: Vec<Value>,
+    assigned: Vec<bool>,
+    functions: AHashMap<&'static str, FuncRef>,
+    failure: Block,
+}
+
+impl<'a> Translator<'a> {
+    /// Leaves the compiled code, so the interpreter can run the index instead. Anything lowered
+    /// after this is unreachable.
+    fn fail(&mut self) -> Value {
+        self.builder.ins().jump(self.failure, &[]);
+
+        let unreachable = self.builder.create_block();
+        self.builder.switch_to_block(unreachable);
+        self.builder.seal_block(unreachable);
+
+        self.builder.ins().f64const(0.0)
+    }
+
+    fn call(&mut self, function: FuncRef, arguments: &[Value]) -> Value {
+        let call = self.builder.ins().call(function, arguments);
+        self.builder.inst_results(call)[0]
+    }
+
+    /// Whether `value` is at least 1, which is how the language decides if something is true.
+    fn truthy(&mut self, value: Value) -> Value {
+        let one = self.builder.ins().f64const(1.0);
+        self.builder
+            .ins()
+            .fcmp(FloatCC::GreaterThanOrEqual, value, one)
+    }
+
+    fn boolean(&mut self, condition: Value) -> Value {
+        let one = self.builder.ins().f64const(1.0);
+        let zero = self.builder.ins().f64const(0.0);
+        self.builder.ins().select(condition, one, zero)
+    }
+
+    fn compare(&mut self, condition: FloatCC, a: Value, b: Value) -> Value {
+        let result = self.builder.ins().fcmp(condition, a, b);
+        self.boolean(result)
+    }
+
+    fn lower(&mut self, spanned_expr: &Spanned<Arc<ResolvedExpr>>) -> Value {
+        match &*spanned_expr.0 {
+            ResolvedExpr::Number(value) => self.builder.ins().f64const(*value),
+            ResolvedExpr::Group(inner) => self.lower(inner),
+            ResolvedExpr::Slot(slot) if *slot < INPUT_NAMES.len() => self.inputs[*slot],
+            // reading a variable before it has been assigned to is an E2 error
+            ResolvedExpr::Slot(slot) if self.assigned[*slot] => {
+                self.builder.use_var(Variable::new(*slot))
+            }
+            ResolvedExpr::Slot(_) | ResolvedExpr::Unassigned(_) => self.fail(),
+            ResolvedExpr::UnaryExpression(op, operand) => {
+                let value = self.lower(operand);
+
+                match op {
+                    UnaryOperation::Negate => self.builder.ins().fneg(value),
+                    UnaryOperation::Not => {
+                        let condition = self.truthy(value);
+                        let zero = self.builder.ins().f64const(0.0);
+                        let one = self.builder.ins().f64const(1.0);
+                        self.builder.ins().select(condition, zero, one)
+                    }
+                }
+            }
+            ResolvedExpr::BinaryExpression(left, BinaryOperation::And, right) => {
+                self.lower_logical(left, right, false)
+            }
+            ResolvedExpr::BinaryExpression(left, BinaryOperation::Or, right) => {
+                self.lower_logical(left, right, true)
+            }
+            ResolvedExpr::BinaryExpression(left, op, right) => {
+                let a = self.lower(left);
+                let b = self.lower(right);
+
+                match op {
+                    BinaryOperation::Add => self.builder.ins().fadd(a, b),
+                    BinaryOperation::Subtract => self.builder.ins().fsub(a, b),
+                    BinaryOperation::Multiply => self.builder.ins().fmul(a, b),
+                    BinaryOperation::Divide => self.builder.ins().fdiv(a, b),
+                    BinaryOperation::Modulo => self.call(self.functions["%"], &[a, b]),
+                    BinaryOperation::Exponent => self.call(self.functions["^"], &[a, b]),
+                    BinaryOperation::LessThan => self.compare(FloatCC::LessThan, a, b),
+                    BinaryOperation::GreaterThan => self.compare(FloatCC::GreaterThan, a, b),
+                    BinaryOperation::LessThanOrEqual => {
+                        self.compare(FloatCC::LessThanOrEqual, a, b)
+                    }
+                    BinaryOperation::GreaterThanOrEqual => {
+                        self.compare(FloatCC::GreaterThanOrEqual, a, b)
+                    }
+                    BinaryOperation::Equal => self.compare(FloatCC::Equal, a, b),
+                    BinaryOperation::And | BinaryOperation::Or => unreachable!(),
+                }
+            }
+            ResolvedExpr::Call(name, args) if name == "if" => {
+                // the argument count is checked before any of the arguments are evaluated
+                if args.len() != 3 {
+                    return self.fail();
+                }
+
+                let condition = self.lower(&args[0]);
+                let condition = self.truthy(condition);
+
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let merge_block = self.builder.create_block();
+                let result = self.builder.append_block_param(merge_block, types::F64);
+
+                self.builder
+                    .ins()
+                    .brif(condition, then_block, &[], else_block, &[]);
+                self.builder.seal_block(then_block);
+                self.builder.seal_block(else_block);
+
+                self.builder.switch_to_block(then_block);
+                let value = self.lower(&args[1]);
+                self.builder.ins().jump(merge_block, &[value]);
+
+                self.builder.switch_to_block(else_block);
+                let value = self.lower(&args[2]);
+                self.builder.ins().jump(merge_block, &[value]);
+
+                self.builder.seal_block(merge_block);
+                self.builder.switch_to_block(merge_block);
+
+                result
+            }
+            ResolvedExpr::Call(name, args) => {
+                // every argument is evaluated before the function is looked up
+                let arguments: Vec<Value> = args.iter().map(|arg| self.lower(arg)).collect();
+
+                let builtin = match Builtin::from_name(name) {
+                    Some(builtin) if builtin.arity() == arguments.len() => builtin,
+                    // either an E1 or an E3 error
+                    _ => return self.fail(),
+                };
+
+                match (builtin, arguments.as_slice()) {
+                    (Builtin::Sqrt, [a]) => self.builder.ins().sqrt(*a),
+                    (Builtin::Floor, [a]) => self.builder.ins().floor(*a),
+                    (Builtin::Ceil, [a]) => self.builder.ins().ceil(*a),
+                    (Builtin::Abs, [a]) => self.builder.ins().fabs(*a),
+                    // the same formula as `builtin::lerp`
+                    (Builtin::Lerp, [fraction, a, b]) => {
+                        let one = self.builder.ins().f64const(1.0);
+                        let inverse = self.builder.ins().fsub(one, *fraction);
+                        let a = self.builder.ins().fmul(*a, inverse);
+                        let b = self.builder.ins().fmul(*b, *fraction);
+                        self.builder.ins().fadd(a, b)
+                    }
+                    (builtin, arguments) => self.call(self.functions[builtin.name()], arguments),
+                }
+            }
+            ResolvedExpr::Error => self.fail(),
+        }
+    }
+
+    /// Lowers `&` (or `|` when `is_or` is set), which only evaluates the right hand side when
+    /// the left hand side doesn't already decide the result.
+    fn lower_logical(
+        &mut self,
+        left: &Spanned<Arc<ResolvedExpr>>,
+        right: &Spanned<Arc<ResolvedExpr>>,
+        is_or: bool,
+    ) -> Value {
+        let value = self.lower(left);
+        let condition = self.truthy(value);
+        let short_value = self.builder.ins().f64const(if is_or { 1.0 } else { 0.0 });
+
+        let right_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        let result = self.builder.append_block_param(merge_block, types::F64);
+
+        if is_or {
+            self.builder
+                .ins()
+                .brif(condition, merge_block, &[short_value], right_block, &[]);
+        } else {
+            self.builder
+                .ins()
+                .brif(condition, right_block, &[], merge_block, &[short_value]);
+        }
+        self.builder.seal_block(right_block);
+
+        self.builder.switch_to_block(right_block);
+        let value = self.lower(right);
+        let condition = self.truthy(value);
+        let value = self.boolean(condition);
+        self.builder.ins().jump(merge_block, &[value]);
+
+        self.builder.seal_block(merge_block);
+        self.builder.switch_to_block(merge_block);
+
+        result
+    }
+}
+
+fn compile(resolution: &Resolution) -> Result<Compiled, String> {
+    let mut flags = settings::builder();
+    flags
+        .set("opt_level", "speed")
+        .map_err(|err| err.to_string())?;
+
+    let isa = cranelift_native::builder()
+        .map_err(|err| err.to_string())?
+        .finish(settings::Flags::new(flags))
+        .map_err(|err| err.to_string())?;
+
+    let mut jit_builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+
+    let mut symbols = vec![];
+
+    for builtin in Builtin::ALL {
+        if let Some(symbol) = builtin_symbol(builtin) {
+            symbols.push((builtin.name(), builtin.arity(), symbol));
+        }
+    }
+    for (name, function) in OPERATOR_BUILTINS {
+        symbols.push((name, 2, function as *const u8));
+    }
+
+    for (name, _, symbol) in symbols.iter() {
+        jit_builder.symbol(format!("laser_{name}"), *symbol);
+    }
+
+    let mut module = JITModule::new(jit_builder);
+    let pointer_type = module.target_config().pointer_type();
+
+    let mut signatures = vec![];
+
+    for count in 0..=2 {
+        let mut signature = module.make_signature();
+        signature.params = vec![AbiParam::new(types::F64); count];
+        signature.returns.push(AbiParam::new(types::F64));
+        signatures.push(signature);
+    }
+
+    let mut function_ids = vec![];
+
+    for (name, count, _) in symbols {
+        let id = module
+            .declare_function(
+                &format!("laser_{name}"),
+                Linkage::Import,
+                &signatures[count],
+            )
+            .map_err(|err| err.to_string())?;
+        function_ids.push((name, id));
+    }
+
+    let mut context = module.make_context();
+    context
+        .func
+        .signature
+        .params
+        .push(AbiParam::new(pointer_type));
+    context
+        .func
+        .signature
+        .returns
+        .push(AbiParam::new(types::I32));
+
+    let mut function_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut function_context);
+
+    let mut functions = AHashMap::new();
+
+    for (name, id) in function_ids {
+        functions.insert(name, module.declare_func_in_func(id, builder.func));
+    }
+
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    builder.seal_block(entry);
+
+    let frame = builder.block_params(entry)[0];
+
+    let inputs = (0..INPUT_NAMES.len())
+        .map(|slot| {
+            builder
+                .ins()
+                .load(types::F64, MemFlags::trusted(), frame, (slot * 8) as i32)
+        })
+        .collect();
+
+    let variable_slots = INPUT_NAMES.len()..resolution.names.len();
+
+    for slot in variable_slots.clone() {
+        builder.declare_var(Variable::new(slot), types::F64);
+    }
+
+    let failure = builder.create_block();
+
+    let mut translator = Translator {
+        builder,
+        inputs,
+        assigned: vec![false; resolution.names.len()],
+        functions,
+        failure,
+    };
+
+    for assignment in resolution.assignments.iter() {
+        match assignment.slot {
+            Some(slot) => {
+                let value = translator.lower(&assignment.expression);
+
+                translator.builder.def_var(Variable::new(slot), value);
+                translator.assigned[slot] = true;
+            }
+            // assigning to a reserved name is always an E4 error
+            None => {
+                translator.fail();
+            }
+        }
+    }
+
+    let mut builder = translator.builder;
+
+    for slot in variable_slots {
+        let value = builder.use_var(Variable::new(slot));
+        builder
+            .ins()
+            .store(MemFlags::trusted(), value, frame, (slot * 8) as i32);
+    }
+
+    let status = builder.ins().iconst(types::I32, SUCCESS as i64);
+    builder.ins().return_(&[status]);
+
+    builder.switch_to_block(failure);
+    builder.seal_block(failure);
+    let status = builder.ins().iconst(types::I32, FAILURE as i64);
+    builder.ins().return_(&[status]);
+
+    builder.finalize();
+
+    let id = module
+        .declare_function("expression", Linkage::Local, &context.func.signature)
+        .map_err(|err| err.to_string())?;
+    module
+        .define_function(id, &mut context)
+        .map_err(|err| err.to_string())?;
+    module.clear_context(&mut context);
+    module
+        .finalize_definitions()
+        .map_err(|err| err.to_string())?;
+
+    let code = module.get_finalized_function(id);
+
+    Ok(Compiled {
+        module: Some(module),
+        // the function was just defined with this signature
+        function: unsafe { std::mem::transmute::<*const u8, CompiledFunction>(code) },
+    })
+}
+
+impl ExecutionContext for Jit {
+    fn load(&mut self, expression: String) -> Result<(), Vec<Diagnostic>> {
+        self.compiled = None;
+        self.errors = vec![];
+        self.frames = vec![];
+
+        match parser::parse(&expression) {
+            Ok(assignments) => {
+                self.load_parsed(assignments);
+                Ok(())
+            }
+            Err(errors) => {
+                self.resolution = Resolution::default();
+                Err(errors)
+            }
+        }
+    }
+
+    fn load_parsed(&mut self, assignments: Vec<Assignment>) {
+        self.compiled = None;
+        self.errors = vec![];
+        self.frames = vec![];
+        self.resolution = resolve::resolve(&assignments);
+
+        self.compiled = match compile(&self.resolution) {
+            Ok(compiled) => Some(compiled),
+            Err(error) => {
+                tracing::warn!(
+                    "Failed to compile expression, falling back to interpreter: {error}"
+                );
+                None
+            }
+        };
+    }
+
+    fn execute(
+        &mut self,
+        grid: Grid,
+        time: f64,
+        projection_start_time: f64,
+    ) -> Result<(Vec<CalcuatedPoint>, bool), Box<Diagnostic>> {
+        let base_ctx = grid.base_context(time, projection_start_time);
+        let function = self.compiled.as_ref().map(|compiled| compiled.function);
+        let resolution = &self.resolution;
+
+        let results: Vec<(Frame, Vec<Diagnostic>)> = (0..grid.count())
+            .into_par_iter()
+            .map(|index| {
+                let ctx = grid.context_for(base_ctx, index);
+                let mut frame = resolution.frame();
+
+                if let Some(function) = function {
+                    frame.reset(ctx);
+
+                    // the function was compiled for a frame with exactly this many slots
+                    let status = unsafe { function(frame.values.as_mut_ptr()) };
+
+                    if status == SUCCESS {
+                        // every assignment succeeded, so every variable has been assigned to
+                        frame.defined.fill(true);
+                        return (frame, vec![]);
+                    }
+                }
+
+                let errors = run(resolution, &mut frame, ctx);
+                (frame, errors)
+            })
+            .collect();
+
+        let mut points = Vec::with_capacity(results.len());
+        self.errors = Vec::with_capacity(results.len());
+        self.frames = Vec::with_capacity(results.len());
+
+        for (index, (frame, errors)) in results.into_iter().enumerate() {
+            points.push(self.resolution.point(&frame, index as u16));
+            self.errors.push(errors);
+            self.frames.push(frame);
+        }
+
+        let encountered_error = self.errors.iter().any(|errors| errors.len() > 0);
+
+        Ok((points, encountered_error))
+    }
+
+    fn retrieve_errors(&mut self, i: u16) -> Result<Vec<Diagnostic>, RetrievalError> {
+        match self.errors.get(i as usize) {
+            Some(errors) => Ok(errors.clone()),
+            None => Err(RetrievalError::MissingValue),
+        }
+    }
+
+    fn retrieve_variables(&mut self, i: u16) -> Result<AHashMap<String, f64>, RetrievalError> {
+        match self.frames.get(i as usize) {
+            Some(frame) => Ok(self.resolution.variables(frame)),
+            None => Err(RetrievalError::MissingValue),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use super::data::CalcuatedPoint;
+use super::{is_reserved, EvalContext};
+use crate::expressions::parser::{
+    Assignment, BinaryOperation, Expr, Span, Spanned, UnaryOperation,
+};
+use ahash::AHashMap;
+use std::sync::Arc;
+
+/// The index of a value in a [`Frame`].
+pub type Slot = usize;
+
+/// The inputs, in the order they are stored in the first slots of every frame.
+pub const INPUT_NAMES: [&str; 10] = [
+    "x",
+    "y",
+    "index",
+    "count",
+    "fraction",
+    "pi",
+    "tau",
+    "time",
+    "projectionTime",
+    "projectionStartTime",
+];
+
+/// The variables that make up a point, and the values they take when they aren't assigned to.
+pub const OUTPUT_NAMES: [&str; 5] = ["x'", "y'", "h", "s", "v"];
+pub const OUTPUT_DEFAULTS: [f64; 5] = [0.0, 0.0, 0.0, 0.0, 1.0];
+
+/// The values of the inputs of a context, in the same order as [`INPUT_NAMES`].
+pub fn inputs(ctx: EvalContext) -> [f64; 10] {
+    [
+        ctx.x,
+        ctx.y,
+        ctx.index,
+        ctx.count,
+        ctx.fraction,
+        ctx.pi,
+        ctx.tau,
+        ctx.time,
+        ctx.projection_time,
+        ctx.projection_start_time,
+    ]
+}
+
+/// Lists every user variable in the order they are first assigned to. Assignments to reserved
+/// names are left out, since they never store anything.
+pub fn variable_names(assignments: &[Assignment]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+
+    for assignment in assignments {
+        let reserved = is_reserved(&assignment.name);
+
+        if !reserved && !names.contains(&assignment.name) {
+            names.push(assignment.name.clone());
+        }
+    }
+
+    names
+}
+
+/// A node in an expression's syntax tree, with every variable replaced by the slot it's stored in.
+#[derive(Debug, Clone)]
+pub enum ResolvedExpr {
+    // Data Types
+    Number(f64),
+    Slot(Slot),
+    /// A variable that is never assigned to, so reading it always fails.
+    Unassigned(String),
+    Group(Spanned<Arc<ResolvedExpr>>),
+
+    // Expressions
+    BinaryExpression(
+        Spanned<Arc<ResolvedExpr>>,
+        BinaryOperation,
+        Spanned<Arc<ResolvedExpr>>,
+    ),
+    UnaryExpression(UnaryOperation, Spanned<Arc<ResolvedExpr>>),
+
+    // Function Call
+    Call(String, Vec<Spanned<Arc<ResolvedExpr>>>),
+
+    // Error
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedAssignment {
+    pub name: String,
+    /// Where the result is stored, or `None` if the name is reserved.
+    pub slot: Option<Slot>,
+    pub expression: Spanned<Arc<ResolvedExpr>>,
+    pub span: Span,
+}
+
+/// The assignments of an expression with every variable resolved to a slot, along with the name
+/// of each slot.
+///
+/// The inputs take up the first slots, in the order of [`INPUT_NAMES`], followed by the user
+/// variables in the order they are first assigned to.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub assignments: Vec<ResolvedAssignment>,
+    pub names: Vec<String>,
+    outputs: [Option<Slot>; 5],
+}
+
+/// An expression without any assignments, which still has a slot for every input.
+impl Default for Resolution {
+    fn default() -> Self {
+        resolve(&[])
+    }
+}
+
+/// The value of every slot for a single index.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub values: Vec<f64>,
+    pub defined: Vec<bool>,
+}
+
+impl Frame {
+    /// Fills in the inputs and forgets the value of every variable, ready to run a new index.
+    pub fn reset(&mut self, ctx: EvalContext) {
+        self.values[..INPUT_NAMES.len()].copy_from_slice(&inputs(ctx));
+        self.defined.fill(false);
+        self.defined[..INPUT_NAMES.len()].fill(true);
+    }
+
+    /// The value of a slot, or `None` if it hasn't been assigned to yet.
+    pub fn get(&self, slot: Slot) -> Option<f64> {
+        if self.defined[slot] {
+            Some(self.values[slot])
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, slot: Slot, value: f64) {
+        self.values[slot] = value;
+        self.defined[slot] = true;
+    }
+}
+
+fn resolve_expr(
+    spanned_expr: &Spanned<Arc<Expr>>,
+    slots: &AHashMap<&str, Slot>,
+) -> Spanned<Arc<ResolvedExpr>> {
+    let resolved = match &*spanned_expr.0 {
+        Expr::Number(value) => ResolvedExpr::Number(*value),
+        Expr::Variable(name) => match slots.get(name.as_str()) {
+            Some(slot) => ResolvedExpr::Slot(*slot),
+            None => ResolvedExpr::Unassigned(name.clone()),
+        },
+        Expr::Group(inner) => ResolvedExpr::Group(resolve_expr(inner, slots)),
+        Expr::BinaryExpression(left, op, right) => ResolvedExpr::BinaryExpression(
+            resolve_expr(left, slots),
+            op.clone(),
+            resolve_expr(right, slots),
+        ),
+        Expr::UnaryExpression(op, operand) => {
+            ResolvedExpr::UnaryExpression(op.clone(), resolve_expr(operand, slots))
+        }
+        Expr::Call(name, args) => ResolvedExpr::Call(
+            name.clone(),
+            args.iter().map(|arg| resolve_expr(arg, slots)).collect(),
+        ),
+        Expr::Error => ResolvedExpr::Error,
+    };
+
+    (Arc::new(resolved), spanned_expr.1.clone())
+}
+
+/// Gives every input and user variable a slot, and resolves each variable in the assignments to
+/// its slot.
+pub fn resolve(assignments: &[Assignment]) -> Resolution {
+    let mut names: Vec<String> = INPUT_NAMES.iter().map(|name| name.to_string()).collect();
+    names.extend(variable_names(assignments));
+
+    let slots: AHashMap<&str, Slot> = names
+        .iter()
+        .enumerate()
+        .map(|(slot, name)| (name.as_str(), slot))
+        .collect();
+
+    let resolved = assignments
+        .iter()
+        .map(|assignment| ResolvedAssignment {
+            name: assignment.name.clone(),
+            slot: if is_reserved(&assignment.name) {
+                None
+            } else {
+                Some(slots[assignment.name.as_str()])
+            },
+            expression: resolve_expr(&assignment.expression, &slots),
+            span: assignment.span.clone(),
+        })
+        .collect();
+
+    let outputs = OUTPUT_NAMES.map(|name| slots.get(name).copied());
+
+    Resolution {
+        assignments: resolved,
+        names,
+        outputs,
+    }
+}
+
+impl Resolution {
+    /// Creates an empty frame with room for every slot.
+    pub fn frame(&self) -> Frame {
+        Frame {
+            values: vec![0.0; self.names.len()],
+            defined: vec![false; self.names.len()],
+        }
+    }
+
+    /// Maps the user variables that have been assigned to in a frame back to their names, for the
+    /// variable inspector.
+    pub fn variables(&self, frame: &Frame) -> AHashMap<String, f64> {
+        let mut variables = AHashMap::new();
+
+        for slot in INPUT_NAMES.len()..self.names.len() {
+            if let Some(value) = frame.get(slot) {
+                variables.insert(self.names[slot].clone(), value);
+            }
+        }
+
+        variables
+    }
+
+    /// Reads the outputs of a frame, falling back to their default values.
+    pub fn point(&self, frame: &Frame, index: u16) -> CalcuatedPoint {
+        let mut outputs = OUTPUT_DEFAULTS;
+
+        for (output, slot) in outputs.iter_mut().zip(self.outputs.iter()) {
+            if let Some(value) = slot.and_then(|slot| frame.get(slot)) {
+                *output = value;
+            }
+        }
+
+        let [x, y, h, s, v] = outputs;
+
+        CalcuatedPoint {
+            x,
+            y,
+            h,
+            s,
+            v,
+            index,
+        }
+    }
+}
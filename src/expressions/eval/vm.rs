@@ -0,0 +1,140 @@
+use super::bytecode::{self, Program};
+use super::context::{ExecutionContext, RetrievalError};
+use super::data::CalcuatedPoint;
+use super::grid::Grid;
+use crate::expressions::errors::Diagnostic;
+use crate::expressions::parser::{self, Assignment};
+use ahash::AHashMap;
+use rayon::prelude::*;
+
+/// An ExecutionContext that compiles the expression to bytecode once when it is loaded, and runs
+/// it on a register machine for every index.
+#[derive(Default)]
+pub struct Vm {
+    program: Option<Program>,
+    errors: Vec<Vec<Diagnostic>>,
+    // the values of every user variable, and whether it was assigned, for each index in turn
+    values: Vec<f64>,
+    defined: Vec<bool>,
+}
+
+impl ExecutionContext for Vm {
+    fn load(&mut self, expression: String) -> Result<(), Vec<Diagnostic>> {
+        self.errors = vec![];
+        self.values = vec![];
+        self.defined = vec![];
+
+        match parser::parse(&expression) {
+            Ok(assignments) => {
+                self.load_parsed(assignments);
+                Ok(())
+            }
+            Err(errors) => {
+                self.program = None;
+                Err(errors)
+            }
+        }
+    }
+
+    fn load_parsed(&mut self, assignments: Vec<Assignment>) {
+        self.errors = vec![];
+        self.values = vec![];
+        self.defined = vec![];
+        self.program = Some(bytecode::compile(&assignments));
+    }
+
+    fn execute(
+        &mut self,
+        grid: Grid,
+        time: f64,
+        projection_start_time: f64,
+    ) -> Result<(Vec<CalcuatedPoint>, bool), Box<Diagnostic>> {
+        let program = match &self.program {
+            Some(program) => program,
+            None => return Ok((vec![], false)),
+        };
+
+        let base_ctx = grid.base_context(time, projection_start_time);
+        let variable_count = program.variable_names.len();
+
+        let results: Vec<(CalcuatedPoint, Vec<f64>, Vec<bool>, Vec<Diagnostic>)> = (0..grid
+            .count())
+            .into_par_iter()
+            .map_init(
+                || (program.registers(), vec![false; variable_count]),
+                |(registers, defined), index| {
+                    let mut errors = vec![];
+
+                    program.run(
+                        grid.context_for(base_ctx, index),
+                        registers,
+                        defined,
+                        &mut errors,
+                    );
+
+                    let [x, y, h, s, v] = program.outputs(registers, defined);
+                    let start = program.variable_register(0);
+
+                    (
+                        CalcuatedPoint {
+                            x,
+                            y,
+                            h,
+                            s,
+                            v,
+                            index,
+                        },
+                        registers[start..start + variable_count].to_vec(),
+                        defined.clone(),
+                        errors,
+                    )
+                },
+            )
+            .collect();
+
+        let mut points = Vec::with_capacity(results.len());
+        self.errors = Vec::with_capacity(results.len());
+        self.values = Vec::with_capacity(results.len() * variable_count);
+        self.defined = Vec::with_capacity(results.len() * variable_count);
+
+        for (point, values, defined, errors) in results {
+            points.push(point);
+            self.values.extend(values);
+            self.defined.extend(defined);
+            self.errors.push(errors);
+        }
+
+        let encountered_error = self.errors.iter().any(|errors| errors.len() > 0);
+
+        Ok((points, encountered_error))
+    }
+
+    fn retrieve_errors(&mut self, i: u16) -> Result<Vec<Diagnostic>, RetrievalError> {
+        match self.errors.get(i as usize) {
+            Some(errors) => Ok(errors.clone()),
+            None => Err(RetrievalError::MissingValue),
+        }
+    }
+
+    fn retrieve_variables(&mut self, i: u16) -> Result<AHashMap<String, f64>, RetrievalError> {
+        let program = match &self.program {
+            Some(program) => program,
+            None => return Err(RetrievalError::MissingValue),
+        };
+
+        if i as usize >= self.errors.len() {
+            return Err(RetrievalError::MissingValue);
+        }
+
+        let start = i as usize * program.variable_names.len();
+        let mut variables = AHashMap::new();
+
+        for (variable, name) in program.variable_names.iter().enumerate() {
+            if self.defined[start + variable] {
+                variables.insert(name.clone(), self.values[start + variable]);
+            }
+        }
+
+        Ok(variables)
+    }
+}
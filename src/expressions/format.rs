@@ -0,0 +1,284 @@
+use super::errors::Diagnostic;
+use super::lexer::{lex, TokenKind};
+use super::parser::{self, Assignment, BinaryOperation, Expr, UnaryOperation};
+use super::source_map::SourceMap;
+use serde::{Deserialize, Serialize};
+
+// binding strength of each kind of expression, used to decide where parentheses are needed
+const OR_AND: u8 = 1;
+const COMPARISON: u8 = 2;
+const ADDITIVE: u8 = 3;
+pub(crate) const MULTIPLICATIVE: u8 = 4;
+pub(crate) const EXPONENT: u8 = 5;
+pub(crate) const UNARY: u8 = 6;
+pub(crate) const ATOM: u8 = 7;
+
+/// The choices the formatter makes that people tend to disagree on. Projects store their own
+/// style, and missing fields fall back to the defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatStyle {
+    /// Put a space on either side of `=` and every binary operator.
+    pub spaces_around_operators: bool,
+    /// Put a space after the comma between function arguments.
+    pub space_after_comma: bool,
+    /// Keep parentheses from the source, even where operator precedence doesn't need them.
+    pub keep_parentheses: bool,
+    /// The most blank lines kept in a row between assignments and comments.
+    pub max_blank_lines: u8,
+}
+
+impl Default for FormatStyle {
+    fn default() -> Self {
+        Self {
+            spaces_around_operators: true,
+            space_after_comma: true,
+            keep_parentheses: true,
+            max_blank_lines: 1,
+        }
+    }
+}
+
+/// Prints a parsed program in the default style, with one assignment per line.
+///
+/// Comments aren't part of the parser's output, so they are not preserved; use [`format_source`]
+/// to keep them.
+pub fn format(assignments: &[Assignment]) -> String {
+    let style = FormatStyle::default();
+    let mut output = String::new();
+
+    for assignment in assignments {
+        write_assignment(assignment, &style, &mut output);
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Prints a single expression, adding parentheses wherever they are needed to keep its meaning.
+pub fn format_expression(expr: &Expr) -> String {
+    let mut output = String::new();
+    write_expr(expr, &FormatStyle::default(), &mut output);
+    output
+}
+
+/// A comment in the source text, with the line it's on.
+struct Comment {
+    /// The character offset of the `#`.
+    start: usize,
+    line: usize,
+    text: String,
+}
+
+/// Parses source text and prints it with one assignment per line, keeping its comments.
+///
+/// Comments on their own line stay above the assignment that follows them, and comments after an
+/// assignment stay at the end of its line. Blank lines between assignments are kept, up to the
+/// style's limit. Returns the parser's errors if the text doesn't parse, since there would be no
+/// way to tell what the broken parts were meant to look like.
+pub fn format_source(text: &str, style: &FormatStyle) -> Result<String, Vec<Diagnostic>> {
+    let assignments = parser::parse(text)?;
+    let source_map = SourceMap::new(text);
+    let chars: Vec<char> = text.chars().collect();
+
+    // an assignment's span can start with the whitespace before it
+    let first_line = |assignment: &Assignment| {
+        let start = (assignment.span.start..assignment.span.end)
+            .find(|&offset| !chars[offset].is_whitespace())
+            .unwrap_or(assignment.span.start);
+
+        source_map.line_of_char(start)
+    };
+    let last_line =
+        |assignment: &Assignment| source_map.line_of_char(assignment.span.end.saturating_sub(1));
+
+    let mut comments = lex(text)
+        .into_iter()
+        .filter(|token| token.kind == TokenKind::Comment)
+        .map(|token| Comment {
+            start: token.span.start,
+            line: source_map.line_of_char(token.span.start),
+            text: token.text.trim_end().to_string(),
+        })
+        .peekable();
+    let mut output = String::new();
+    let mut previous_line: Option<usize> = None;
+
+    let write_gap = |line: usize, previous_line: &mut Option<usize>, output: &mut String| {
+        if let Some(previous) = *previous_line {
+            let blank_lines = line.saturating_sub(previous + 1);
+
+            for _ in 0..blank_lines.min(style.max_blank_lines as usize) {
+                output.push('\n');
+            }
+        }
+
+        *previous_line = Some(line);
+    };
+
+    for (i, assignment) in assignments.iter().enumerate() {
+        while let Some(comment) = comments.next_if(|comment| comment.start < assignment.span.start)
+        {
+            write_gap(comment.line, &mut previous_line, &mut output);
+            output.push_str(&comment.text);
+            output.push('\n');
+        }
+
+        write_gap(first_line(assignment), &mut previous_line, &mut output);
+        write_assignment(assignment, style, &mut output);
+
+        let line = last_line(assignment);
+        previous_line = Some(line);
+
+        // a comment only belongs at the end of the line if no other assignment comes before it
+        let next_start = assignments.get(i + 1).map(|next| next.span.start);
+
+        if let Some(comment) = comments.next_if(|comment| {
+            comment.line == line && next_start.is_none_or(|start| comment.start < start)
+        }) {
+            output.push(' ');
+            output.push_str(&comment.text);
+        }
+
+        output.push('\n');
+    }
+
+    for comment in comments {
+        write_gap(comment.line, &mut previous_line, &mut output);
+        output.push_str(&comment.text);
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+fn write_assignment(assignment: &Assignment, style: &FormatStyle, output: &mut String) {
+    output.push_str(&assignment.name);
+    output.push_str(if style.spaces_around_operators {
+        " = "
+    } else {
+        "="
+    });
+    write_expr(&assignment.expression.0, style, output);
+    output.push(';');
+}
+
+pub fn binary_operator(op: &BinaryOperation) -> &'static str {
+    match op {
+        BinaryOperation::Add => "+",
+        BinaryOperation::Subtract => "-",
+        BinaryOperation::Multiply => "*",
+        BinaryOperation::Divide => "/",
+        BinaryOperation::Exponent => "^",
+        BinaryOperation::Modulo => "%",
+        BinaryOperation::LessThan => "<",
+        BinaryOperation::GreaterThan => ">",
+        BinaryOperation::LessThanOrEqual => "<=",
+        BinaryOperation::GreaterThanOrEqual => ">=",
+        BinaryOperation::Equal => "==",
+        BinaryOperation::And => "&",
+        BinaryOperation::Or => "|",
+    }
+}
+
+pub fn unary_operator(op: &UnaryOperation) -> &'static str {
+    match op {
+        UnaryOperation::Negate => "-",
+        UnaryOperation::Not => "!",
+    }
+}
+
+pub(crate) fn binary_precedence(op: &BinaryOperation) -> u8 {
+    match op {
+        BinaryOperation::And | BinaryOperation::Or => OR_AND,
+        BinaryOperation::LessThan
+        | BinaryOperation::GreaterThan
+        | BinaryOperation::LessThanOrEqual
+        | BinaryOperation::GreaterThanOrEqual
+        | BinaryOperation::Equal => COMPARISON,
+        BinaryOperation::Add | BinaryOperation::Subtract => ADDITIVE,
+        BinaryOperation::Multiply | BinaryOperation::Divide | BinaryOperation::Modulo => {
+            MULTIPLICATIVE
+        }
+        BinaryOperation::Exponent => EXPONENT,
+    }
+}
+
+fn precedence(expr: &Expr, style: &FormatStyle) -> u8 {
+    match expr {
+        // parentheses that aren't kept are added back wherever the contents need them
+        Expr::Group(inner) if !style.keep_parentheses => precedence(&inner.0, style),
+        Expr::BinaryExpression(_, op, _) => binary_precedence(op),
+        Expr::UnaryExpression(_, _) => UNARY,
+        // a negative literal is printed with a leading minus, so it behaves like a negation
+        Expr::Number(value) if value.is_sign_negative() => UNARY,
+        _ => ATOM,
+    }
+}
+
+fn write_operand(
+    expr: &Expr,
+    parent: u8,
+    right_side: bool,
+    style: &FormatStyle,
+    output: &mut String,
+) {
+    let own = precedence(expr, style);
+
+    // every binary operator is left associative, so an operand of equal strength on the right
+    // hand side only exists if it was grouped in the source
+    if own < parent || (right_side && own == parent) {
+        output.push('(');
+        write_expr(expr, style, output);
+        output.push(')');
+    } else {
+        write_expr(expr, style, output);
+    }
+}
+
+fn write_expr(expr: &Expr, style: &FormatStyle, output: &mut String) {
+    match expr {
+        Expr::Number(value) => output.push_str(&value.to_string()),
+        Expr::Variable(name) => output.push_str(name),
+        Expr::Group(inner) if !style.keep_parentheses => write_expr(&inner.0, style, output),
+        Expr::Group(inner) => {
+            output.push('(');
+            write_expr(&inner.0, style, output);
+            output.push(')');
+        }
+        Expr::BinaryExpression(left, op, right) => {
+            let own = binary_precedence(op);
+
+            write_operand(&left.0, own, false, style, output);
+
+            if style.spaces_around_operators {
+                output.push(' ');
+                output.push_str(binary_operator(op));
+                output.push(' ');
+            } else {
+                output.push_str(binary_operator(op));
+            }
+
+            write_operand(&right.0, own, true, style, output);
+        }
+        Expr::UnaryExpression(op, operand) => {
+            output.push_str(unary_operator(op));
+            write_operand(&operand.0, UNARY, false, style, output);
+        }
+        Expr::Call(name, args) => {
+            output.push_str(name);
+            output.push('(');
+
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(if style.space_after_comma { ", " } else { "," });
+                }
+                write_expr(&arg.0, style, output);
+            }
+
+            output.push(')');
+        }
+        // only produced alongside a parser error, so there's nothing meaningful to print
+        Expr::Error => output.push('?'),
+    }
+}
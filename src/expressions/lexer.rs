@@ -0,0 +1,155 @@
+use super::parser::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// An integer or decimal, like `12`, `1.5` or `.5`.
+    Number,
+    /// A variable or function name, which may end with a `'` (like `x'`).
+    Identifier,
+
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    EqualEqual,
+    Ampersand,
+    Pipe,
+    Bang,
+
+    /// The `=` of an assignment.
+    Equals,
+    Comma,
+    Semicolon,
+    LeftParen,
+    RightParen,
+
+    /// A `#` and the rest of its line, not including the line break.
+    Comment,
+    Whitespace,
+    /// A character that can't start any other token.
+    Unknown,
+}
+
+impl TokenKind {
+    /// Whether the token has no meaning to the parser.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Comment | TokenKind::Whitespace)
+    }
+}
+
+/// A piece of source text. Spans count characters, like the parser's.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+    pub text: String,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits source text into tokens, including whitespace and comments. Every character ends up in
+/// exactly one token, so joining the text of the tokens gives back the source.
+///
+/// Tokens follow the same rules as [`super::parser::parser`]: an integer is either `0` or doesn't
+/// start with `0`, and a decimal point must be followed by at least one digit.
+pub fn lex(text: &str) -> Vec<Token> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut start = 0;
+
+    while start < chars.len() {
+        let c = chars[start];
+        let next = chars.get(start + 1).copied();
+        let digits_from = |from: usize| {
+            (from..chars.len())
+                .find(|&i| !chars[i].is_ascii_digit())
+                .unwrap_or(chars.len())
+        };
+
+        let (kind, end) = match c {
+            '#' => (
+                TokenKind::Comment,
+                (start..chars.len())
+                    .find(|&i| chars[i] == '\n' || chars[i] == '\r')
+                    .unwrap_or(chars.len()),
+            ),
+            c if c.is_whitespace() => (
+                TokenKind::Whitespace,
+                (start..chars.len())
+                    .find(|&i| !chars[i].is_whitespace())
+                    .unwrap_or(chars.len()),
+            ),
+            c if c.is_ascii_digit() => {
+                let int_end = if c == '0' {
+                    start + 1
+                } else {
+                    digits_from(start + 1)
+                };
+
+                let has_fraction = chars.get(int_end) == Some(&'.')
+                    && chars.get(int_end + 1).is_some_and(|c| c.is_ascii_digit());
+
+                if has_fraction {
+                    (TokenKind::Number, digits_from(int_end + 1))
+                } else {
+                    (TokenKind::Number, int_end)
+                }
+            }
+            '.' if next.is_some_and(|c| c.is_ascii_digit()) => {
+                (TokenKind::Number, digits_from(start + 1))
+            }
+            c if is_ident_start(c) => {
+                let end = (start + 1..chars.len())
+                    .find(|&i| !is_ident_continue(chars[i]))
+                    .unwrap_or(chars.len());
+
+                match chars.get(end) {
+                    Some('\'') => (TokenKind::Identifier, end + 1),
+                    _ => (TokenKind::Identifier, end),
+                }
+            }
+            '<' if next == Some('=') => (TokenKind::LessEqual, start + 2),
+            '>' if next == Some('=') => (TokenKind::GreaterEqual, start + 2),
+            '=' if next == Some('=') => (TokenKind::EqualEqual, start + 2),
+            '+' => (TokenKind::Plus, start + 1),
+            '-' => (TokenKind::Minus, start + 1),
+            '*' => (TokenKind::Star, start + 1),
+            '/' => (TokenKind::Slash, start + 1),
+            '%' => (TokenKind::Percent, start + 1),
+            '^' => (TokenKind::Caret, start + 1),
+            '<' => (TokenKind::Less, start + 1),
+            '>' => (TokenKind::Greater, start + 1),
+            '&' => (TokenKind::Ampersand, start + 1),
+            '|' => (TokenKind::Pipe, start + 1),
+            '!' => (TokenKind::Bang, start + 1),
+            '=' => (TokenKind::Equals, start + 1),
+            ',' => (TokenKind::Comma, start + 1),
+            ';' => (TokenKind::Semicolon, start + 1),
+            '(' => (TokenKind::LeftParen, start + 1),
+            ')' => (TokenKind::RightParen, start + 1),
+            _ => (TokenKind::Unknown, start + 1),
+        };
+
+        tokens.push(Token {
+            kind,
+            span: start..end,
+            text: chars[start..end].iter().collect(),
+        });
+
+        start = end;
+    }
+
+    tokens
+}
@@ -0,0 +1,363 @@
+use super::eval::builtin::Builtin;
+use super::eval::resolve::{INPUT_NAMES, OUTPUT_NAMES};
+use super::format::{
+    binary_operator, binary_precedence, unary_operator, ATOM, EXPONENT, MULTIPLICATIVE, UNARY,
+};
+use super::parser::{Assignment, BinaryOperation, Expr, UnaryOperation};
+use ahash::{AHashMap, AHashSet};
+
+// the characters new variable names are made of, in the order they are handed out
+const NAME_CHARACTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Prints a parsed program as the shortest text that parses back into a program that evaluates
+/// identically.
+///
+/// Comments and whitespace are left out, user variables are renamed to the shortest free names
+/// (the most used ones first), parentheses are only written where operator precedence needs them,
+/// and a number multiplied by a variable, call or parenthesised expression is written with
+/// shorthand multiplication, such as `2sin(x)`. Inputs, outputs and functions keep their names.
+pub fn minify(assignments: &[Assignment]) -> String {
+    let names = short_names(assignments);
+    let mut output = String::new();
+
+    for assignment in assignments {
+        output.push_str(rename(&names, &assignment.name));
+        output.push('=');
+        write_expr(&assignment.expression.0, &names, &mut output);
+        output.push(';');
+    }
+
+    output
+}
+
+fn rename<'a>(names: &'a AHashMap<String, String>, name: &'a str) -> &'a str {
+    names.get(name).map_or(name, |short| short.as_str())
+}
+
+fn count_variables(expr: &Expr, uses: &mut Vec<(String, usize)>) {
+    match expr {
+        Expr::Number(_) | Expr::Error => {}
+        Expr::Variable(name) => match uses.iter_mut().find(|(seen, _)| seen == name) {
+            Some((_, count)) => *count += 1,
+            None => uses.push((name.clone(), 1)),
+        },
+        Expr::Group(inner) => count_variables(&inner.0, uses),
+        Expr::BinaryExpression(left, _, right) => {
+            count_variables(&left.0, uses);
+            count_variables(&right.0, uses);
+        }
+        Expr::UnaryExpression(_, operand) => count_variables(&operand.0, uses),
+        Expr::Call(_, args) => {
+            for arg in args.iter() {
+                count_variables(&arg.0, uses);
+            }
+        }
+    }
+}
+
+/// The `n`th shortest variable name: `a` to `Z`, then `aa`, `ba` and so on.
+fn nth_name(mut n: usize) -> String {
+    let mut name = String::new();
+
+    loop {
+        name.push(NAME_CHARACTERS[n % NAME_CHARACTERS.len()] as char);
+        n /= NAME_CHARACTERS.len();
+
+        if n == 0 {
+            return name;
+        }
+
+        n -= 1;
+    }
+}
+
+/// Picks a new name for every user variable that is assigned to.
+fn short_names(assignments: &[Assignment]) -> AHashMap<String, String> {
+    let mut uses: Vec<(String, usize)> = vec![];
+
+    for assignment in assignments {
+        match uses.iter_mut().find(|(seen, _)| *seen == assignment.name) {
+            Some((_, count)) => *count += 1,
+            None => uses.push((assignment.name.clone(), 1)),
+        }
+
+        count_variables(&assignment.expression.0, &mut uses);
+    }
+
+    let renamed = |name: &str| {
+        !INPUT_NAMES.contains(&name)
+            && !OUTPUT_NAMES.contains(&name)
+            && Builtin::from_name(name).is_none()
+            && assignments.iter().any(|assignment| assignment.name == name)
+    };
+
+    // names that are kept as they are can't be handed out again
+    let mut taken: AHashSet<String> = INPUT_NAMES
+        .into_iter()
+        .chain(OUTPUT_NAMES)
+        .chain(Builtin::ALL.iter().map(|builtin| builtin.name()))
+        .map(|name| name.to_string())
+        .collect();
+
+    taken.extend(
+        uses.iter()
+            .filter(|(name, _)| !renamed(name))
+            .map(|(name, _)| name.clone()),
+    );
+
+    // the sort is stable, so variables that are used equally often keep the order they first
+    // appear in
+    uses.retain(|(name, _)| renamed(name));
+    uses.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let mut names = AHashMap::new();
+    let mut next = 0;
+
+    for (name, _) in uses {
+        let short = loop {
+            let candidate = nth_name(next);
+            next += 1;
+
+            if !taken.contains(&candidate) {
+                break candidate;
+            }
+        };
+
+        names.insert(name, short);
+    }
+
+    names
+}
+
+/// Looks through any parentheses from the source, since they are added back where needed.
+fn strip(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Group(inner) => strip(&inner.0),
+        _ => expr,
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match strip(expr) {
+        Expr::BinaryExpression(_, op, _) => binary_precedence(op),
+        Expr::UnaryExpression(_, _) => UNARY,
+        Expr::Number(value) if value.is_sign_negative() => UNARY,
+        _ => ATOM,
+    }
+}
+
+/// Whether an operand is written in parentheses when it's on the right hand side of an operator
+/// with the given precedence.
+fn needs_parentheses(expr: &Expr, parent: u8) -> bool {
+    precedence(expr) <= parent
+}
+
+/// Whether an expression, as printed after `^` in shorthand multiplication, is something the
+/// parser accepts there: any number of negations of a number, variable, call or parenthesised
+/// expression.
+fn fits_shorthand_exponent(expr: &Expr) -> bool {
+    match strip(expr) {
+        _ if needs_parentheses(expr, EXPONENT) => true,
+        Expr::UnaryExpression(UnaryOperation::Negate, operand) => {
+            needs_parentheses(&operand.0, UNARY - 1) || fits_shorthand_exponent(&operand.0)
+        }
+        Expr::UnaryExpression(UnaryOperation::Not, _) => false,
+        _ => true,
+    }
+}
+
+/// Whether the right hand side of a multiplication, as printed after a number, is something the
+/// parser accepts in shorthand multiplication: a variable, call or parenthesised expression,
+/// optionally raised to a power.
+fn fits_shorthand(expr: &Expr) -> bool {
+    match strip(expr) {
+        _ if needs_parentheses(expr, MULTIPLICATIVE) => true,
+        Expr::Variable(_) | Expr::Call(_, _) => true,
+        Expr::BinaryExpression(base, BinaryOperation::Exponent, exponent) => {
+            fits_shorthand_base(&base.0) && fits_shorthand_exponent(&exponent.0)
+        }
+        _ => false,
+    }
+}
+
+fn fits_shorthand_base(expr: &Expr) -> bool {
+    match strip(expr) {
+        _ if precedence(expr) < EXPONENT => true,
+        Expr::Variable(_) | Expr::Call(_, _) => true,
+        Expr::BinaryExpression(base, BinaryOperation::Exponent, exponent) => {
+            fits_shorthand_base(&base.0) && fits_shorthand_exponent(&exponent.0)
+        }
+        _ => false,
+    }
+}
+
+fn write_number(value: f64, output: &mut String) {
+    let text = value.to_string();
+
+    // the leading zero of a fraction isn't needed
+    match text.strip_prefix("0.") {
+        Some(digits) => {
+            output.push('.');
+            output.push_str(digits);
+        }
+        None => match text.strip_prefix("-0.") {
+            Some(digits) => {
+                output.push_str("-.");
+                output.push_str(digits);
+            }
+            None => output.push_str(&text),
+        },
+    }
+}
+
+fn write_operand(
+    expr: &Expr,
+    parent: u8,
+    right_side: bool,
+    names: &AHashMap<String, String>,
+    output: &mut String,
+) {
+    let own = precedence(expr);
+
+    if own < parent || (right_side && own == parent) {
+        output.push('(');
+        write_expr(expr, names, output);
+        output.push(')');
+    } else {
+        write_expr(expr, names, output);
+    }
+}
+
+fn write_expr(expr: &Expr, names: &AHashMap<String, String>, output: &mut String) {
+    match expr {
+        Expr::Number(value) => write_number(*value, output),
+        Expr::Variable(name) => output.push_str(rename(names, name)),
+        Expr::Group(inner) => write_expr(&inner.0, names, output),
+        Expr::BinaryExpression(left, op, right) => {
+            let own = binary_precedence(op);
+
+            // shorthand multiplication binds as tightly as a single value, so it's only used
+            // where a regular multiplication wouldn't need parentheses either; that way the
+            // text means the same thing whichever way it's read
+            if let (BinaryOperation::Multiply, Expr::Number(value)) = (op, strip(&left.0)) {
+                if !value.is_sign_negative() && fits_shorthand(&right.0) {
+                    write_number(*value, output);
+                    write_operand(&right.0, own, true, names, output);
+                    return;
+                }
+            }
+
+            write_operand(&left.0, own, false, names, output);
+            output.push_str(binary_operator(op));
+            write_operand(&right.0, own, true, names, output);
+        }
+        Expr::UnaryExpression(op, operand) => {
+            output.push_str(unary_operator(op));
+            write_operand(&operand.0, UNARY, false, names, output);
+        }
+        Expr::Call(name, args) => {
+            output.push_str(name);
+            output.push('(');
+
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_expr(&arg.0, names, output);
+            }
+
+            output.push(')');
+        }
+        // only produced alongside a parser error, so there's nothing meaningful to print
+        Expr::Error => output.push('?'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expressions::eval::grid::assert_same_points;
+    use crate::expressions::parser;
+
+    /// Minifies a program, checking the result parses back into one that does the same thing,
+    /// and returns it.
+    fn assert_round_trip(source: &str) -> String {
+        let original = parser::parse(source).unwrap();
+        let minified = minify(&original);
+
+        let reparsed = match parser::parse(&minified) {
+            Ok(reparsed) => reparsed,
+            Err(errors) => panic!("{minified:?} (from {source:?}) doesn't parse: {errors:?}"),
+        };
+
+        assert_same_points(&original, &reparsed);
+        assert_eq!(minify(&reparsed), minified, "minifying {source:?} twice");
+
+        minified
+    }
+
+    #[test]
+    fn shorthand_multiplication() {
+        assert_eq!(assert_round_trip("x' = 2x^2;"), "x'=2x^2;");
+        assert_eq!(assert_round_trip("x' = 2 * (x + y);"), "x'=2(x+y);");
+        assert_eq!(assert_round_trip("x' = 2(x + y);"), "x'=2(x+y);");
+        assert_eq!(assert_round_trip("x' = 3sin(x) ^ -2;"), "x'=3sin(x)^-2;");
+    }
+
+    #[test]
+    fn shorthand_that_would_change_meaning() {
+        // `2x^2` is `2 * x^2`, so the parentheses have to stay
+        assert_eq!(assert_round_trip("x' = (2x)^2;"), "x'=(2x)^2;");
+        assert_eq!(assert_round_trip("x' = 5 / (2x);"), "x'=5/(2x);");
+        assert_eq!(assert_round_trip("x' = (2 * x) ^ y;"), "x'=(2x)^y;");
+        assert_round_trip("x' = 2 * x ^ !y;");
+        assert_round_trip("x' = 2 * -x;");
+    }
+
+    #[test]
+    fn negative_numbers_and_exponents() {
+        assert_eq!(assert_round_trip("a = x; x' = a - -.5;"), "a=x;x'=a--.5;");
+        assert_eq!(assert_round_trip("x' = x ^ -2;"), "x'=x^-2;");
+        assert_round_trip("x' = -x ^ 2;");
+        assert_round_trip("x' = (-x) ^ 2;");
+        assert_round_trip("x' = 0.25 - 1.5;");
+    }
+
+    #[test]
+    fn left_associative_chains() {
+        assert_eq!(assert_round_trip("x' = x - y - 3;"), "x'=x-y-3;");
+        assert_eq!(assert_round_trip("x' = x - (y - 3);"), "x'=x-(y-3);");
+        assert_eq!(assert_round_trip("x' = x / y / 3 % 2;"), "x'=x/y/3%2;");
+        assert_eq!(assert_round_trip("x' = x / (y * 3);"), "x'=x/(y*3);");
+        assert_round_trip("x' = x ^ 2 ^ 0.5;");
+        assert_round_trip("x' = (x ^ 2) ^ 0.5;");
+        assert_round_trip("x' = x < y == (y < x);");
+    }
+
+    #[test]
+    fn renamed_variables() {
+        // the most used variable gets the shortest name, and no name clashes with a builtin
+        assert_eq!(
+            assert_round_trip("angle = time * 3; n = 2; x' = sin(angle) * angle + n;"),
+            "a=time*3;b=2;x'=sin(a)*a+b;"
+        );
+
+        let many: String = (0..60).map(|i| format!("v{i} = x + {i};")).collect();
+        let minified = assert_round_trip(&format!("{many} x' = v59 + v58;"));
+
+        for kept in INPUT_NAMES
+            .into_iter()
+            .chain(Builtin::ALL.iter().map(|builtin| builtin.name()))
+        {
+            assert!(!minified.contains(&format!(";{kept}=")), "{minified}");
+        }
+    }
+
+    #[test]
+    fn renamed_variables_next_to_function_names() {
+        // a variable can't be renamed to anything a call or shorthand would run into
+        assert_round_trip("sinx = x; x' = 2sinx + sin(sinx);");
+        assert_round_trip("abs2 = 1; x' = 2abs(abs2) + 3abs2;");
+        assert_round_trip("a = 1; b = a; x' = 2a * 2b + min(a, b);");
+    }
+}
@@ -0,0 +1,585 @@
+use super::eval::builtin::Builtin;
+use super::eval::resolve::OUTPUT_NAMES;
+use super::format::{binary_operator, unary_operator};
+use super::parser::{Assignment, BinaryOperation, Expr, Span, Spanned, UnaryOperation};
+use ahash::{AHashMap, AHashSet};
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+/// Rewrites a program into one that calculates the same outputs with less work.
+///
+/// - Constant subtrees are folded, including the `pi` and `tau` inputs and variables that only
+///   ever hold a constant.
+/// - Assignments that can't affect `x'`, `y'`, `h`, `s` or `v` are removed.
+/// - Pure subexpressions that are calculated more than once are hoisted into temporary
+///   variables. Anything containing `rand()` is never shared, since every call gives a new value.
+///
+/// The program must pass [`super::eval::check::check`] first. Such a program can't run into an
+/// error, which is what makes it safe to evaluate a hoisted subexpression even when the `if`
+/// branch it came from isn't taken.
+pub fn optimize(assignments: &[Assignment]) -> Vec<Assignment> {
+    let folded = fold_constants(assignments);
+    let live = remove_unused(folded);
+
+    share_subexpressions(live)
+}
+
+fn number(value: f64, span: &Span) -> Spanned<Arc<Expr>> {
+    (Arc::new(Expr::Number(value)), span.clone())
+}
+
+fn as_number(expr: &Spanned<Arc<Expr>>) -> Option<f64> {
+    match &*expr.0 {
+        Expr::Number(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Calculates a binary operation the same way the evaluator does.
+fn apply_binary(op: &BinaryOperation, a: f64, b: f64) -> f64 {
+    match op {
+        BinaryOperation::Add => a + b,
+        BinaryOperation::Subtract => a - b,
+        BinaryOperation::Multiply => a * b,
+        BinaryOperation::Divide => a / b,
+        BinaryOperation::Modulo => a % b,
+        BinaryOperation::Exponent => f64::powf(a, b),
+        BinaryOperation::LessThan => (a < b) as u64 as f64,
+        BinaryOperation::GreaterThan => (a > b) as u64 as f64,
+        BinaryOperation::LessThanOrEqual => (a <= b) as u64 as f64,
+        BinaryOperation::GreaterThanOrEqual => (a >= b) as u64 as f64,
+        BinaryOperation::Equal => (a == b) as u64 as f64,
+        BinaryOperation::And => (a >= 1.0 && b >= 1.0) as u64 as f64,
+        BinaryOperation::Or => (a >= 1.0 || b >= 1.0) as u64 as f64,
+    }
+}
+
+/// Replaces a node with a literal, unless the value can't be written as one.
+fn folded(value: f64, span: &Span) -> Option<Spanned<Arc<Expr>>> {
+    // there's no literal for NaN or infinity
+    if value.is_finite() {
+        Some(number(value, span))
+    } else {
+        None
+    }
+}
+
+fn fold(spanned_expr: &Spanned<Arc<Expr>>, known: &AHashMap<String, f64>) -> Spanned<Arc<Expr>> {
+    let span = &spanned_expr.1;
+
+    let expr = match &*spanned_expr.0 {
+        Expr::Number(_) | Expr::Error => return spanned_expr.clone(),
+        Expr::Variable(name) => match known.get(name) {
+            Some(value) => return number(*value, span),
+            None => return spanned_expr.clone(),
+        },
+        // the formatter adds back any parentheses that are needed
+        Expr::Group(inner) => return fold(inner, known),
+        Expr::BinaryExpression(left, op, right) => {
+            let left = fold(left, known);
+
+            // `&` and `|` only look at their right hand side when the left one doesn't decide
+            // the result
+            match (op, as_number(&left)) {
+                (BinaryOperation::And, Some(a)) if a < 1.0 || a.is_nan() => {
+                    return number(0.0, span)
+                }
+                (BinaryOperation::Or, Some(a)) if a >= 1.0 => return number(1.0, span),
+                _ => {}
+            }
+
+            let right = fold(right, known);
+
+            if let (Some(a), Some(b)) = (as_number(&left), as_number(&right)) {
+                if let Some(result) = folded(apply_binary(op, a, b), span) {
+                    return result;
+                }
+            }
+
+            Expr::BinaryExpression(left, op.clone(), right)
+        }
+        Expr::UnaryExpression(op, operand) => {
+            let operand = fold(operand, known);
+
+            if let Some(a) = as_number(&operand) {
+                let value = match op {
+                    UnaryOperation::Negate => -a,
+                    UnaryOperation::Not => {
+                        if a >= 1.0 {
+                            0.0
+                        } else {
+                            1.0
+                        }
+                    }
+                };
+
+                if let Some(result) = folded(value, span) {
+                    return result;
+                }
+            }
+
+            Expr::UnaryExpression(op.clone(), operand)
+        }
+        Expr::Call(name, args) => {
+            let args: Vec<Spanned<Arc<Expr>>> = args.iter().map(|arg| fold(arg, known)).collect();
+
+            let builtin = Builtin::from_name(name);
+
+            if builtin == Some(Builtin::If) && args.len() == 3 {
+                if let Some(condition) = as_number(&args[0]) {
+                    return if condition >= 1.0 {
+                        args[1].clone()
+                    } else {
+                        args[2].clone()
+                    };
+                }
+            }
+
+            let values: Option<Vec<f64>> = args.iter().map(as_number).collect();
+
+            // `if` is folded above, as soon as its condition is known
+            if let Some(result) = builtin
+                .zip(values)
+                .and_then(|(builtin, values)| builtin.apply(&values))
+                .and_then(|value| folded(value, span))
+            {
+                return result;
+            }
+
+            Expr::Call(name.clone(), args)
+        }
+    };
+
+    (Arc::new(expr), span.clone())
+}
+
+fn fold_constants(assignments: &[Assignment]) -> Vec<Assignment> {
+    let mut known: AHashMap<String, f64> = AHashMap::new();
+    known.insert("pi".to_string(), std::f64::consts::PI);
+    known.insert("tau".to_string(), std::f64::consts::TAU);
+
+    assignments
+        .iter()
+        .map(|assignment| {
+            let expression = fold(&assignment.expression, &known);
+
+            match as_number(&expression) {
+                Some(value) => known.insert(assignment.name.clone(), value),
+                None => known.remove(&assignment.name),
+            };
+
+            Assignment {
+                name: assignment.name.clone(),
+                expression,
+                span: assignment.span.clone(),
+            }
+        })
+        .collect()
+}
+
+fn collect_variables(spanned_expr: &Spanned<Arc<Expr>>, variables: &mut AHashSet<String>) {
+    match &*spanned_expr.0 {
+        Expr::Number(_) | Expr::Error => {}
+        Expr::Variable(name) => {
+            variables.insert(name.clone());
+        }
+        Expr::Group(inner) => collect_variables(inner, variables),
+        Expr::BinaryExpression(left, _, right) => {
+            collect_variables(left, variables);
+            collect_variables(right, variables);
+        }
+        Expr::UnaryExpression(_, operand) => collect_variables(operand, variables),
+        Expr::Call(_, args) => {
+            for arg in args.iter() {
+                collect_variables(arg, variables);
+            }
+        }
+    }
+}
+
+/// Removes every assignment whose value is never read by a later assignment that is kept, or
+/// left in an output.
+fn remove_unused(assignments: Vec<Assignment>) -> Vec<Assignment> {
+    let mut live: AHashSet<String> = OUTPUT_NAMES.iter().map(|name| name.to_string()).collect();
+    let mut kept = vec![];
+
+    for assignment in assignments.into_iter().rev() {
+        // the value read by an earlier assignment is the one from before this assignment, so
+        // this one only needs to be kept if something after it reads the variable
+        if live.remove(&assignment.name) {
+            collect_variables(&assignment.expression, &mut live);
+            kept.push(assignment);
+        }
+    }
+
+    kept.reverse();
+    kept
+}
+
+/// What a subexpression calculates, in terms of the values of its parts. Two subexpressions with
+/// the same value always calculate the same number.
+#[derive(PartialEq, Eq, Hash)]
+enum Value {
+    Number(u64),
+    /// A variable, and how many times it was assigned to before, so the same name before and
+    /// after an assignment are different values.
+    Variable(String, usize),
+    Binary(&'static str, usize, usize),
+    Unary(&'static str, usize),
+    Call(String, Vec<usize>),
+}
+
+/// A value calculated by a subexpression with more than one node.
+struct Candidate {
+    count: usize,
+    size: usize,
+}
+
+/// A subexpression along with the number of its value, from [`Values::number`].
+struct Numbered {
+    expr: Spanned<Arc<Expr>>,
+    /// `None` for anything that calls `rand()`.
+    value: Option<usize>,
+    size: usize,
+    children: Vec<Numbered>,
+}
+
+/// Numbers the values of the subexpressions of a program, in the order they're first seen, and
+/// counts how many times each one is calculated.
+#[derive(Default)]
+struct Values {
+    numbers: AHashMap<Value, usize>,
+    candidates: Vec<Candidate>,
+}
+
+impl Values {
+    fn value(&mut self, value: Value, size: usize) -> usize {
+        let candidates = &mut self.candidates;
+
+        *self.numbers.entry(value).or_insert_with(|| {
+            candidates.push(Candidate { count: 0, size });
+            candidates.len() - 1
+        })
+    }
+
+    fn number(
+        &mut self,
+        spanned_expr: &Spanned<Arc<Expr>>,
+        versions: &AHashMap<String, usize>,
+    ) -> Numbered {
+        let leaf = |value: Option<usize>| Numbered {
+            expr: spanned_expr.clone(),
+            value,
+            size: 1,
+            children: vec![],
+        };
+
+        let (value, children) = match &*spanned_expr.0 {
+            Expr::Number(value) => {
+                return leaf(Some(self.value(Value::Number(value.to_bits()), 1)));
+            }
+            Expr::Variable(name) => {
+                let version = versions.get(name).copied().unwrap_or(0);
+                return leaf(Some(self.value(Value::Variable(name.clone(), version), 1)));
+            }
+            Expr::Error => return leaf(None),
+            Expr::Group(inner) => {
+                let inner = self.number(inner, versions);
+
+                return Numbered {
+                    expr: spanned_expr.clone(),
+                    value: inner.value,
+                    size: inner.size,
+                    children: vec![inner],
+                };
+            }
+            Expr::BinaryExpression(left, op, right) => {
+                let left = self.number(left, versions);
+                let right = self.number(right, versions);
+
+                let value = left
+                    .value
+                    .zip(right.value)
+                    .map(|(left, right)| Value::Binary(binary_operator(op), left, right));
+
+                (value, vec![left, right])
+            }
+            Expr::UnaryExpression(op, operand) => {
+                let operand = self.number(operand, versions);
+                let value = operand
+                    .value
+                    .map(|operand| Value::Unary(unary_operator(op), operand));
+
+                (value, vec![operand])
+            }
+            Expr::Call(name, args) => {
+                let args: Vec<Numbered> =
+                    args.iter().map(|arg| self.number(arg, versions)).collect();
+
+                let value = match Builtin::from_name(name) {
+                    Some(Builtin::Rand) => None,
+                    _ => args
+                        .iter()
+                        .map(|arg| arg.value)
+                        .collect::<Option<Vec<usize>>>()
+                        .map(|args| Value::Call(name.clone(), args)),
+                };
+
+                (value, args)
+            }
+        };
+
+        let size = children.iter().map(|child| child.size).sum::<usize>() + 1;
+
+        let value = value.map(|value| {
+            let number = self.value(value, size);
+            self.candidates[number].count += 1;
+            number
+        });
+
+        Numbered {
+            expr: spanned_expr.clone(),
+            value,
+            size,
+            children,
+        }
+    }
+}
+
+/// Replaces every occurence of the subexpression with the given value with `temporary`. Returns
+/// whether anything was replaced.
+fn replace_subexpression(
+    numbered: &Numbered,
+    target: usize,
+    temporary: &str,
+) -> (Spanned<Arc<Expr>>, bool) {
+    let span = &numbered.expr.1;
+
+    if numbered.value == Some(target) {
+        return (
+            (
+                Arc::new(Expr::Variable(temporary.to_string())),
+                span.clone(),
+            ),
+            true,
+        );
+    }
+
+    let mut replaced = false;
+    let children: Vec<Spanned<Arc<Expr>>> = numbered
+        .children
+        .iter()
+        .map(|child| {
+            let (expr, found) = replace_subexpression(child, target, temporary);
+            replaced |= found;
+            expr
+        })
+        .collect();
+
+    if !replaced {
+        return (numbered.expr.clone(), false);
+    }
+
+    let expr = match (&*numbered.expr.0, children.as_slice()) {
+        (Expr::Group(_), [inner]) => Expr::Group(inner.clone()),
+        (Expr::BinaryExpression(_, op, _), [left, right]) => {
+            Expr::BinaryExpression(left.clone(), op.clone(), right.clone())
+        }
+        (Expr::UnaryExpression(op, _), [operand]) => {
+            Expr::UnaryExpression(op.clone(), operand.clone())
+        }
+        (Expr::Call(name, _), args) => Expr::Call(name.clone(), args.to_vec()),
+        _ => unreachable!("only nodes with children can have something replaced"),
+    };
+
+    ((Arc::new(expr), span.clone()), true)
+}
+
+/// Finds the subexpression with the given value, as it's first written.
+fn find_subexpression(numbered: &Numbered, target: usize) -> Option<&Numbered> {
+    if numbered.value == Some(target) {
+        return Some(numbered);
+    }
+
+    numbered
+        .children
+        .iter()
+        .find_map(|child| find_subexpression(child, target))
+}
+
+/// Picks a name for a temporary variable that isn't used anywhere in the program.
+fn temporary_name(assignments: &[Assignment], next: &mut usize) -> String {
+    let mut used = AHashSet::new();
+
+    for assignment in assignments.iter() {
+        used.insert(assignment.name.clone());
+        collect_variables(&assignment.expression, &mut used);
+    }
+
+    loop {
+        let name = format!("_t{next}");
+        *next += 1;
+
+        if !used.contains(&name) {
+            return name;
+        }
+    }
+}
+
+/// Hoists the largest subexpression that is calculated more than once into a temporary
+/// variable, and repeats until there are none left. Each pass numbers every subexpression once.
+fn share_subexpressions(mut assignments: Vec<Assignment>) -> Vec<Assignment> {
+    let mut next_temporary = 0;
+
+    loop {
+        let mut values = Values::default();
+        let mut versions: AHashMap<String, usize> = AHashMap::new();
+
+        let numbered: Vec<Numbered> = assignments
+            .iter()
+            .map(|assignment| {
+                let numbered = values.number(&assignment.expression, &versions);
+                *versions.entry(assignment.name.clone()).or_insert(0) += 1;
+                numbered
+            })
+            .collect();
+
+        // the largest, and of those the first one seen
+        let target = values
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.count > 1)
+            .max_by_key(|(value, candidate)| (candidate.size, Reverse(*value)))
+            .map(|(value, _)| value);
+
+        let target = match target {
+            Some(value) => value,
+            None => return assignments,
+        };
+
+        let temporary = temporary_name(&assignments, &mut next_temporary);
+        let mut assigned = false;
+        let mut rewritten = Vec::with_capacity(assignments.len() + 1);
+
+        for (assignment, numbered) in assignments.iter().zip(numbered.iter()) {
+            let (expression, replaced) = replace_subexpression(numbered, target, &temporary);
+
+            // the temporary goes right before its first use, where every variable it reads
+            // still has the value it had there
+            if replaced && !assigned {
+                let subexpression = find_subexpression(numbered, target)
+                    .expect("a replaced subexpression can be found")
+                    .expr
+                    .clone();
+
+                rewritten.push(Assignment {
+                    name: temporary.clone(),
+                    span: subexpression.1.clone(),
+                    expression: subexpression,
+                });
+
+                assigned = true;
+            }
+
+            rewritten.push(Assignment {
+                name: assignment.name.clone(),
+                expression,
+                span: assignment.span.clone(),
+            });
+        }
+
+        assignments = rewritten;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expressions::eval::grid::assert_same_points;
+    use crate::expressions::{format, parser};
+
+    /// Optimizes a program, checking the result does the same thing, and returns it formatted.
+    fn assert_equivalent(source: &str) -> String {
+        let original = parser::parse(source).unwrap();
+        let optimized = optimize(&original);
+
+        assert_same_points(&original, &optimized);
+
+        format::format(&optimized)
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(
+            assert_equivalent("degree = 2 * pi / 360; x' = x * degree;"),
+            format::format(
+                &parser::parse(&format!("x' = x * {};", 2.0 * std::f64::consts::PI / 360.0))
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            assert_equivalent("x' = if(1 > 2, x, y) + 0 * 5;"),
+            "x' = y + 0;\n"
+        );
+    }
+
+    #[test]
+    fn does_not_fold_into_nan_or_infinity() {
+        let optimized = assert_equivalent("x' = 1 / 0; y' = 0 / 0 + x;");
+
+        assert!(optimized.contains("1 / 0"), "{optimized}");
+        assert!(optimized.contains("0 / 0"), "{optimized}");
+    }
+
+    #[test]
+    fn shares_repeated_subexpressions() {
+        assert_eq!(
+            assert_equivalent("x' = sin(time * 3) * x; y' = sin(time * 3) * y;"),
+            "_t0 = sin(time * 3);\nx' = _t0 * x;\ny' = _t0 * y;\n"
+        );
+
+        // an `if` branch that isn't taken for some indexes still can't run into an error
+        assert_equivalent("x' = if(x > 0, sqrt(x * y), 0); y' = sqrt(x * y) + index;");
+    }
+
+    #[test]
+    fn removes_dead_assignments() {
+        assert_eq!(
+            assert_equivalent("unused = x * 2; a = y; a = a + 1; x' = a; b = a; h = 5; h = x;"),
+            "a = y;\na = a + 1;\nx' = a;\nh = x;\n"
+        );
+    }
+
+    #[test]
+    fn does_not_share_across_reassignments() {
+        let optimized = assert_equivalent(
+            "a = x; b = a * 2 + 1; a = y; c = a * 2 + 1; x' = b; y' = c; h = a * 2 + 1;",
+        );
+
+        // the second and third `a * 2 + 1` are the same, but the first one isn't
+        assert_eq!(
+            optimized,
+            "a = x;\nb = a * 2 + 1;\na = y;\n_t0 = a * 2 + 1;\nc = _t0;\nx' = b;\ny' = c;\nh = _t0;\n"
+        );
+    }
+
+    #[test]
+    fn never_shares_rand() {
+        // `floor(rand())` is always 0, so the points can still be compared
+        let optimized =
+            assert_equivalent("x' = floor(rand()) + x * 3; y' = x * 3 + floor(rand());");
+
+        assert_eq!(optimized.matches("rand()").count(), 2, "{optimized}");
+        assert!(optimized.starts_with("_t0 = x * 3;\n"), "{optimized}");
+    }
+
+    #[test]
+    fn shares_the_largest_subexpression_first() {
+        // `x * y` is still calculated twice once `sin(x * y) + 1` has been shared
+        assert_eq!(
+            assert_equivalent("x' = sin(x * y) + 1; y' = sin(x * y) + 1; h = x * y;"),
+            "_t1 = x * y;\n_t0 = sin(_t1) + 1;\nx' = _t0;\ny' = _t0;\nh = _t1;\n"
+        );
+    }
+}
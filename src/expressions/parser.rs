@@ -1,10 +1,14 @@
-use super::errors;
+use super::edit::TextEdit;
+use super::errors::{Diagnostic, ErrorCode};
+use super::lexer::{lex, Token, TokenKind};
 use chumsky::prelude::*;
 use std::sync::Arc;
+
+/// A range of character (not byte) offsets into the source text.
 pub type Span = std::ops::Range<usize>;
 pub type Spanned<T> = (T, Span);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BinaryOperation {
     Add,
     Subtract,
@@ -22,13 +26,14 @@ pub enum BinaryOperation {
     Or,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum UnaryOperation {
     Negate,
     Not,
 }
 
-#[derive(Debug, Clone)]
+/// A node in an expression's syntax tree. Every child node is spanned.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     // Data Types
     Number(f64),
@@ -46,13 +51,17 @@ pub enum Expr {
     Error,
 }
 
-#[derive(Debug, Clone)]
+/// A single `name = expression;` statement. An expression's source text is a list of these.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Assignment {
     pub name: String,
     pub expression: Spanned<Arc<Expr>>,
     pub span: Span,
 }
 
+/// Builds the chumsky parser for a whole expression.
+///
+/// Most users will want [`parse`] instead, which also converts the parser's errors.
 pub fn parser() -> impl Parser<char, Vec<Assignment>, Error = Simple<char>> {
     let ident = text::ident()
         .chain::<char, _, _>(just('\'').or_not())
@@ -325,54 +334,194 @@ pub fn parser() -> impl Parser<char, Vec<Assignment>, Error = Simple<char>> {
         .then_ignore(end())
 }
 
-pub fn process_parser_error(error: Simple<char>, text: String) -> errors::Error {
-    let loc = errors::get_position_from_span(error.span(), text.clone());
+/// Parses the source text of an expression into its assignments.
+pub fn parse(text: &str) -> Result<Vec<Assignment>, Vec<Diagnostic>> {
+    parser().parse(text).map_err(|parse_errors| {
+        parse_errors
+            .into_iter()
+            .map(|err| process_parser_error(err, text.to_string()))
+            .collect()
+    })
+}
 
-    let mut processed_error = errors::Error {
-        line_number: loc.0,
-        col_number: loc.1,
-        error_type: errors::ErrorType::ParseError,
-        reason: "P255: Unexpected parser error.".to_string(),
-        id: 255,
-    };
+/// Finds the last `(` before `end` that hasn't been closed, within the same assignment.
+fn unclosed_parenthesis(text: &str, end: usize) -> Option<Span> {
+    let mut open = vec![];
+
+    for token in lex(text) {
+        if token.span.start >= end {
+            break;
+        }
+
+        match token.kind {
+            TokenKind::LeftParen => open.push(token.span),
+            TokenKind::RightParen => {
+                open.pop();
+            }
+            TokenKind::Semicolon => open.clear(),
+            _ => {}
+        }
+    }
 
-    let found_character = error.found();
+    open.pop()
+}
+
+/// Where a missing `;` or `)` goes: straight after the last token before `offset`, rather than
+/// after the whitespace and comments in between.
+fn insertion_point(text: &str, offset: usize) -> usize {
+    lex(text)
+        .into_iter()
+        .rev()
+        .find(|token| token.span.end <= offset && !token.kind.is_trivia())
+        .map_or(offset, |token| token.span.end)
+}
+
+/// Whether the next assignment (or a comment) starts at `offset`, in which case a missing `;`
+/// is certainly all that's wrong with the one before it.
+fn starts_assignment(text: &str, offset: usize) -> bool {
+    let mut tokens = lex(text)
+        .into_iter()
+        .filter(|token| token.span.start >= offset && token.kind != TokenKind::Whitespace);
+
+    match tokens.next().map(|token| token.kind) {
+        Some(TokenKind::Comment) => true,
+        Some(TokenKind::Identifier) => {
+            matches!(tokens.next(), Some(token) if token.kind == TokenKind::Equals)
+        }
+        _ => false,
+    }
+}
+
+/// Whether the assignment ends at `offset`, with a `;` or the end of the text, in which case a
+/// missing `)` can only go there.
+fn ends_assignment(text: &str, offset: usize) -> bool {
+    let next = lex(text)
+        .into_iter()
+        .find(|token| token.span.start >= offset && token.kind != TokenKind::Whitespace);
+
+    next.is_none_or(|token| token.kind == TokenKind::Semicolon)
+}
+
+/// The name of the next assignment, if the parser has run into it because the one before it is
+/// missing its `;`. A number followed by a name (`2 b`) is a multiplication, so the parser only
+/// notices at the `=` after the name, which it expects to be the start of an `==`.
+fn swallowed_assignment(text: &str, offset: usize) -> Option<Span> {
+    let tokens: Vec<Token> = lex(text)
+        .into_iter()
+        .filter(|token| token.span.end <= offset && !token.kind.is_trivia())
+        .collect();
+
+    match tokens.as_slice() {
+        [.., before, name, equals]
+            if name.kind == TokenKind::Identifier
+                && equals.kind == TokenKind::Equals
+                && matches!(
+                    before.kind,
+                    TokenKind::Number | TokenKind::Identifier | TokenKind::RightParen
+                ) =>
+        {
+            Some(name.span.clone())
+        }
+        _ => None,
+    }
+}
+
+fn insertion(at: usize, text: &str) -> Vec<TextEdit> {
+    vec![TextEdit {
+        span: at..at,
+        replacement: text.to_string(),
+    }]
+}
+
+/// Converts one of chumsky's errors into a [`Diagnostic`]. `text` is used to point out any
+/// parenthesis that was left open.
+pub fn process_parser_error(error: Simple<char>, text: String) -> Diagnostic {
+    let span = error.span();
+    let found_character = error.found().copied().unwrap_or('_');
 
     let mut wants_semi = false;
     let mut wants_close_parens = false;
-    let mut wants_new_line = false;
 
-    let got_none = found_character == None;
+    let got_none = error.found().is_none();
 
     error.expected().for_each(|want| match want {
         Some(';') => wants_semi = true,
         Some(')') => wants_close_parens = true,
-        Some('\n') => wants_new_line = true,
         _ => (),
     });
 
-    if got_none {
-        processed_error.reason = "P2: Unexpected end of file.".into();
-        processed_error.id = 2;
+    let at = insertion_point(&text, span.start);
+
+    let mut diagnostic = if got_none {
+        let diagnostic = Diagnostic::new(ErrorCode::P2, "Unexpected end of file.", span.clone())
+            .with_label("the expression ends here");
+
+        // an expression that's complete apart from what has to come after it
+        if wants_close_parens {
+            diagnostic.with_fix("Add a ')'", insertion(at, ")"))
+        } else if wants_semi {
+            diagnostic.with_fix("Add a ';'", insertion(at, ";"))
+        } else {
+            diagnostic
+        }
     } else if wants_semi {
-        processed_error.reason = format!(
-            "P3: Unexpected character '{}'. Perhaps you forgot a semi-colon?",
-            found_character.unwrap_or(&'_')
-        );
-        processed_error.id = 4;
+        let diagnostic = Diagnostic::new(
+            ErrorCode::P3,
+            format!("Unexpected character '{found_character}'. Perhaps you forgot a semi-colon?"),
+            span.clone(),
+        )
+        .with_label("expected ';'")
+        .with_help("End every assignment with a semi-colon, like `a = 1;`.");
+
+        // `a = 2 3;` is as likely to be missing an operator as a semi-colon
+        match starts_assignment(&text, span.start) {
+            true => diagnostic.with_fix("Add a ';'", insertion(at, ";")),
+            false => diagnostic.with_suggested_fix("Add a ';'", insertion(at, ";")),
+        }
     } else if wants_close_parens {
-        processed_error.reason = format!(
-            "P4: Unexpected character '{}'. Perhaps you forgot to close your parenthesis?",
-            found_character.unwrap_or(&'_')
-        );
-        processed_error.id = 5;
+        let diagnostic = Diagnostic::new(
+            ErrorCode::P4,
+            format!("Unexpected character '{found_character}'. Perhaps you forgot to close your parenthesis?"),
+            span.clone(),
+        )
+        .with_label("expected ')'")
+        .with_help("Close the parenthesis with a ')'.");
+
+        // in `min(1 2)` the `)` could as well be missing after the `2`, with a `,` missing before it
+        match ends_assignment(&text, span.start) {
+            true => diagnostic.with_fix("Add a ')'", insertion(at, ")")),
+            false => diagnostic.with_suggested_fix("Add a ')'", insertion(at, ")")),
+        }
+    } else if let Some(name) = swallowed_assignment(&text, span.start) {
+        let at = insertion_point(&text, name.start);
+
+        Diagnostic::new(
+            ErrorCode::P1,
+            format!("Unexpected character '{found_character}'."),
+            span.clone(),
+        )
+        .with_label("unexpected character")
+        .with_secondary(name, "this looks like the start of the next assignment")
+        .with_help("Perhaps the assignment before it is missing a semi-colon?")
+        .with_fix("Add a ';'", insertion(at, ";"))
     } else {
-        processed_error.reason = format!(
-            "P1: Unexpected character '{}'.",
-            found_character.unwrap_or(&'_')
-        );
-        processed_error.id = 0;
+        Diagnostic::new(
+            ErrorCode::P1,
+            format!("Unexpected character '{found_character}'."),
+            span.clone(),
+        )
+        .with_label("unexpected character")
+    };
+
+    if got_none || wants_close_parens {
+        if let Some(open) = unclosed_parenthesis(&text, span.start) {
+            diagnostic = diagnostic.with_secondary(open, "this parenthesis is never closed");
+        }
+    }
+
+    if let Some(label) = error.label() {
+        diagnostic = diagnostic.with_note(format!("This happened while parsing the {label}."));
     }
 
-    processed_error
+    diagnostic
 }
@@ -0,0 +1,382 @@
+//! Runs what can be run of an expression that has parser errors, so that a typo in one
+//! assignment doesn't stop the rest of the expression from being previewed.
+
+use super::compat;
+use super::cst::{self, CstItem};
+use super::errors::{Diagnostic, ErrorCode};
+use super::lexer::{lex, Token, TokenKind};
+use super::parser::{self, Assignment, Expr, Span, Spanned};
+use std::sync::Arc;
+
+/// The parts of an expression that could be parsed.
+#[derive(Debug, Clone)]
+pub struct PartialParse {
+    /// The assignments that parsed, leaving out any that use a variable assigned to by one that
+    /// didn't.
+    pub assignments: Vec<Assignment>,
+    /// The Tower compatibility warnings for the assignments that parsed.
+    pub warnings: Vec<Diagnostic>,
+    /// An I1 for every assignment that was left out.
+    pub skipped: Vec<Diagnostic>,
+    /// Every parser error in the expression.
+    pub errors: Vec<Diagnostic>,
+}
+
+/// Something that happens to a variable, in the order the expression does it.
+enum Event<'a> {
+    /// Assignments that couldn't be parsed, and the names they assign to.
+    Broken(Span, Vec<String>),
+    Parsed(&'a Assignment),
+}
+
+/// The index of the statement a character offset is in, or comes straight after.
+fn statement_at(statements: &[Span], offset: usize) -> Option<usize> {
+    statements.iter().rposition(|span| span.start <= offset)
+}
+
+/// The names assigned to in a span of text that doesn't parse, found by looking for names
+/// followed by an `=`.
+fn assigned_names(tokens: &[Token], span: &Span) -> Vec<String> {
+    let meaningful: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| span.contains(&token.span.start) && !token.kind.is_trivia())
+        .collect();
+
+    let mut names = vec![];
+
+    for pair in meaningful.windows(2) {
+        if pair[0].kind == TokenKind::Identifier && pair[1].kind == TokenKind::Equals {
+            names.push(pair[0].text.clone());
+        }
+    }
+
+    names
+}
+
+/// The first use of a variable in `broken`, and where it is.
+fn broken_use<'a>(
+    spanned_expr: &'a Spanned<Arc<Expr>>,
+    broken: &[String],
+) -> Option<(&'a str, &'a Span)> {
+    match &*spanned_expr.0 {
+        Expr::Number(_) | Expr::Error => None,
+        Expr::Variable(name) => broken
+            .contains(name)
+            .then_some((name.as_str(), &spanned_expr.1)),
+        Expr::Group(inner) => broken_use(inner, broken),
+        Expr::BinaryExpression(left, _, right) => {
+            broken_use(left, broken).or_else(|| broken_use(right, broken))
+        }
+        Expr::UnaryExpression(_, operand) => broken_use(operand, broken),
+        Expr::Call(_, args) => args.iter().find_map(|arg| broken_use(arg, broken)),
+    }
+}
+
+/// Lists names like `'a', 'b' and 'c'`.
+fn name_list(names: &[String]) -> String {
+    let quoted: Vec<String> = names.iter().map(|name| format!("'{name}'")).collect();
+
+    match quoted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {last}", rest.join(", ")),
+        None => String::new(),
+    }
+}
+
+fn skipped_statement(span: Span, names: &[String]) -> Diagnostic {
+    let message = match names.len() {
+        0 => "This text was skipped, because it couldn't be parsed.".to_string(),
+        1 => format!(
+            "The assignment to {} was skipped, because it couldn't be parsed.",
+            name_list(names)
+        ),
+        _ => format!(
+            "The assignments to {} were skipped, because they couldn't be parsed.",
+            name_list(names)
+        ),
+    };
+
+    Diagnostic::new(ErrorCode::I1, message, span)
+        .with_label("skipped")
+        .with_help("Fix the parser errors to run it again.")
+}
+
+/// Parses as much of an expression as possible, in Tower compatibility mode if `tower` is set.
+///
+/// The syntax tree splits the expression into statements, skipping to the next `;` after one
+/// that doesn't parse. When the whole text doesn't parse, those statements (and any others the
+/// parser rejects) are blanked out and the rest is parsed again, which only needs repeating in
+/// the rare case the parser rejects something the tree accepted. Blanking keeps every other
+/// character where it was, so spans still point into the original text. Assignments that use a
+/// variable assigned to by a skipped statement, and that haven't been assigned to again since,
+/// are left out as well.
+pub fn parse(text: &str, tower: bool) -> PartialParse {
+    let tree = if tower {
+        cst::parse_lenient(text)
+    } else {
+        cst::parse(text)
+    };
+    let statements: Vec<Span> = tree.items.iter().map(CstItem::span).collect();
+
+    let original: Vec<char> = text.chars().collect();
+    let mut chars = original.clone();
+    let mut errors: Vec<Diagnostic> = vec![];
+    let mut blanked: Vec<usize> = vec![];
+
+    let (assignments, warnings) = loop {
+        let current: String = chars.iter().collect();
+
+        let parsed = if tower {
+            compat::parse(&current).map(|parsed| (parsed.assignments, parsed.warnings))
+        } else {
+            parser::parse(&current).map(|assignments| (assignments, vec![]))
+        };
+
+        let parse_errors = match parsed {
+            Ok(parsed) => break parsed,
+            Err(parse_errors) => parse_errors,
+        };
+
+        // every statement the tree couldn't make sense of is skipped at once, rather than
+        // parsing the text again for each of them. The parser's errors are only used to find
+        // the statements it rejects that the tree accepted, since they can point past the end
+        // of the statement that's actually broken.
+        let mut broken: Vec<usize> = if blanked.is_empty() {
+            tree.items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| matches!(item, CstItem::Error { .. }))
+                .map(|(index, _)| index)
+                .collect()
+        } else {
+            vec![]
+        };
+
+        if broken.is_empty() {
+            broken = parse_errors
+                .iter()
+                .filter_map(|error| statement_at(&statements, error.span.start))
+                .filter(|index| !blanked.contains(index))
+                .collect();
+            broken.sort_unstable();
+            broken.dedup();
+        }
+
+        for error in parse_errors {
+            if !errors.iter().any(|seen| seen.is_same_as(&error)) {
+                errors.push(error);
+            }
+        }
+
+        // nothing is left to skip, so the rest of the text can't be parsed either
+        if broken.is_empty() {
+            break (vec![], vec![]);
+        }
+
+        for index in broken {
+            // line breaks are kept, so that the lines of the text stay where they were
+            for c in chars[statements[index].clone()].iter_mut() {
+                if *c != '\n' && *c != '\r' {
+                    *c = ' ';
+                }
+            }
+
+            blanked.push(index);
+        }
+    };
+
+    // the parser stops at the first statement it can't recover from, so the ones after it are
+    // parsed by themselves to find out what's wrong with them
+    for index in blanked.iter() {
+        let reported = errors
+            .iter()
+            .any(|error| statement_at(&statements, error.span.start) == Some(*index));
+
+        if reported {
+            continue;
+        }
+
+        let span = statements[*index].clone();
+        let statement: String = original[span.clone()].iter().collect();
+
+        let parse_errors = if tower {
+            compat::parse(&statement).err()
+        } else {
+            parser::parse(&statement).err()
+        };
+
+        match parse_errors {
+            Some(parse_errors) => errors.extend(
+                parse_errors
+                    .into_iter()
+                    .map(|error| error.offset_by(span.start)),
+            ),
+            None => errors.extend(tree.items[*index].error(text)),
+        }
+    }
+
+    errors.sort_by_key(|error| error.span.start);
+
+    let tokens = lex(text);
+    let mut events: Vec<(usize, Event)> = blanked
+        .into_iter()
+        .map(|index| {
+            let span = statements[index].clone();
+            let names = assigned_names(&tokens, &span);
+
+            (span.start, Event::Broken(span, names))
+        })
+        .collect();
+
+    events.extend(
+        assignments
+            .iter()
+            .map(|assignment| (assignment.span.start, Event::Parsed(assignment))),
+    );
+    events.sort_by_key(|(start, _)| *start);
+
+    // the variables whose latest assignment was skipped
+    let mut broken: Vec<String> = vec![];
+    let mut kept = vec![];
+    let mut skipped = vec![];
+
+    for (_, event) in events {
+        match event {
+            Event::Broken(span, names) => {
+                skipped.push(skipped_statement(span, &names));
+
+                for name in names {
+                    if !broken.contains(&name) {
+                        broken.push(name);
+                    }
+                }
+            }
+            Event::Parsed(assignment) => match broken_use(&assignment.expression, &broken) {
+                Some((name, used)) => {
+                    skipped.push(
+                        Diagnostic::new(
+                            ErrorCode::I1,
+                            format!(
+                                "The assignment to '{}' was skipped, because it uses '{name}', which was skipped.",
+                                assignment.name
+                            ),
+                            assignment.span.clone(),
+                        )
+                        .with_label("skipped")
+                        .with_secondary(used.clone(), format!("'{name}' is used here")),
+                    );
+
+                    if !broken.contains(&assignment.name) {
+                        broken.push(assignment.name.clone());
+                    }
+                }
+                None => {
+                    broken.retain(|name| *name != assignment.name);
+                    kept.push(assignment.clone());
+                }
+            },
+        }
+    }
+
+    PartialParse {
+        assignments: kept,
+        warnings,
+        skipped,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(partial: &PartialParse) -> Vec<&str> {
+        partial
+            .assignments
+            .iter()
+            .map(|assignment| assignment.name.as_str())
+            .collect()
+    }
+
+    fn spans(diagnostics: &[Diagnostic]) -> Vec<(usize, usize)> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.span.start, diagnostic.span.end))
+            .collect()
+    }
+
+    #[test]
+    fn skips_broken_statements_and_what_uses_them() {
+        let partial = parse(
+            "a = 1 +;
+b = 2;
+c = a;
+a = 3;
+d = a;",
+            false,
+        );
+
+        assert_eq!(names(&partial), ["b", "a", "d"]);
+        assert_eq!(spans(&partial.skipped), [(0, 8), (16, 22)]);
+        assert_eq!(spans(&partial.errors), [(7, 8)]);
+    }
+
+    #[test]
+    fn reports_every_broken_statement() {
+        let partial = parse(
+            "a = sin(x, 2;
+b = min(1);
+x' = a + b;
+y' = (1 + 2;",
+            false,
+        );
+
+        assert_eq!(names(&partial), ["b"]);
+        assert_eq!(spans(&partial.skipped), [(0, 13), (26, 37), (38, 50)]);
+        // the second error is found by parsing its statement by itself, but still points into
+        // the whole text
+        assert_eq!(spans(&partial.errors), [(7, 8), (49, 50)]);
+        assert!(partial.errors[1].message.contains("close your parenthesis"));
+    }
+
+    #[test]
+    fn recovers_after_an_unclosed_parenthesis() {
+        let partial = parse(
+            "a = 1;
+b = (2;
+c = 3;",
+            false,
+        );
+
+        assert_eq!(names(&partial), ["a", "c"]);
+        assert_eq!(spans(&partial.skipped), [(7, 14)]);
+    }
+
+    #[test]
+    fn skips_assignments_the_tree_accepts_but_the_parser_does_not() {
+        let partial = parse(
+            "a = 1 # comment
+ + 2;
+b = 3;",
+            false,
+        );
+
+        assert_eq!(names(&partial), ["b"]);
+        assert_eq!(spans(&partial.skipped), [(0, 21)]);
+    }
+
+    #[test]
+    fn tower_quirks_are_not_skipped() {
+        let partial = parse(
+            "a = 1
+b = 2 +;
+c = sin(a",
+            true,
+        );
+
+        assert_eq!(names(&partial), ["a", "c"]);
+        assert_eq!(spans(&partial.skipped), [(6, 14)]);
+        // two missing semi-colons, and the unclosed parenthesis
+        assert_eq!(partial.warnings.len(), 3);
+    }
+}
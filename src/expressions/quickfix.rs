@@ -0,0 +1,151 @@
+//! Applies the fixes attached to diagnostics to the source text.
+
+use super::compat;
+use super::edit::{apply_edits, TextEdit};
+use super::errors::{Diagnostic, Fix};
+use super::eval::check;
+use super::parser;
+
+// every round applies at least one fix, so this is only reached by fixes that keep causing problems of
+// their own
+const MAX_ROUNDS: usize = 1000;
+
+/// Every problem with an expression that can be found without running it: the parser errors if
+/// it doesn't parse, or else the Tower compatibility warnings (when `tower` is set) and the
+/// problems [`check::check`] finds.
+pub fn diagnose(text: &str, tower: bool) -> Vec<Diagnostic> {
+    match parse_and_check(text, tower) {
+        Ok(diagnostics) | Err(diagnostics) => diagnostics,
+    }
+}
+
+/// Like [`diagnose`], but with parser errors returned as an error.
+fn parse_and_check(text: &str, tower: bool) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let parsed = if tower {
+        compat::parse(text).map(|parsed| (parsed.assignments, parsed.warnings))
+    } else {
+        parser::parse(text).map(|assignments| (assignments, vec![]))
+    };
+
+    parsed.map(|(assignments, mut diagnostics)| {
+        diagnostics.extend(check::check(&assignments));
+        diagnostics
+    })
+}
+
+fn safe_fixes(diagnostics: &[Diagnostic]) -> impl Iterator<Item = &Fix> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| diagnostic.fix.as_ref())
+        .filter(|fix| fix.safe)
+}
+
+/// Applies a single fix to the text its diagnostic was found in.
+pub fn apply(text: &str, fix: &Fix) -> String {
+    apply_edits(text, &fix.edits)
+}
+
+/// Applies every safe fix, returning the fixed text and the number of fixes applied.
+///
+/// The text is diagnosed again after each round of fixes. Parser errors are fixed one at a time,
+/// since fixing one often uncovers the next, and the parser's guesses about the rest of the text
+/// were made before it was fixed. The fixes for an expression that parses are worked out
+/// together (a `;` that goes before a stray `)` that's removed, for example), so they're all
+/// applied at once.
+pub fn fix_all(text: &str, tower: bool) -> (String, usize) {
+    let mut text = text.to_string();
+    let mut applied = 0;
+
+    for _ in 0..MAX_ROUNDS {
+        let fixes: Vec<Fix> = match parse_and_check(&text, tower) {
+            Ok(diagnostics) => safe_fixes(&diagnostics).cloned().collect(),
+            Err(parse_errors) => safe_fixes(&parse_errors).take(1).cloned().collect(),
+        };
+
+        let edits: Vec<TextEdit> = fixes.iter().flat_map(|fix| fix.edits.clone()).collect();
+        let fixed = apply_edits(&text, &edits);
+
+        if fixed == text {
+            break;
+        }
+
+        text = fixed;
+        applied += fixes.len();
+    }
+
+    (text, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixes everything it safely can, checking that doing it again changes nothing.
+    fn assert_fixed_point(text: &str, tower: bool) -> (String, usize) {
+        let (fixed, applied) = fix_all(text, tower);
+
+        assert_eq!(
+            fix_all(&fixed, tower),
+            (fixed.clone(), 0),
+            "fixing {text:?} again"
+        );
+
+        (fixed, applied)
+    }
+
+    #[test]
+    fn missing_semicolons() {
+        assert_eq!(
+            assert_fixed_point("a = 1\nb = a\nx' = b", false),
+            ("a = 1;\nb = a;\nx' = b;".into(), 3)
+        );
+    }
+
+    #[test]
+    fn missing_parentheses() {
+        assert_eq!(
+            assert_fixed_point("a = (1 + 2;\nx' = (a * 2", false),
+            ("a = (1 + 2);\nx' = (a * 2);".into(), 3)
+        );
+    }
+
+    #[test]
+    fn tower_quirks() {
+        let (fixed, applied) =
+            assert_fixed_point("a = min(x\nb = sin(time, 1))\nx' = a + atan2(b)", true);
+
+        assert_eq!(
+            fixed,
+            "a = min(x, 0);\nb = sin(time);\nx' = a + atan2(b, 0);"
+        );
+        assert_eq!(applied, 8);
+        assert!(diagnose(&fixed, false).is_empty());
+    }
+
+    #[test]
+    fn unsafe_fixes_are_never_applied() {
+        // each of these could as well be missing an operator or a `,`
+        for text in ["a = 2 3;\nx' = a;", "x' = min(1 2);", "x' = (1 + 2 3);"] {
+            let diagnostics = diagnose(text, false);
+
+            assert!(diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.fix.as_ref().is_some_and(|fix| !fix.safe)));
+            assert_eq!(fix_all(text, false), (text.to_string(), 0));
+        }
+
+        // the safe fix is still applied around an unsafe one
+        assert_eq!(
+            fix_all("a = 1\nx' = a 2;", false),
+            ("a = 1;\nx' = a 2;".into(), 1)
+        );
+    }
+
+    #[test]
+    fn problems_without_fixes_are_left_alone() {
+        let text = "x = 1;\nx' = sinn(b);";
+
+        assert!(!diagnose(text, false).is_empty());
+        assert_eq!(assert_fixed_point(text, false), (text.to_string(), 0));
+    }
+}
@@ -0,0 +1,68 @@
+//! The reference pages for the expression language in `docs/expressions`, split into a section
+//! for each input, output and function.
+
+pub const INPUTS_PAGE: &str = include_str!("../../docs/expressions/inputs.md");
+pub const OUTPUTS_PAGE: &str = include_str!("../../docs/expressions/outputs.md");
+pub const FUNCTIONS_PAGE: &str = include_str!("../../docs/expressions/functions.md");
+
+/// The part of a reference page about a single name.
+#[derive(Debug, Clone, Copy)]
+pub struct Section {
+    pub name: &'static str,
+    /// How the function is called, such as `lerp(fraction, a, b)`. Only functions have one.
+    pub signature: Option<&'static str>,
+    /// The rest of the section, in Markdown.
+    pub description: &'static str,
+}
+
+impl Section {
+    /// The names of the function's parameters, from its signature.
+    pub fn parameters(&self) -> Vec<&'static str> {
+        let Some(signature) = self.signature else {
+            return vec![];
+        };
+
+        let inside = signature
+            .split_once('(')
+            .and_then(|(_, rest)| rest.strip_suffix(')'))
+            .unwrap_or("");
+
+        inside
+            .split(',')
+            .map(str::trim)
+            .filter(|parameter| !parameter.is_empty())
+            .collect()
+    }
+}
+
+/// Every `##` section of a page. A section of the functions page starts with the signature, on a
+/// line of its own in backticks.
+fn sections(page: &'static str) -> impl Iterator<Item = Section> {
+    page.split("\n## ").skip(1).map(|section| {
+        let (name, body) = section.split_once('\n').unwrap_or((section, ""));
+        let body = body.trim();
+        let (first, rest) = body.split_once('\n').unwrap_or((body, ""));
+
+        let signature = first
+            .strip_prefix('`')
+            .and_then(|first| first.strip_suffix('`'))
+            .filter(|signature| signature.contains('('));
+
+        Section {
+            name: name.trim(),
+            signature,
+            description: match signature {
+                Some(_) => rest.trim(),
+                None => body,
+            },
+        }
+    })
+}
+
+/// The section about an input, output or function.
+pub fn section(name: &str) -> Option<Section> {
+    [INPUTS_PAGE, OUTPUTS_PAGE, FUNCTIONS_PAGE]
+        .into_iter()
+        .flat_map(sections)
+        .find(|section| section.name == name)
+}
@@ -0,0 +1,394 @@
+//! Prints diagnostics the way rustc does: a header with the code and message, then the source
+//! lines the diagnostic points at, with the spans underlined and labelled.
+
+use super::errors::{Diagnostic, Severity};
+use super::parser::Span;
+use super::source_map::SourceMap;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+// tabs are shown as this many spaces, so that the underlines line up with them
+const TAB_WIDTH: usize = 4;
+
+fn severity_style(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "\x1b[1;31m",
+        Severity::Warning => "\x1b[1;33m",
+        Severity::Info => "\x1b[1;36m",
+        Severity::Hint => "\x1b[1;32m",
+    }
+}
+
+/// Wraps text in ANSI escape codes, or leaves it as it is when colours are turned off.
+struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    fn paint(&self, text: &str, style: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// A line of source text, without its line break.
+struct Line {
+    /// The character offset the line starts at.
+    start: usize,
+    chars: Vec<char>,
+}
+
+impl Line {
+    fn new(map: &SourceMap, index: usize) -> Line {
+        Line {
+            start: map.line_span(index).start,
+            chars: map.line_text(index).chars().collect(),
+        }
+    }
+}
+
+/// How far across the line a column is shown, once tabs are expanded. Columns past the end of
+/// the line count as one character each.
+fn display_column(line: &Line, column: usize) -> usize {
+    let width: usize = line
+        .chars
+        .iter()
+        .take(column)
+        .map(|c| if *c == '\t' { TAB_WIDTH } else { 1 })
+        .sum();
+
+    width + column.saturating_sub(line.chars.len())
+}
+
+fn display_line(line: &Line) -> String {
+    line.chars
+        .iter()
+        .map(|c| match c {
+            '\t' => " ".repeat(TAB_WIDTH),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// A span to underline, and the message to show next to it.
+struct Annotation<'a> {
+    span: Span,
+    message: &'a str,
+    primary: bool,
+}
+
+impl<'a> Annotation<'a> {
+    /// The first and last lines the span covers.
+    fn lines(&self, map: &SourceMap) -> (usize, usize) {
+        let first = map.line_of_char(self.span.start);
+        let last = match self.span.end > self.span.start {
+            true => map.line_of_char(self.span.end - 1),
+            false => first,
+        };
+
+        (first, last)
+    }
+}
+
+/// Renders a diagnostic as text, like:
+///
+/// ```text
+/// error[E2]: Cannot find variable 'a'. Are you using it too early?
+///  --> circle.txt:1:6
+///   |
+/// 1 | x' = a * sin(x);
+///   |      ^ not assigned to yet
+/// 2 | a = 50;
+///   | ------- 'a' is assigned here, after it's used
+///   |
+///   = help: Move the assignment to 'a' before this one.
+/// ```
+///
+/// `map` is made from the source the diagnostic's spans point into, and `origin` is what the
+/// source is called in the header, such as its file name. ANSI colours are only used when
+/// `color` is true, which makes the plain output suitable for comparing in tests.
+pub fn render(diagnostic: &Diagnostic, map: &SourceMap, origin: &str, color: bool) -> String {
+    let painter = Painter { color };
+    let style = severity_style(diagnostic.severity);
+
+    let mut annotations = vec![Annotation {
+        span: map.trim(&diagnostic.span),
+        message: diagnostic.label.as_deref().unwrap_or(""),
+        primary: true,
+    }];
+
+    annotations.extend(diagnostic.secondary.iter().map(|label| Annotation {
+        span: map.trim(&label.span),
+        message: &label.message,
+        primary: false,
+    }));
+
+    let mut shown: Vec<usize> = annotations
+        .iter()
+        .flat_map(|annotation| {
+            let (first, last) = annotation.lines(map);
+            first..=last
+        })
+        .collect();
+
+    shown.sort_unstable();
+    shown.dedup();
+
+    let gutter_width = shown.last().map_or(1, |last| (last + 1).to_string().len());
+    let pad = " ".repeat(gutter_width);
+    let bar = painter.paint("|", BLUE);
+
+    let position = map.position(annotations[0].span.start);
+    let mut output = format!(
+        "{}{}\n{pad}{} {origin}:{position}\n{pad} {bar}\n",
+        painter.paint(
+            &format!("{}[{}]", diagnostic.severity.name(), diagnostic.code),
+            style
+        ),
+        painter.paint(&format!(": {}", diagnostic.message), BOLD),
+        painter.paint("-->", BLUE),
+    );
+
+    let mut previous: Option<usize> = None;
+
+    for index in shown.iter().copied() {
+        // a single line between two shown ones is shown too, rather than being replaced
+        match previous {
+            Some(previous) if index == previous + 2 => {
+                output += &render_source_line(map, previous + 1, gutter_width, &painter);
+            }
+            Some(previous) if index > previous + 2 => {
+                output += &format!("{}\n", painter.paint("...", BLUE));
+            }
+            _ => {}
+        }
+
+        output += &render_source_line(map, index, gutter_width, &painter);
+
+        let line = Line::new(map, index);
+        let mut rows: Vec<(usize, String)> = vec![];
+
+        for annotation in annotations.iter() {
+            let (first, last) = annotation.lines(map);
+
+            if index < first || index > last {
+                continue;
+            }
+
+            let start = match index == first {
+                true => annotation.span.start - line.start,
+                false => 0,
+            };
+            let end = match index == last {
+                true => annotation.span.end.saturating_sub(line.start),
+                false => line.chars.len(),
+            };
+
+            let from = display_column(&line, start);
+            let width = display_column(&line, end.max(start))
+                .saturating_sub(from)
+                .max(1);
+
+            let (marker, marker_style) = match annotation.primary {
+                true => ("^", style),
+                false => ("-", BLUE),
+            };
+
+            let mut row = format!(
+                "{}{}",
+                " ".repeat(from),
+                painter.paint(&marker.repeat(width), marker_style)
+            );
+
+            // the message goes after the last line of the span
+            if index == last && !annotation.message.is_empty() {
+                row += &format!(" {}", painter.paint(annotation.message, marker_style));
+            }
+
+            rows.push((from, row));
+        }
+
+        rows.sort_by_key(|(from, _)| *from);
+
+        for (_, row) in rows {
+            output += &format!("{pad} {bar} {row}\n");
+        }
+
+        previous = Some(index);
+    }
+
+    if !diagnostic.notes.is_empty() || diagnostic.help.is_some() {
+        output += &format!("{pad} {bar}\n");
+    }
+
+    for note in diagnostic.notes.iter() {
+        output += &format!(
+            "{pad} {} {}: {note}\n",
+            painter.paint("=", BLUE),
+            painter.paint("note", BOLD)
+        );
+    }
+
+    if let Some(help) = &diagnostic.help {
+        output += &format!(
+            "{pad} {} {}: {help}\n",
+            painter.paint("=", BLUE),
+            painter.paint("help", BOLD)
+        );
+    }
+
+    output
+}
+
+fn render_source_line(
+    map: &SourceMap,
+    index: usize,
+    gutter_width: usize,
+    painter: &Painter,
+) -> String {
+    let number = painter.paint(&format!("{:>gutter_width$}", index + 1), BLUE);
+    let bar = painter.paint("|", BLUE);
+
+    match display_line(&Line::new(map, index)) {
+        source if source.is_empty() => format!("{number} {bar}\n"),
+        source => format!("{number} {bar} {source}\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expressions::errors::ErrorCode;
+
+    fn render_plain(diagnostic: &Diagnostic, text: &str) -> String {
+        render(diagnostic, &SourceMap::new(text), "test.txt", false)
+    }
+
+    fn lines(lines: &[&str]) -> String {
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    #[test]
+    fn single_line() {
+        let diagnostic = Diagnostic::unknown_function("foo", 5..11);
+
+        assert_eq!(
+            render_plain(&diagnostic, "x' = foo(1);\n"),
+            lines(&[
+                "error[E3]: No such function 'foo'.",
+                " --> test.txt:1:6",
+                "  |",
+                "1 | x' = foo(1);",
+                "  |      ^^^^^^ unknown function",
+            ])
+        );
+    }
+
+    #[test]
+    fn multi_line_span() {
+        let diagnostic = Diagnostic::new(ErrorCode::P1, "Something is wrong.", 4..16)
+            .with_label("this whole group");
+
+        assert_eq!(
+            render_plain(&diagnostic, "a = (1 +\n  2 * 3);\nx' = a;"),
+            lines(&[
+                "error[P1]: Something is wrong.",
+                " --> test.txt:1:5",
+                "  |",
+                "1 | a = (1 +",
+                "  |     ^^^^",
+                "2 |   2 * 3);",
+                "  | ^^^^^^^ this whole group",
+            ])
+        );
+    }
+
+    #[test]
+    fn secondary_label_on_another_line() {
+        let diagnostic = Diagnostic::unknown_variable("a", 5..6)
+            .with_secondary(17..24, "'a' is assigned here, after it's used")
+            .with_help("Move the assignment to 'a' before this one.");
+
+        assert_eq!(
+            render_plain(&diagnostic, "x' = a * sin(x);\na = 50;\n"),
+            lines(&[
+                "error[E2]: Cannot find variable 'a'. Are you using it too early?",
+                " --> test.txt:1:6",
+                "  |",
+                "1 | x' = a * sin(x);",
+                "  |      ^ not assigned to yet",
+                "2 | a = 50;",
+                "  | ------- 'a' is assigned here, after it's used",
+                "  |",
+                "  = help: Move the assignment to 'a' before this one.",
+            ])
+        );
+    }
+
+    #[test]
+    fn tabs() {
+        let diagnostic = Diagnostic::unknown_function("foo", 7..13);
+
+        assert_eq!(
+            render_plain(&diagnostic, "\tx' =\t\tfoo(1);"),
+            lines(&[
+                "error[E3]: No such function 'foo'.",
+                " --> test.txt:1:8",
+                "  |",
+                "1 |     x' =        foo(1);",
+                "  |                 ^^^^^^ unknown function",
+            ])
+        );
+    }
+
+    #[test]
+    fn zero_width_span_at_the_end() {
+        let diagnostic = Diagnostic::new(ErrorCode::P2, "Unexpected end of file.", 11..11)
+            .with_label("the expression ends here")
+            .with_secondary(5..6, "this parenthesis is never closed");
+
+        assert_eq!(
+            render_plain(&diagnostic, "x' = (1 + 2"),
+            lines(&[
+                "error[P2]: Unexpected end of file.",
+                " --> test.txt:1:12",
+                "  |",
+                "1 | x' = (1 + 2",
+                "  |      - this parenthesis is never closed",
+                "  |            ^ the expression ends here",
+            ])
+        );
+    }
+
+    #[test]
+    fn lines_in_between_are_left_out() {
+        let text = "a = 1;\nb = 2;\nc = 3;\nd = 4;\ne = 5;\nf = 6;\n";
+        let diagnostic = Diagnostic::new(ErrorCode::E4, "Something is wrong.", 0..1)
+            .with_label("here")
+            .with_secondary(14..15, "on line 3")
+            .with_secondary(35..36, "on line 6");
+
+        // a single line between two shown ones is shown rather than being replaced with `...`
+        assert_eq!(
+            render_plain(&diagnostic, text),
+            lines(&[
+                "error[E4]: Something is wrong.",
+                " --> test.txt:1:1",
+                "  |",
+                "1 | a = 1;",
+                "  | ^ here",
+                "2 | b = 2;",
+                "3 | c = 3;",
+                "  | - on line 3",
+                "...",
+                "6 | f = 6;",
+                "  | - on line 6",
+            ])
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Converts between the different ways of pointing at a place in source text.
+//!
+//! The parser, the lexer and egui's cursors count characters, Rust strings are indexed by bytes,
+//! and editors that speak the language server protocol count UTF-16 code units. [`SourceMap`]
+//! works out where every line starts once, so that converting between any of them only has to
+//! look through a single line.
+
+use super::parser::Span;
+use std::fmt;
+
+/// A line and column, both starting from 0. What the column counts depends on where it came
+/// from: characters, UTF-8 bytes or UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl LineColumn {
+    fn clamped(self, length: usize) -> LineColumn {
+        LineColumn {
+            line: self.line,
+            column: self.column.min(length),
+        }
+    }
+}
+
+/// Shown the way editors and compilers do, with both numbers starting from 1, like `3:14`.
+impl fmt::Display for LineColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+/// Where a line starts, in bytes and in characters.
+#[derive(Debug, Clone, Copy)]
+struct LineStart {
+    byte: usize,
+    char: usize,
+}
+
+/// The line starts of a piece of source text.
+///
+/// Lines are separated by `\n`, and a `\r` right before it (a Windows line ending) belongs to
+/// the line break rather than the line, so it doesn't count towards any column. Text that ends
+/// with a line break has an empty last line after it.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    text: String,
+    lines: Vec<LineStart>,
+    chars: usize,
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        SourceMap::new("")
+    }
+}
+
+impl SourceMap {
+    pub fn new(text: &str) -> SourceMap {
+        let mut lines = vec![LineStart { byte: 0, char: 0 }];
+        let mut chars = 0;
+
+        for (byte, c) in text.char_indices() {
+            chars += 1;
+
+            if c == '\n' {
+                lines.push(LineStart {
+                    byte: byte + 1,
+                    char: chars,
+                });
+            }
+        }
+
+        SourceMap {
+            text: text.to_string(),
+            lines,
+            chars,
+        }
+    }
+
+    /// The text the map was made from.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The number of characters in the text.
+    pub fn char_count(&self) -> usize {
+        self.chars
+    }
+
+    /// The line a character offset is on. Offsets past the end of the text are on the last line.
+    pub fn line_of_char(&self, offset: usize) -> usize {
+        self.lines.partition_point(|line| line.char <= offset) - 1
+    }
+
+    /// The line a byte offset is on. Offsets past the end of the text are on the last line.
+    pub fn line_of_byte(&self, offset: usize) -> usize {
+        self.lines.partition_point(|line| line.byte <= offset) - 1
+    }
+
+    /// The text of a line, without its line break.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.lines[line].byte;
+
+        // only a `\r` that's followed by a `\n` is part of a line break
+        match self.lines.get(line + 1) {
+            Some(next) => {
+                let text = &self.text[start..next.byte - 1];
+                text.strip_suffix('\r').unwrap_or(text)
+            }
+            None => &self.text[start..],
+        }
+    }
+
+    /// The characters of a line, without its line break.
+    pub fn line_span(&self, line: usize) -> Span {
+        let start = self.lines[line].char;
+        start..start + self.line_text(line).chars().count()
+    }
+
+    /// Converts a character offset into a byte offset. Offsets past the end of the text are
+    /// clamped to it.
+    pub fn char_to_byte(&self, offset: usize) -> usize {
+        let line = self.line_of_char(offset);
+        let start = self.lines[line];
+
+        self.text[start.byte..]
+            .char_indices()
+            .nth(offset - start.char)
+            .map_or(self.text.len(), |(byte, _)| start.byte + byte)
+    }
+
+    /// Converts a byte offset into a character offset. Offsets inside a character count as that
+    /// character, and offsets past the end of the text are clamped to it.
+    pub fn byte_to_char(&self, offset: usize) -> usize {
+        let line = self.line_of_byte(offset);
+        let start = self.lines[line];
+        let within = self.text[start.byte..]
+            .char_indices()
+            .take_while(|(byte, c)| start.byte + byte + c.len_utf8() <= offset)
+            .count();
+
+        (start.char + within).min(self.chars)
+    }
+
+    /// Converts a character span into a byte range, for slicing the text.
+    pub fn byte_range(&self, span: &Span) -> std::ops::Range<usize> {
+        self.char_to_byte(span.start)..self.char_to_byte(span.end)
+    }
+
+    /// Leaves out the whitespace at either end of a span, which the parser includes in the spans
+    /// of expressions. Spans with nothing but whitespace are left as they are.
+    pub fn trim(&self, span: &Span) -> Span {
+        let text = &self.text[self.byte_range(span)];
+        let leading = text.chars().take_while(|c| c.is_whitespace()).count();
+        let trailing = text.chars().rev().take_while(|c| c.is_whitespace()).count();
+
+        match leading == text.chars().count() {
+            true => span.clone(),
+            false => span.start + leading..span.end - trailing,
+        }
+    }
+
+    /// The line of a character offset, and how many characters into the line it is.
+    pub fn position(&self, offset: usize) -> LineColumn {
+        let line = self.line_of_char(offset);
+        let column = offset.min(self.chars) - self.lines[line].char;
+
+        LineColumn {
+            line,
+            column: column.min(self.line_span(line).len()),
+        }
+    }
+
+    /// The line of a character offset, and how many UTF-8 bytes into the line it is.
+    pub fn position_utf8(&self, offset: usize) -> LineColumn {
+        let line = self.line_of_char(offset);
+
+        LineColumn {
+            line,
+            column: self.char_to_byte(offset) - self.lines[line].byte,
+        }
+        .clamped(self.line_text(line).len())
+    }
+
+    /// The line of a character offset, and how many UTF-16 code units into the line it is, as
+    /// the language server protocol counts them.
+    pub fn position_utf16(&self, offset: usize) -> LineColumn {
+        let line = self.line_of_char(offset);
+        let start = self.lines[line];
+        let column = self.text[start.byte..]
+            .chars()
+            .take(offset.saturating_sub(start.char))
+            .map(char::len_utf16)
+            .sum();
+
+        LineColumn { line, column }.clamped(self.line_text(line).encode_utf16().count())
+    }
+
+    /// Converts a line and a column counted in characters into a character offset. Columns
+    /// past the end of the line are clamped to it.
+    pub fn offset(&self, position: LineColumn) -> usize {
+        match self.line_bounds(position.line) {
+            Some(span) => (span.start + position.column).min(span.end),
+            None => self.chars,
+        }
+    }
+
+    /// Converts a line and a column counted in UTF-8 bytes into a character offset.
+    pub fn offset_utf8(&self, position: LineColumn) -> usize {
+        match self.lines.get(position.line) {
+            Some(start) => {
+                let column = position.column.min(self.line_text(position.line).len());
+                self.byte_to_char(start.byte + column)
+            }
+            None => self.chars,
+        }
+    }
+
+    /// Converts a line and a column counted in UTF-16 code units into a character offset.
+    pub fn offset_utf16(&self, position: LineColumn) -> usize {
+        let Some(span) = self.line_bounds(position.line) else {
+            return self.chars;
+        };
+
+        let mut units = 0;
+        let within = self
+            .line_text(position.line)
+            .chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= position.column
+            })
+            .count();
+
+        span.start + within
+    }
+
+    fn line_bounds(&self, line: usize) -> Option<Span> {
+        (line < self.lines.len()).then(|| self.line_span(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 lines, the middle ones with Windows line endings, a 4-byte (2 UTF-16 unit) emoji and a
+    // 2-byte `é`, and an empty last line
+    const TEXT: &str = "a = 1;\r\n# 😀 é\r\nb = a;\n";
+
+    fn at(line: usize, column: usize) -> LineColumn {
+        LineColumn { line, column }
+    }
+
+    #[test]
+    fn lines() {
+        let map = SourceMap::new(TEXT);
+
+        assert_eq!(map.line_count(), 4);
+        assert_eq!(map.char_count(), 22);
+        assert_eq!(map.line_text(0), "a = 1;");
+        assert_eq!(map.line_text(1), "# 😀 é");
+        assert_eq!(map.line_text(3), "");
+        assert_eq!(map.line_span(1), 8..13);
+        assert_eq!(map.line_span(3), 22..22);
+
+        assert_eq!(map.line_of_char(7), 0);
+        assert_eq!(map.line_of_char(8), 1);
+        assert_eq!(map.line_of_char(100), 3);
+        assert_eq!(map.line_of_byte(18), 1);
+        assert_eq!(map.line_of_byte(19), 2);
+
+        assert_eq!(SourceMap::new("").line_count(), 1);
+        assert_eq!(SourceMap::new("\r").line_text(0), "\r");
+    }
+
+    #[test]
+    fn bytes_and_chars() {
+        let map = SourceMap::new(TEXT);
+
+        assert_eq!(map.char_to_byte(10), 10);
+        assert_eq!(map.char_to_byte(11), 14);
+        assert_eq!(map.char_to_byte(15), 19);
+        assert_eq!(map.char_to_byte(22), TEXT.len());
+        assert_eq!(map.char_to_byte(100), TEXT.len());
+
+        assert_eq!(map.byte_to_char(10), 10);
+        assert_eq!(map.byte_to_char(12), 10);
+        assert_eq!(map.byte_to_char(14), 11);
+        assert_eq!(map.byte_to_char(100), 22);
+
+        assert_eq!(&TEXT[map.byte_range(&(10..13))], "😀 é");
+        assert_eq!(map.trim(&(14..22)), 15..21);
+        assert_eq!(map.trim(&(6..8)), 6..8);
+    }
+
+    #[test]
+    fn positions() {
+        let map = SourceMap::new(TEXT);
+
+        // the space after the emoji
+        assert_eq!(map.position(11), at(1, 3));
+        assert_eq!(map.position_utf8(11), at(1, 6));
+        assert_eq!(map.position_utf16(11), at(1, 4));
+
+        // the end of the line with `é` on it
+        assert_eq!(map.position(13), at(1, 5));
+        assert_eq!(map.position_utf8(13), at(1, 9));
+        assert_eq!(map.position_utf16(13), at(1, 6));
+
+        // the `\r` and `\n` of a line break are at the end of the line before it
+        for offset in [6, 7] {
+            assert_eq!(map.position(offset), at(0, 6));
+            assert_eq!(map.position_utf8(offset), at(0, 6));
+            assert_eq!(map.position_utf16(offset), at(0, 6));
+        }
+
+        assert_eq!(map.position(22), at(3, 0));
+        assert_eq!(map.position_utf16(100), at(3, 0));
+        assert_eq!(map.position(11).to_string(), "2:4");
+    }
+
+    #[test]
+    fn offsets() {
+        let map = SourceMap::new(TEXT);
+
+        assert_eq!(map.offset(at(1, 3)), 11);
+        assert_eq!(map.offset_utf8(at(1, 6)), 11);
+        assert_eq!(map.offset_utf16(at(1, 4)), 11);
+
+        // a column in the middle of the emoji is the emoji
+        assert_eq!(map.offset_utf8(at(1, 4)), 10);
+        assert_eq!(map.offset_utf16(at(1, 3)), 10);
+
+        // columns past the end of a line are clamped to it, and lines past the end of the text
+        // to the end of the text
+        assert_eq!(map.offset(at(0, 50)), 6);
+        assert_eq!(map.offset_utf8(at(1, 50)), 13);
+        assert_eq!(map.offset_utf16(at(1, 50)), 13);
+        assert_eq!(map.offset(at(9, 0)), 22);
+        assert_eq!(map.offset_utf16(at(9, 0)), 22);
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let map = SourceMap::new(TEXT);
+
+        for line in 0..map.line_count() {
+            for offset in map.line_span(line).start..=map.line_span(line).end {
+                assert_eq!(map.offset(map.position(offset)), offset);
+                assert_eq!(map.offset_utf8(map.position_utf8(offset)), offset);
+                assert_eq!(map.offset_utf16(map.position_utf16(offset)), offset);
+                assert_eq!(map.byte_to_char(map.char_to_byte(offset)), offset);
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Finds the name that was probably meant when an unknown one is used.
+
+/// The number of single character insertions, deletions, substitutions and swaps of neighbouring
+/// characters it takes to turn `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between the first i characters of a and the first j of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+/// The candidate closest to `name`, if any is close enough to have probably been meant.
+///
+/// Differences in case don't count towards how far apart two names are, since getting the case
+/// of a camelCase input wrong (`projectiontime`) is the most common typo. Beyond that, a name
+/// may be one edit away from the candidate for every three characters it has, so very short
+/// names only match candidates that differ from them in case.
+pub fn best_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let lowercase = name.to_lowercase();
+    let allowed = name.chars().count() / 3;
+
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| {
+            let distance = edit_distance(&lowercase, &candidate.to_lowercase());
+            // names that differ in the same letters are told apart by their case
+            (distance, edit_distance(name, candidate), candidate)
+        })
+        .filter(|(distance, _, _)| *distance <= allowed)
+        .min_by_key(|(distance, case_distance, _)| (*distance, *case_distance))
+        .map(|(_, _, candidate)| candidate)
+}
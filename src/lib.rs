@@ -0,0 +1,26 @@
+//! The expression language behind Tower Unite's laser projectors, as used by Laser Studio.
+//!
+//! This crate contains everything Laser Studio needs to work with expressions without its
+//! user interface: the parser, the interpreter, the project file format and the code that
+//! generates the points for a rectangular grid of lasers. The egui application is built on top
+//! of it, and it can be used without the `gui` feature to avoid pulling in eframe.
+//!
+//! ```
+//! use laser_studio::expressions::eval::grid::Grid;
+//! use laser_studio::expressions::parser;
+//!
+//! let text = "x' = x; y' = y * sin(time);";
+//! let assignments = parser::parse(text).expect("the expression should parse");
+//!
+//! let grid = Grid { x_size: 20, y_size: 20 };
+//! let result = grid.run(&assignments, 0.0, 0.0);
+//!
+//! assert_eq!(result.points.len(), 400);
+//! assert!(result.errors.iter().all(|errors| errors.is_empty()));
+//! ```
+
+// only the JIT backend needs unsafe code, to call the machine code it generates
+#![deny(unsafe_code)]
+
+pub mod expressions;
+pub mod project;
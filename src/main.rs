@@ -1,16 +1,43 @@
 #![forbid(unsafe_code)]
 
+#[cfg(feature = "gui")]
 mod app;
-pub mod expressions;
-pub mod project;
+mod cli;
 
-use app::LaserStudioApp;
-
-use tracing::info;
-use tracing_subscriber;
+use tracing::{info, Level};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::{fmt, prelude::*};
 
 fn main() {
-    tracing_subscriber::fmt::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if !args.is_empty() {
+        // logs go to stderr so they don't end up mixed in with a command's output
+        tracing_subscriber::registry()
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .with(log_filter())
+            .init();
+        std::process::exit(cli::run(args));
+    }
+
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(log_filter())
+        .init();
+    run_gui();
+}
+
+fn log_filter() -> Targets {
+    // cranelift logs the code for every expression the JIT backend compiles, which is far too
+    // much to show by default
+    Targets::new()
+        .with_default(Level::INFO)
+        .with_target("cranelift_codegen", Level::WARN)
+        .with_target("cranelift_jit", Level::WARN)
+}
+
+#[cfg(feature = "gui")]
+fn run_gui() {
     info!("Starting LS");
 
     // set up EGUI
@@ -21,8 +48,16 @@ fn main() {
     eframe::run_native(
         "Laser Studio",
         options,
-        Box::new(|_cc| Box::new(LaserStudioApp::default())),
+        Box::new(|cc| Box::new(app::LaserStudioApp::new(cc))),
     );
 
     info!("Frame closed, exiting...");
 }
+
+#[cfg(not(feature = "gui"))]
+fn run_gui() {
+    info!("Built without the gui feature, not starting LS");
+
+    eprintln!("Laser Studio was built without the gui feature. Run `laser-studio help` to see the available commands.");
+    std::process::exit(2);
+}
@@ -1,5 +1,12 @@
+use crate::expressions::eval::context::Backend;
+use crate::expressions::format::FormatStyle;
 use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
 
+/// The expression text of a project, and the size of the grid it is rendered on.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct TextData {
     pub content: String,
@@ -20,10 +27,26 @@ impl Default for TextData {
     }
 }
 
+/// A Laser Studio project, stored on disk as JSON in an `.lsp` file.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Project {
     pub version: u16,
     pub text_data: TextData,
+    /// How the formatter lays out this project's expression.
+    #[serde(default)]
+    pub format_style: FormatStyle,
+    /// Whether the expression is formatted whenever the project is saved.
+    #[serde(default)]
+    pub format_on_save: bool,
+    /// Whether the expression is parsed in Tower compatibility mode, accepting the mistakes
+    /// Tower Unite lets through.
+    #[serde(default)]
+    pub tower_compatibility: bool,
+    /// The id of the backend the render workspace evaluates the expression with, as used by
+    /// [`Backend::from_id`]. The interpreter is used when there is none, or when this build
+    /// doesn't include the backend.
+    #[serde(default)]
+    pub backend: Option<String>,
 }
 
 impl Default for Project {
@@ -31,6 +54,53 @@ impl Default for Project {
         Self {
             version: 2,
             text_data: TextData::default(),
+            format_style: FormatStyle::default(),
+            format_on_save: false,
+            tower_compatibility: false,
+            backend: None,
+        }
+    }
+}
+
+impl Project {
+    /// The backend the project is rendered with.
+    pub fn backend(&self) -> Backend {
+        self.backend
+            .as_deref()
+            .and_then(Backend::from_id)
+            .unwrap_or(Backend::Interpreter)
+    }
+
+    /// Reads a project from an `.lsp` file.
+    pub fn open(path: &Path) -> std::result::Result<Project, String> {
+        let file = match File::open(path) {
+            Ok(value) => value,
+            Err(_err) => return Err("An error occured while opening the file for reading.".into()),
+        };
+
+        let reader = BufReader::new(file);
+
+        match serde_json::from_reader(reader) {
+            Ok(value) => Ok(value),
+            Err(_err) => return Err("An deserialization error occured.".into()),
+        }
+    }
+
+    /// Writes the project to an `.lsp` file, replacing it if it already exists.
+    pub fn save(&self, path: &Path) -> std::result::Result<(), String> {
+        let serialized = match serde_json::to_string(self) {
+            Ok(value) => value,
+            Err(_error) => return Err("A serialization error occured.".into()),
+        };
+
+        let mut file = match File::create(path) {
+            Ok(value) => value,
+            Err(_error) => return Err("Failed to create a new file.".into()),
+        };
+
+        match file.write_all(serialized.as_bytes()) {
+            Ok(_value) => Ok(()),
+            Err(_error) => return Err("Failed to write to the file.".into()),
         }
     }
 }